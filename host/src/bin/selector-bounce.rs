@@ -0,0 +1,47 @@
+//! Feed recorded bounce traces through every debouncing algorithm, and
+//! check they make the presses and releases they should out of them. See
+//! `selector_host::bounce` for what a trace looks like.
+//!
+//!   selector-bounce traces/*.trace
+
+use std::fs;
+use std::process::ExitCode;
+
+use selector::Algorithm;
+use selector_host::bounce::Trace;
+
+fn main() -> ExitCode {
+    let paths: Vec<String> = std::env::args().skip(1).collect();
+    if paths.is_empty() {
+	eprintln!("usage: selector-bounce <trace>...");
+	return ExitCode::FAILURE;
+    }
+
+    let mut ok = true;
+    for path in &paths {
+	let trace = match fs::read_to_string(path).map_err(|e| e.to_string()).and_then(|t| Trace::parse(&t)) {
+	    Ok(trace) => trace,
+	    Err(e) => {
+		eprintln!("{}: {}", path, e);
+		ok = false;
+		continue;
+	    }
+	};
+
+	println!("{}", path);
+	for algorithm in Algorithm::ALL {
+	    let outcome = trace.run(algorithm);
+	    let pass = outcome.pass(&trace);
+	    ok &= pass;
+
+	    let glitches = outcome.glitches;
+	    let events: Vec<_> = outcome.events.iter()
+		.map(|(at, pressed)| format!("{} {:.1}ms", if *pressed { "press" } else { "release" }, *at as f64 / 1000.0))
+		.collect();
+	    println!("  {:<12} {}  {}, {} glitch{}", algorithm.name(), if pass { "ok  " } else { "FAIL" },
+		     if events.is_empty() { "-".to_string() } else { events.join(", ") },
+		     glitches, if glitches == 1 { "" } else { "es" });
+	}
+    }
+    if ok { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+}
@@ -0,0 +1,239 @@
+//! Talk to the gear selector over its UART.
+//!
+//! The port needs to be raw, and at the right speed, already:
+//!
+//!   stty -F /dev/ttyUSB0 115200 raw -echo
+//!
+//! To try it out without a board, connect two pseudo terminals and run a
+//! pretend board on one end:
+//!
+//!   socat pty,raw,echo=0,link=/tmp/board pty,raw,echo=0,link=/tmp/host &
+//!   selector-cli /tmp/board board &
+//!   selector-cli /tmp/host state
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::process::ExitCode;
+
+use selector::console::{DisplayDtc, DisplayEntry, DisplayLatency, Event};
+use selector::protocol::{Config, Nak};
+use selector::{
+    Command, DtcStore, EventLog, FaultLog, Gear, GearSelector, Inputs, LatencyStats, LogEntry, LogEvent, Message,
+    Selection, DEFAULT_AGING, DEFAULT_INTERLOCK, DEFAULT_THRESHOLDS,
+};
+use selector_host::Link;
+
+const USAGE: &str = "\
+usage: selector-cli <port> <command>
+
+commands:
+  select <gear>        select a gear (P, N, R, D, ...)
+  state                show the current gear
+  brightness <0-255>   set the LED brightness
+  config               show how the board is set up
+  faults               show the fault log
+  dtcs                 show the trouble codes
+  clear-dtcs           clear the trouble codes
+  events [flash]       show the event log, in RAM or flash
+  latency              show the button to LED times
+  clear-latency        clear the button to LED times
+  monitor              show button presses and gear changes as they happen
+  board                pretend to be a board, for testing";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let command = match args[..] {
+	[_, "select", gear] => match Gear::from_name(gear) {
+	    Some(gear) => Some(Command::SelectGear(gear)),
+	    None => return usage(),
+	},
+	[_, "state"] => Some(Command::QueryState),
+	[_, "brightness", level] => match level.parse() {
+	    Ok(level) => Some(Command::SetBrightness(level)),
+	    Err(_) => return usage(),
+	},
+	[_, "config"] => Some(Command::ReadConfig),
+	[_, "faults"] => Some(Command::ReadFaultLog),
+	[_, "dtcs"] => Some(Command::ReadDtcs),
+	[_, "clear-dtcs"] => Some(Command::ClearDtcs),
+	[_, "events"] => Some(Command::ReadEvents(false)),
+	[_, "events", "flash"] => Some(Command::ReadEvents(true)),
+	[_, "latency"] => Some(Command::ReadLatency),
+	[_, "clear-latency"] => Some(Command::ClearLatency),
+	[_, "monitor"] | [_, "board"] => None,
+	_ => return usage(),
+    };
+
+    let port = match OpenOptions::new().read(true).write(true).open(args[0]) {
+	Ok(port) => port,
+	Err(e) => {
+	    eprintln!("{}: {}", args[0], e);
+	    return ExitCode::FAILURE;
+	}
+    };
+    let mut link = Link::new(port);
+
+    let result = match (command, args[1]) {
+	(Some(command), _) => run(&mut link, command),
+	(None, "monitor") => monitor(&mut link),
+	(None, _) => board(&mut link),
+    };
+    match result {
+	Ok(true) => ExitCode::SUCCESS,
+	Ok(false) => ExitCode::FAILURE,
+	Err(e) => {
+	    eprintln!("{}", e);
+	    ExitCode::FAILURE
+	}
+    }
+}
+
+fn usage() -> ExitCode {
+    eprintln!("{}", USAGE);
+    ExitCode::FAILURE
+}
+
+// Send the command and print the replies, up to the Ack/Nak.
+fn run(link: &mut Link<File>, command: Command) -> io::Result<bool> {
+    link.send(&command)?;
+    loop {
+	match link.receive::<Message>()? {
+	    Ok(Message::Ack) => return Ok(true),
+	    Ok(Message::Nak(nak)) => {
+		match nak {
+		    Nak::BadCommand => println!("Board didn't understand the command"),
+		    Nak::Rejected(rejection) => println!("Rejected: {}", rejection.reason()),
+		}
+		return Ok(false);
+	    }
+	    Ok(message) => print(&message),
+	    Err(e) => eprintln!("Bad frame: {}", e.reason()),
+	}
+    }
+}
+
+fn monitor(link: &mut Link<File>) -> io::Result<bool> {
+    loop {
+	match link.receive::<Message>()? {
+	    Ok(message) => print(&message),
+	    Err(e) => eprintln!("Bad frame: {}", e.reason()),
+	}
+    }
+}
+
+fn print(message: &Message) {
+    match message {
+	Message::Ack | Message::Nak(_) => {}
+	Message::State(selection) => println!("{}", describe(selection)),
+	Message::Config(config) => {
+	    let gears: Vec<_> = Gear::ALL.into_iter().filter(|g| config.has(*g)).map(Gear::name).collect();
+	    println!("Gears:           {}", gears.join(" "));
+	    match config.confirm_ms {
+		0  => println!("Confirm timeout: none"),
+		ms => println!("Confirm timeout: {}ms", ms),
+	    }
+	    println!("Long press:      {}ms", config.long_ms);
+	    println!("Double press:    {}ms", config.double_ms);
+	    println!("Boot count:      {}", config.boot_count);
+	}
+	Message::Fault(index, record) => {
+	    println!("#{:<3} {:>10}ms  {}", index, record.at, Event::Fault(record.fault));
+	}
+	Message::Dtc(index, dtc) => println!("#{:<3} {}", index, DisplayDtc(dtc)),
+	Message::Event(index, entry) => println!("#{:<4} {}", index, DisplayEntry(entry)),
+	Message::Latency(gear, stats) => println!("{}", DisplayLatency(*gear, *stats)),
+	Message::Button(gear, pressed) => {
+	    println!("Button {} {}", gear.name(), if *pressed { "pressed" } else { "released" });
+	}
+	Message::GearChanged(selection) => println!("Gear changed: {}", describe(selection)),
+    }
+}
+
+fn describe(selection: &Selection) -> String {
+    format!("gear {}, requested {} ({}{})", selection.gear.map_or("-", Gear::name),
+	    selection.requested.map_or("-", Gear::name), selection.mode.name(),
+	    if selection.park_lock { ", park lock" } else { "" })
+}
+
+// ================================================================================
+
+// A board with P, N, R and D, that does whatever it's told - brake pressed,
+// standing still and the transmission confirming everything right away.
+// Nothing is kept in flash, and there are no buttons to time.
+fn board(link: &mut Link<File>) -> io::Result<bool> {
+    let inputs = Inputs { brake: true, standstill: true };
+    let mut selector = GearSelector::new(DEFAULT_INTERLOCK);
+    selector.restore(Gear::P);
+    let faults = FaultLog::<16>::new();
+    let mut dtcs = DtcStore::new(DEFAULT_AGING);
+    let mut events = EventLog::<64>::new();
+    let start = std::time::Instant::now();
+    let entry = |event| LogEntry { at: start.elapsed().as_millis() as u64, boot: 1, event };
+
+    loop {
+	let command = match link.receive::<Command>()? {
+	    Ok(command) => command,
+	    Err(e) => {
+		eprintln!("Bad frame: {}", e.reason());
+		link.send(&Message::Nak(Nak::BadCommand))?;
+		continue;
+	    }
+	};
+	eprintln!("{:?}", command);
+
+	// Like the real one, the event comes after the Ack.
+	let mut changed = None;
+	match command {
+	    Command::SelectGear(gear) => match selector.press(gear, &inputs, 0) {
+		Ok(selection) => {
+		    events.push(entry(LogEvent::GearChanged(selection)));
+		    link.send(&Message::State(selection))?;
+		    changed = Some(selection);
+		}
+		Err(rejection) => {
+		    events.push(entry(LogEvent::Rejected(rejection)));
+		    link.send(&Message::Nak(Nak::Rejected(rejection)))?;
+		    continue;
+		}
+	    },
+	    Command::QueryState => link.send(&Message::State(selector.selection()))?,
+	    Command::SetBrightness(_) => {}
+	    Command::ReadConfig => link.send(&Message::Config(Config {
+		positions:  [Gear::P, Gear::N, Gear::R, Gear::D].iter().fold(0, |p, g| p | 1 << *g as u8),
+		confirm_ms: 0,
+		long_ms:    DEFAULT_THRESHOLDS.long_ms as u16,
+		double_ms:  DEFAULT_THRESHOLDS.double_ms as u16,
+		boot_count: 1,
+	    }))?,
+	    Command::ReadFaultLog => {
+		for (index, record) in faults.iter().enumerate() {
+		    link.send(&Message::Fault(index as u8, *record))?;
+		}
+	    }
+	    Command::ReadDtcs => {
+		for (index, dtc) in dtcs.iter().enumerate() {
+		    link.send(&Message::Dtc(index as u8, *dtc))?;
+		}
+	    }
+	    Command::ClearDtcs => dtcs.clear(),
+	    Command::ReadEvents(false) => {
+		for (index, entry) in events.iter().enumerate() {
+		    link.send(&Message::Event(index as u16, *entry))?;
+		}
+	    }
+	    Command::ReadEvents(true) => {}
+	    Command::ReadLatency => {
+		for gear in [Gear::P, Gear::N, Gear::R, Gear::D] {
+		    link.send(&Message::Latency(gear, LatencyStats::default()))?;
+		}
+	    }
+	    Command::ClearLatency => {}
+	}
+	link.send(&Message::Ack)?;
+	if let Some(selection) = changed {
+	    link.send(&Message::GearChanged(selection))?;
+	}
+    }
+}
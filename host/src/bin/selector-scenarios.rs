@@ -0,0 +1,38 @@
+//! Run scenario scripts on the simulator and check what they `expect`,
+//! without printing what the panel does - the test suite for the selector
+//! logic.
+//!
+//!   selector-scenarios scenarios/*.scenario
+//!
+//! Every script starts on a fresh P/R/N/D panel in P, with the brake
+//! pressed, standing still and gear changes needing a confirmation within
+//! 2s - like `selector-simulator` with no options. See `sim::run()`, the
+//! same scenarios are also run by `cargo test`. Time is made up, so a
+//! "hold D 1500" takes no time at all, and comes out the same every run.
+//! Run a failing one through `selector-simulator` to see what happened.
+
+use std::fs;
+use std::process::ExitCode;
+
+use selector_host::sim;
+
+fn main() -> ExitCode {
+    let paths: Vec<String> = std::env::args().skip(1).collect();
+    if paths.is_empty() {
+	eprintln!("usage: selector-scenarios <scenario>...");
+	return ExitCode::FAILURE;
+    }
+
+    let mut failed = 0;
+    for path in &paths {
+	match fs::read_to_string(path).map_err(|e| e.to_string()).and_then(|script| sim::run(&script)) {
+	    Ok(checks) => println!("ok    {} ({} check{})", path, checks, if checks == 1 { "" } else { "s" }),
+	    Err(e) => {
+		println!("FAIL  {}: {}", path, e);
+		failed += 1;
+	    }
+	}
+    }
+    println!("{} of {} passed", paths.len() - failed, paths.len());
+    if failed == 0 { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+}
@@ -0,0 +1,100 @@
+//! Run the selector on a virtual panel, scripted from a file or typed in,
+//! and print what the LEDs and the NeoPixel do.
+//!
+//!   selector-simulator [--gears PRND] [--start P] [--no-confirm] [script]
+//!   selector-simulator scenarios/park-to-drive.scenario
+//!
+//! The panel has a button and LED for each of `--gears`, and starts out in
+//! the `--start` gear with the brake pressed and standing still. Gear
+//! changes need the transmission to `confirm` them within 2s, as on the
+//! board, unless `--no-confirm`. Time only moves on a `wait` (or `tap`,
+//! `hold`), so a script always gives the same output.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::process::ExitCode;
+
+use selector::console::Event;
+use selector::{Gear, Rgb};
+use selector_host::sim::{self, Panel, Simulator, CONFIRM_MS, SCRIPT_HELP};
+
+const USAGE: &str = "usage: selector-simulator [--gears PRND] [--start P] [--no-confirm] [script]";
+
+// Prints everything, with when it happened.
+struct Printer;
+
+impl Panel for Printer {
+    fn led(&mut self, at: u64, gear: Gear, level: u8) {
+	println!("{:>8}ms  LED {} {}", at, gear.name(), level);
+    }
+
+    fn pixel(&mut self, at: u64, (r, g, b): Rgb) {
+	println!("{:>8}ms  pixel #{:02X}{:02X}{:02X}", at, r, g, b);
+    }
+
+    fn event(&mut self, at: u64, event: Event) {
+	println!("{:>8}ms  {}", at, event);
+    }
+}
+
+fn main() -> ExitCode {
+    let mut gears = vec![Gear::P, Gear::R, Gear::N, Gear::D];
+    let mut start = Gear::P;
+    let mut confirm = Some(CONFIRM_MS);
+    let mut script = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+	match arg.as_str() {
+	    "--gears" => {
+		let names = args.next().unwrap_or_default();
+		match names.chars().map(|c| Gear::from_name(&c.to_string())).collect::<Option<Vec<_>>>() {
+		    Some(g) if !g.is_empty() => gears = g,
+		    _ => return usage(),
+		}
+	    }
+	    "--start" => match args.next().as_deref().and_then(Gear::from_name) {
+		Some(gear) => start = gear,
+		None => return usage(),
+	    },
+	    "--no-confirm" => confirm = None,
+	    "-h" | "--help" => return usage(),
+	    _ if script.is_none() && !arg.starts_with('-') => script = Some(arg),
+	    _ => return usage(),
+	}
+    }
+
+    let input: Box<dyn BufRead> = match &script {
+	Some(path) => match File::open(path) {
+	    Ok(file) => Box::new(BufReader::new(file)),
+	    Err(e) => {
+		eprintln!("{}: {}", path, e);
+		return ExitCode::FAILURE;
+	    }
+	},
+	None => Box::new(io::stdin().lock()),
+    };
+
+    let mut sim = Simulator::new(Printer, &gears, start, confirm);
+    for (n, line) in input.lines().enumerate() {
+	let line = match line {
+	    Ok(line) => line,
+	    Err(e) => {
+		eprintln!("{}", e);
+		return ExitCode::FAILURE;
+	    }
+	};
+	if let Err(e) = sim::parse(&line).and_then(|steps| steps.into_iter().try_for_each(|step| sim.step(step))) {
+	    eprintln!("line {}: {}", n + 1, e);
+	    if script.is_some() {
+		return ExitCode::FAILURE;
+	    }
+	}
+    }
+    ExitCode::SUCCESS
+}
+
+fn usage() -> ExitCode {
+    eprintln!("{}\n\nscript:\n{}", USAGE, SCRIPT_HELP);
+    ExitCode::FAILURE
+}
@@ -0,0 +1,105 @@
+//! Recorded bounce traces, fed through a debouncer.
+//!
+//! A trace is the raw button input, one change per line - the time in µs
+//! and the new level (1 = pressed, it starts out released) - and what it
+//! should come out as:
+//!
+//!   # Comment
+//!   100000 1
+//!   100180 0
+//!   100400 1
+//!   expect press
+//!
+//! The debouncer works in whatever unit it's given, so it's fed µs here
+//! with the timings in `DEFAULT_DEBOUNCE` scaled to match.
+
+use selector::{Algorithm, DebounceConfig, Debouncer, DEFAULT_DEBOUNCE};
+
+pub struct Trace {
+    pub edges:  Vec<(u64, bool)>,
+
+    /// The presses (true) and releases it should come out as.
+    pub expect: Vec<bool>,
+}
+
+/// What a trace came out as with one algorithm.
+pub struct Outcome {
+    /// The presses and releases, with when they happened (µs).
+    pub events:   Vec<(u64, bool)>,
+    pub glitches: u32,
+}
+
+impl Trace {
+    pub fn parse(text: &str) -> Result<Trace, String> {
+	let mut trace = Trace { edges: Vec::new(), expect: Vec::new() };
+	for (n, line) in text.lines().enumerate() {
+	    let bad = || format!("line {}: can't make sense of '{}'", n + 1, line);
+	    let words: Vec<&str> = line.split('#').next().unwrap().split_whitespace().collect();
+	    match words[..] {
+		[] => {}
+		["expect", ref events @ ..] => {
+		    for event in events {
+			trace.expect.push(match *event {
+			    "press"   => true,
+			    "release" => false,
+			    _ => return Err(bad()),
+			});
+		    }
+		}
+		[at, level] => {
+		    let at = at.parse().map_err(|_| bad())?;
+		    let level = match level {
+			"0" => false,
+			"1" => true,
+			_ => return Err(bad()),
+		    };
+		    if trace.edges.last().is_some_and(|(last, _)| *last > at) {
+			return Err(format!("line {}: time going backwards", n + 1));
+		    }
+		    trace.edges.push((at, level));
+		}
+		_ => return Err(bad()),
+	    }
+	}
+	Ok(trace)
+    }
+
+    /// Same as `read_button` does - update on every edge, and whenever the
+    /// deadline have passed.
+    pub fn run(&self, algorithm: Algorithm) -> Outcome {
+	let config = DebounceConfig {
+	    algorithm,
+	    press_ms:   DEFAULT_DEBOUNCE.press_ms * 1000,
+	    release_ms: DEFAULT_DEBOUNCE.release_ms * 1000,
+	    sample_ms:  DEFAULT_DEBOUNCE.sample_ms * 1000,
+	};
+	let mut debouncer = Debouncer::new(config, false);
+	let mut edges = self.edges.iter().peekable();
+	let mut level = false;
+	let mut events = Vec::new();
+
+	loop {
+	    let now = match (edges.peek(), debouncer.deadline()) {
+		(Some((at, _)), Some(deadline)) if deadline < *at => deadline,
+		(Some((at, new)), _) => {
+		    level = *new;
+		    edges.next();
+		    *at
+		}
+		(None, Some(deadline)) => deadline,
+		(None, None) => break,
+	    };
+	    if let Some(pressed) = debouncer.update(level, now) {
+		events.push((now, pressed));
+	    }
+	}
+	Outcome { events, glitches: debouncer.glitches() }
+    }
+}
+
+impl Outcome {
+    /// Came out as `trace` says it should.
+    pub fn pass(&self, trace: &Trace) -> bool {
+	self.events.iter().map(|(_, pressed)| *pressed).eq(trace.expect.iter().copied())
+    }
+}
@@ -0,0 +1,44 @@
+//! Host side of the UART protocol (see `selector::protocol`), the selector
+//! running on a virtual panel (`sim`) on made up time (`runtime`), and
+//! bounce traces for checking the debouncer (`bounce`).
+
+use std::io::{self, Read, Write};
+
+use selector::protocol::{self, Decoder, Error, Packet, MAX_FRAME};
+
+pub mod bounce;
+pub mod runtime;
+pub mod sim;
+
+/// Frames over anything that can be read and written - a serial port, a
+/// pseudo terminal, a pipe.
+pub struct Link<P> {
+    port:    P,
+    decoder: Decoder,
+}
+
+impl<P: Read + Write> Link<P> {
+    pub fn new(port: P) -> Self {
+	Self { port, decoder: Decoder::new() }
+    }
+
+    pub fn send(&mut self, packet: &impl Packet) -> io::Result<()> {
+	let mut frame = [0; MAX_FRAME];
+	self.port.write_all(protocol::encode(packet, &mut frame))?;
+	self.port.flush()
+    }
+
+    /// Waits for the next frame. Broken frames are returned as errors, so
+    /// the caller can decide whether to care.
+    pub fn receive<T: Packet>(&mut self) -> io::Result<Result<T, Error>> {
+	let mut byte = [0];
+	loop {
+	    if self.port.read(&mut byte)? == 0 {
+		return Err(io::ErrorKind::UnexpectedEof.into());
+	    }
+	    if let Some(payload) = self.decoder.push(byte[0]) {
+		return Ok(payload.and_then(T::read));
+	    }
+	}
+    }
+}
@@ -0,0 +1,269 @@
+//! Made up time for embassy, and just enough of an executor around it to
+//! run the tasks in `selector::tasks` on the host. The time driver is
+//! `embassy_time_driver`'s, so `Timer` and `Instant` are the real thing -
+//! and so are the embassy-sync channels and signals between the tasks.
+//!
+//! Time only moves when `Executor::run_until()` moves it, and then only to
+//! the next alarm, once everything that was due before it has run. So the
+//! same inputs make the same outputs at the same times, every run, however
+//! slow the machine.
+//!
+//! There's only the one driver, so only one `Clock` at a time - a second
+//! one waits for the first to be dropped.
+
+use std::future::{pending, Future};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Wake, Waker};
+
+use embassy_time::{Duration, Instant, Timer};
+use embassy_time_driver::{AlarmHandle, Driver};
+
+// Polls at one point in time before we decide a task is spinning.
+const MAX_POLLS: usize = 100_000;
+
+// In ticks, like `embassy_time_driver` wants it. The alarm is `u64::MAX`
+// when it's not set, and there's only one - the timer queue's.
+struct Virtual {
+    state: Mutex<State>,
+}
+
+struct State {
+    now:      u64,
+    alarm:    u64,
+    callback: Option<(fn(*mut ()), usize)>,
+}
+
+impl Virtual {
+    fn lock(&self) -> MutexGuard<'_, State> {
+	self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // When the alarm goes off, if it's set.
+    fn next(&self) -> Option<u64> {
+	Some(self.lock().alarm).filter(|at| *at != u64::MAX)
+    }
+
+    // Never backwards. If the alarm's due, it goes off - outside the lock,
+    // as the timer queue sets the next one from the callback.
+    fn set(&self, now: u64) {
+	let callback = {
+	    let mut state = self.lock();
+	    state.now = state.now.max(now);
+	    match state.alarm <= state.now {
+		true => {
+		    state.alarm = u64::MAX;
+		    state.callback
+		}
+		false => None,
+	    }
+	};
+	if let Some((callback, ctx)) = callback {
+	    callback(ctx as *mut ());
+	}
+    }
+}
+
+impl Driver for Virtual {
+    fn now(&self) -> u64 {
+	self.lock().now
+    }
+
+    unsafe fn allocate_alarm(&self) -> Option<AlarmHandle> {
+	Some(AlarmHandle::new(0))
+    }
+
+    fn set_alarm_callback(&self, _alarm: AlarmHandle, callback: fn(*mut ()), ctx: *mut ()) {
+	self.lock().callback = Some((callback, ctx as usize));
+    }
+
+    // False if it's passed already, then the queue deals with it itself.
+    fn set_alarm(&self, _alarm: AlarmHandle, timestamp: u64) -> bool {
+	let mut state = self.lock();
+	if timestamp <= state.now {
+	    state.alarm = u64::MAX;
+	    return false;
+	}
+	state.alarm = timestamp;
+	true
+    }
+}
+
+embassy_time_driver::time_driver_impl!(static DRIVER: Virtual = Virtual {
+    state: Mutex::new(State { now: 0, alarm: u64::MAX, callback: None }),
+});
+
+// Whoever has the clock.
+static CLOCK: Mutex<()> = Mutex::new(());
+
+/// The time, in ms since the clock was made. Embassy's time goes on from
+/// one clock to the next, so timers that outlived theirs only cost a poll.
+pub struct Clock {
+    epoch: Instant,
+    _only: MutexGuard<'static, ()>,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+	let only = CLOCK.lock().unwrap_or_else(PoisonError::into_inner);
+	Self { epoch: Instant::now(), _only: only }
+    }
+}
+
+impl Clock {
+    pub fn now(&self) -> u64 {
+	(Instant::now() - self.epoch).as_millis()
+    }
+
+    /// Returns at `at`, straight away if that's passed - never for
+    /// `u64::MAX`, which doesn't take up a place in the timer queue.
+    pub fn at(&self, at: u64) -> impl Future<Output = ()> {
+	let at = (at != u64::MAX).then(|| self.instant(at));
+	async move {
+	    match at {
+		Some(at) => Timer::at(at).await,
+		None => pending().await,
+	    }
+	}
+    }
+
+    fn instant(&self, ms: u64) -> Instant {
+	self.epoch + Duration::from_millis(ms)
+    }
+}
+
+struct Woken(AtomicBool);
+
+impl Wake for Woken {
+    fn wake(self: Arc<Self>) {
+	self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+struct Task {
+    future: Pin<Box<dyn Future<Output = ()>>>,
+    woken:  Arc<Woken>,
+}
+
+/// Runs tasks until they're all waiting, one at a time.
+#[derive(Default)]
+pub struct Executor {
+    tasks: Vec<Task>,
+}
+
+impl Executor {
+    /// Starts `future` the next time anything runs.
+    pub fn spawn(&mut self, future: impl Future<Output = ()> + 'static) {
+	self.tasks.push(Task { future: Box::pin(future), woken: Arc::new(Woken(AtomicBool::new(true))) });
+    }
+
+    /// Polls whatever was woken, until nothing is.
+    pub fn run(&mut self) {
+	for _ in 0..MAX_POLLS {
+	    let mut ran = false;
+	    for task in &mut self.tasks {
+		if task.woken.0.swap(false, Ordering::Relaxed) {
+		    ran = true;
+		    let waker = Waker::from(task.woken.clone());
+		    // Tasks don't return - if one does, it's just never woken again.
+		    _ = task.future.as_mut().poll(&mut Context::from_waker(&waker));
+		}
+	    }
+	    if !ran {
+		return;
+	    }
+	}
+	panic!("tasks still running after {} polls", MAX_POLLS);
+    }
+
+    /// Lets time pass until `until` on `clock`, running whatever comes due
+    /// on the way.
+    pub fn run_until(&mut self, clock: &Clock, until: u64) {
+	let until = clock.instant(until).as_ticks();
+	self.run();
+	while let Some(next) = DRIVER.next().filter(|next| *next <= until) {
+	    DRIVER.set(next);
+	    self.run();
+	}
+	DRIVER.set(until);
+	self.run();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::future::poll_fn;
+    use std::rc::Rc;
+    use std::task::Poll;
+
+    use super::*;
+
+    #[test]
+    fn timers() {
+	let clock = Rc::new(Clock::default());
+	let log = Rc::new(RefCell::new(Vec::new()));
+	let mut executor = Executor::default();
+	for at in [300, 100, 200] {
+	    let (clock, log) = (clock.clone(), log.clone());
+	    executor.spawn(async move {
+		clock.at(at).await;
+		log.borrow_mut().push((at, clock.now()));
+	    });
+	}
+
+	// Each one at its own time, in order, and no further than asked.
+	executor.run_until(&clock, 250);
+	assert_eq!(*log.borrow(), [(100, 100), (200, 200)]);
+	assert_eq!(clock.now(), 250);
+	executor.run_until(&clock, 1000);
+	assert_eq!(*log.borrow(), [(100, 100), (200, 200), (300, 300)]);
+	assert_eq!(clock.now(), 1000);
+    }
+
+    #[test]
+    fn embassy() {
+	// Embassy's own timers, on the same time.
+	let clock = Rc::new(Clock::default());
+	let log = Rc::new(RefCell::new(Vec::new()));
+	let mut executor = Executor::default();
+	let (c, l) = (clock.clone(), log.clone());
+	executor.spawn(async move {
+	    let start = Instant::now();
+	    Timer::after_millis(40).await;
+	    l.borrow_mut().push(((Instant::now() - start).as_millis(), c.now()));
+	});
+
+	executor.run_until(&clock, 39);
+	assert!(log.borrow().is_empty());
+	executor.run_until(&clock, 100);
+	assert_eq!(*log.borrow(), [(40, 40)]);
+    }
+
+    #[test]
+    fn never() {
+	let clock = Clock::default();
+	let mut executor = Executor::default();
+	let done = Rc::new(RefCell::new(false));
+	let (at, d) = (clock.at(u64::MAX), done.clone());
+	executor.spawn(async move {
+	    at.await;
+	    *d.borrow_mut() = true;
+	});
+	executor.run_until(&clock, 1_000_000);
+	assert!(!*done.borrow());
+	assert_eq!(DRIVER.next(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "still running")]
+    fn spinning() {
+	let mut executor = Executor::default();
+	executor.spawn(poll_fn(|cx| {
+	    cx.waker().wake_by_ref();
+	    Poll::Pending
+	}));
+	executor.run();
+    }
+}
@@ -0,0 +1,456 @@
+//! The selector on a virtual panel - the button readers, the gesture
+//! handler, the LED drivers, the status light and the power manager from
+//! `selector::tasks`, the same code the board runs, on embassy's time and
+//! channels with made up time underneath (see `runtime`).
+//!
+//! Nothing happens by itself, time only moves on a `Step::Wait` - so the
+//! same script gives the same output every time. What the hardware would
+//! show goes to a `Panel`.
+//!
+//! A script can also say what it expects to happen - the gear, the LEDs,
+//! something being printed - and the step fails if it didn't.
+
+use std::cell::{Cell, RefCell};
+use std::future::{poll_fn, Future};
+use std::rc::Rc;
+use std::task::{Poll, Waker};
+
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::signal::Signal;
+
+use selector::console::Event;
+use selector::tasks::{self, ActiveInput, Board, Confirmation, LedOutput, Shared, StatusPixel, WaitInput};
+use selector::{
+    Edge, Fault, Gear, GearSelector, Gesture, Inputs, LedStatus, Mode, Reaction, Rgb, Selection, StatusEvent,
+    DEFAULT_INTERLOCK,
+};
+
+use crate::runtime::{Clock, Executor};
+
+/// Where the outputs go. All times are in ms since the start.
+pub trait Panel {
+    /// The LED of `gear` changed to `level` (0-255).
+    fn led(&mut self, at: u64, gear: Gear, level: u8);
+
+    /// The NeoPixel changed.
+    fn pixel(&mut self, at: u64, color: Rgb);
+
+    /// Something the console would print - button, gear change, fault.
+    fn event(&mut self, at: u64, event: Event);
+}
+
+/// One line of a script.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Step {
+    Press(Gear),
+    Release(Gear),
+    Wait(u64),
+    Brake(bool),
+    Standstill(bool),
+    Ignition(bool),
+
+    /// The transmission says it's in this gear, or (`None`) in whatever
+    /// we asked for.
+    Confirm(Option<Gear>),
+
+    Expect(Expect),
+}
+
+/// What a script can check. `None` is no gear.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expect {
+    Gear(Option<Gear>),
+    Requested(Option<Gear>),
+    Mode(Mode),
+    ParkLock(bool),
+    Asleep(bool),
+
+    /// The LED of a gear is at this level right now.
+    Led(Gear, u8),
+
+    /// Something printed since the last time events were checked has this
+    /// in it.
+    Event(String),
+}
+
+pub const SCRIPT_HELP: &str = "\
+press <gear>            button down
+release <gear>          button up
+tap <gear> [ms]         press, wait (100ms) and release
+hold <gear> <ms>        the same, for longer
+wait <ms>               let time pass
+brake <on|off>          brake pedal
+standstill <on|off>     vehicle standing still
+ignition <on|off>       ignition (starts out off)
+confirm [gear]          the transmission confirms the requested gear (or that one)
+expect gear <gear|->    the engaged gear
+expect requested <gear|->
+			the gear waiting to be confirmed
+expect mode <mode>      normal, sport or manual
+expect park-lock <on|off>
+expect asleep <on|off>  gone to sleep, parked and idle
+expect led <gear> <on|off|0-255>
+expect event <text>     something with this in it was printed since the last one";
+
+/// Parse a line of a script - `#` starts a comment. Some lines are more
+/// than one step.
+pub fn parse(line: &str) -> Result<Vec<Step>, String> {
+    let words: Vec<&str> = line.split('#').next().unwrap().split_whitespace().collect();
+    let gear = |name: &str| Gear::from_name(name).ok_or_else(|| format!("no such gear '{}'", name));
+    let ms = |ms: &str| ms.parse::<u64>().map_err(|_| format!("'{}' isn't a time in ms", ms));
+    let on = |on: &str| match on {
+	"on"  => Ok(true),
+	"off" => Ok(false),
+	_ => Err(format!("'{}' should be on or off", on)),
+    };
+    let maybe = |name: &str| match name {
+	"-" => Ok(None),
+	_ => gear(name).map(Some),
+    };
+    let mode = |name: &str| {
+	[Mode::Normal, Mode::Sport, Mode::Manual].into_iter().find(|m| m.name() == name)
+	    .ok_or_else(|| format!("no such mode '{}'", name))
+    };
+    let level = |level: &str| match level {
+	"on"  => Ok(255),
+	"off" => Ok(0),
+	_ => level.parse::<u8>().map_err(|_| format!("'{}' should be on, off or 0-255", level)),
+    };
+
+    Ok(match words[..] {
+	[] => vec![],
+	["press", g] => vec![Step::Press(gear(g)?)],
+	["release", g] => vec![Step::Release(gear(g)?)],
+	["tap", g] => vec![Step::Press(gear(g)?), Step::Wait(100), Step::Release(gear(g)?)],
+	["tap", g, t] | ["hold", g, t] => vec![Step::Press(gear(g)?), Step::Wait(ms(t)?), Step::Release(gear(g)?)],
+	["wait", t] => vec![Step::Wait(ms(t)?)],
+	["brake", o] => vec![Step::Brake(on(o)?)],
+	["standstill", o] => vec![Step::Standstill(on(o)?)],
+	["ignition", o] => vec![Step::Ignition(on(o)?)],
+	["confirm"] => vec![Step::Confirm(None)],
+	["confirm", g] => vec![Step::Confirm(Some(gear(g)?))],
+	["expect", "gear", g] => vec![Step::Expect(Expect::Gear(maybe(g)?))],
+	["expect", "requested", g] => vec![Step::Expect(Expect::Requested(maybe(g)?))],
+	["expect", "mode", m] => vec![Step::Expect(Expect::Mode(mode(m)?))],
+	["expect", "park-lock", o] => vec![Step::Expect(Expect::ParkLock(on(o)?))],
+	["expect", "asleep", o] => vec![Step::Expect(Expect::Asleep(on(o)?))],
+	["expect", "led", g, l] => vec![Step::Expect(Expect::Led(gear(g)?, level(l)?))],
+	["expect", "event", ref text @ ..] if !text.is_empty() => vec![Step::Expect(Expect::Event(text.join(" ")))],
+	_ => return Err(format!("can't make sense of '{}'", line.trim())),
+    })
+}
+
+/// Same as `BOARD.confirm_ms` on the panel board.
+pub const CONFIRM_MS: u64 = 2000;
+
+/// Nothing to show, the script checks what it wants to.
+pub struct Quiet;
+
+impl Panel for Quiet {
+    fn led(&mut self, _: u64, _: Gear, _: u8) {}
+    fn pixel(&mut self, _: u64, _: Rgb) {}
+    fn event(&mut self, _: u64, _: Event) {}
+}
+
+/// Run `script` on a fresh P/R/N/D panel in P, with gear changes needing a
+/// confirmation within `CONFIRM_MS`. Returns how many `expect`s there were,
+/// or where it went wrong.
+pub fn run(script: &str) -> Result<usize, String> {
+    let mut sim = Simulator::new(Quiet, &[Gear::P, Gear::R, Gear::N, Gear::D], Gear::P, Some(CONFIRM_MS));
+    let mut checks = 0;
+    for (n, line) in script.lines().enumerate() {
+	let steps = parse(line).map_err(|e| format!("line {}: {}", n + 1, e))?;
+	for step in steps {
+	    checks += matches!(step, Step::Expect(_)) as usize;
+	    sim.step(step).map_err(|e| format!("line {} at {}ms: {}", n + 1, sim.now(), e))?;
+	}
+    }
+    Ok(checks)
+}
+
+// ================================================================================
+
+// A button or the ignition, set by the script.
+#[derive(Clone, Default)]
+struct Switch(Rc<SwitchState>);
+
+#[derive(Default)]
+struct SwitchState {
+    on:    Cell<bool>,
+    seen:  Cell<bool>,
+    waker: RefCell<Option<Waker>>,
+}
+
+impl Switch {
+    fn set(&self, on: bool) {
+	self.0.on.set(on);
+	if let Some(waker) = self.0.waker.take() {
+	    waker.wake();
+	}
+    }
+}
+
+impl ActiveInput for Switch {
+    fn is_active(&mut self) -> bool {
+	self.0.seen.set(self.0.on.get());
+	self.0.on.get()
+    }
+}
+
+impl WaitInput for Switch {
+    async fn changed(&mut self) {
+	poll_fn(|cx| match self.0.on.get() != self.0.seen.get() {
+	    true  => Poll::Ready(()),
+	    false => {
+		*self.0.waker.borrow_mut() = Some(cx.waker().clone());
+		Poll::Pending
+	    }
+	}).await
+    }
+}
+
+// What the tasks run on - the `Board` the firmware has, with the hardware
+// being the panel.
+struct SimBoard<P> {
+    clock:     Clock,
+    gears:     Vec<Gear>,
+    shared:    RefCell<Shared>,
+    leds:      Vec<Channel<NoopRawMutex, LedStatus, 64>>,
+    status:    Channel<NoopRawMutex, StatusEvent, 8>,
+    gestures:  Channel<NoopRawMutex, (Gear, Gesture), 16>,
+    confirmed: Signal<NoopRawMutex, Confirmation>,
+    activity:  Signal<NoopRawMutex, ()>,
+    levels:    RefCell<Vec<u8>>,
+    color:     Cell<Rgb>,
+    panel:     RefCell<P>,
+
+    // Everything printed since the last `expect event`.
+    events:    RefCell<Vec<Event>>,
+}
+
+impl<P: Panel> SimBoard<P> {
+    fn event(&self, event: Event) {
+	self.events.borrow_mut().push(event);
+	self.panel.borrow_mut().event(self.clock.now(), event);
+    }
+}
+
+impl<P: Panel> Board for SimBoard<P> {
+    fn now(&self) -> u64 {
+	self.clock.now()
+    }
+
+    fn at(&self, at: u64) -> impl Future<Output = ()> {
+	self.clock.at(at)
+    }
+
+    fn positions(&self) -> usize {
+	self.gears.len()
+    }
+
+    fn gear(&self, index: usize) -> Gear {
+	self.gears[index]
+    }
+
+    fn lock<R>(&self, f: impl FnOnce(&mut Shared) -> R) -> R {
+	f(&mut self.shared.borrow_mut())
+    }
+
+    fn send_led(&self, index: usize, status: LedStatus) -> bool {
+	self.leds[index].try_send(status).is_ok()
+    }
+
+    async fn receive_led(&self, index: usize) -> LedStatus {
+	self.leds[index].receive().await
+    }
+
+    fn send_status(&self, event: StatusEvent) -> bool {
+	self.status.try_send(event).is_ok()
+    }
+
+    async fn receive_status(&self) -> StatusEvent {
+	self.status.receive().await
+    }
+
+    async fn send_gesture(&self, button: Gear, gesture: Gesture) {
+	self.gestures.send((button, gesture)).await
+    }
+
+    async fn receive_gesture(&self) -> (Gear, Gesture) {
+	self.gestures.receive().await
+    }
+
+    async fn receive_confirmation(&self) -> Confirmation {
+	self.confirmed.wait().await
+    }
+
+    fn activity(&self) {
+	self.activity.signal(());
+    }
+
+    async fn wait_activity(&self) {
+	self.activity.wait().await
+    }
+
+    fn button(&self, index: usize, edge: Edge) {
+	self.event(Event::Button(self.gears[index], edge == Edge::Press));
+    }
+
+    fn changed(&self, selection: &Selection) {
+	self.event(Event::GearChanged(*selection));
+    }
+
+    fn fault(&self, fault: Fault, _reaction: Reaction) {
+	self.event(Event::Fault(fault));
+    }
+}
+
+// The LED of `index` - tells the panel about anything that changed.
+struct Light<P> {
+    board: Rc<SimBoard<P>>,
+    index: usize,
+}
+
+impl<P: Panel> LedOutput for Light<P> {
+    fn set(&mut self, level: u8) {
+	let old = std::mem::replace(&mut self.board.levels.borrow_mut()[self.index], level);
+	if level != old {
+	    self.board.panel.borrow_mut().led(self.board.now(), self.board.gears[self.index], level);
+	}
+    }
+}
+
+struct Pixel<P>(Rc<SimBoard<P>>);
+
+impl<P: Panel> StatusPixel for Pixel<P> {
+    async fn show(&mut self, color: Rgb) {
+	if self.0.color.replace(color) != color {
+	    self.0.panel.borrow_mut().pixel(self.0.now(), color);
+	}
+    }
+}
+
+/// The tasks in `selector::tasks`, as the board runs them, on a panel.
+pub struct Simulator<P> {
+    board:    Rc<SimBoard<P>>,
+    executor: Executor,
+    buttons:  Vec<Switch>,
+    ignition: Switch,
+}
+
+impl<P: Panel + 'static> Simulator<P> {
+    /// A panel with a button and LED for each of `gears`, starting out in
+    /// `gear` with the brake pressed and standing still. Every gear change
+    /// needs a confirmation within `confirm_ms`, if given. There's only
+    /// the one time driver, so waits for any other simulator to be dropped.
+    pub fn new(panel: P, gears: &[Gear], gear: Gear, confirm_ms: Option<u64>) -> Self {
+	let mut shared = Shared::new(match confirm_ms {
+	    Some(ms) => GearSelector::new(DEFAULT_INTERLOCK).confirm_within(ms),
+	    None => GearSelector::new(DEFAULT_INTERLOCK),
+	});
+	shared.inputs = Inputs { brake: true, standstill: true };
+	let restored = shared.selector.restore(gear);
+
+	let board = Rc::new(SimBoard {
+	    clock:     Clock::default(),
+	    gears:     gears.to_vec(),
+	    shared:    RefCell::new(shared),
+	    leds:      gears.iter().map(|_| Channel::new()).collect(),
+	    status:    Channel::new(),
+	    gestures:  Channel::new(),
+	    confirmed: Signal::new(),
+	    activity:  Signal::new(),
+	    levels:    RefCell::new(vec![0; gears.len()]),
+	    color:     Cell::new((0, 0, 0)),
+	    panel:     RefCell::new(panel),
+	    events:    RefCell::new(Vec::new()),
+	});
+	let buttons: Vec<Switch> = gears.iter().map(|_| Switch::default()).collect();
+	let ignition = Switch::default();
+
+	// The same tasks `main` spawns, less the ones for hardware we don't
+	// have.
+	let mut executor = Executor::default();
+	for (index, button) in buttons.iter().enumerate() {
+	    let (b, input) = (board.clone(), button.clone());
+	    executor.spawn(async move { tasks::button_loop(&*b, index, input).await });
+	    let (b, output) = (board.clone(), Light { board: board.clone(), index });
+	    executor.spawn(async move { tasks::led_loop(&*b, index, output, None::<Switch>).await });
+	}
+	let b = board.clone();
+	executor.spawn(async move { tasks::gesture_loop(&*b).await });
+	let (b, mut pixel) = (board.clone(), Pixel(board.clone()));
+	executor.spawn(async move { tasks::status_loop(&*b, &mut pixel, restored).await });
+	let (b, input) = (board.clone(), ignition.clone());
+	executor.spawn(async move { tasks::power_loop(&*b, Some(input)).await });
+
+	tasks::show_leds(&*board, &restored);
+	executor.run();
+	Self { board, executor, buttons, ignition }
+    }
+
+    pub fn now(&self) -> u64 {
+	self.board.now()
+    }
+
+    pub fn selection(&self) -> Selection {
+	self.board.lock(|s| s.selector.selection())
+    }
+
+    /// The level of the LED of `gear`, if there is one.
+    pub fn led(&self, gear: Gear) -> Option<u8> {
+	let index = self.board.gears.iter().position(|g| *g == gear)?;
+	Some(self.board.levels.borrow()[index])
+    }
+
+    pub fn step(&mut self, step: Step) -> Result<(), String> {
+	match step {
+	    Step::Press(gear) | Step::Release(gear) => {
+		let index = self.board.gears.iter().position(|g| *g == gear)
+		    .ok_or_else(|| format!("there's no {} button", gear.name()))?;
+		self.buttons[index].set(matches!(step, Step::Press(_)));
+	    }
+	    Step::Wait(ms) => {
+		let until = self.board.now() + ms;
+		self.executor.run_until(&self.board.clock, until);
+	    }
+	    Step::Brake(on) => self.board.lock(|s| s.inputs.brake = on),
+	    Step::Standstill(on) => self.board.lock(|s| s.inputs.standstill = on),
+	    Step::Ignition(on) => self.ignition.set(on),
+	    Step::Confirm(gear) => self.board.confirmed.signal(gear.map_or(Confirmation::Requested, Confirmation::Gear)),
+	    Step::Expect(expect) => return self.check(expect),
+	}
+	self.executor.run();
+	Ok(())
+    }
+
+    fn check(&mut self, expect: Expect) -> Result<(), String> {
+	let selection = self.selection();
+	let asleep = self.board.lock(|s| s.asleep);
+	let gear = |gear: Option<Gear>| gear.map_or("-", Gear::name);
+	let on = |on: bool| if on { "on" } else { "off" };
+	let (what, wanted, got) = match expect {
+	    Expect::Gear(g)      => ("gear", gear(g).to_string(), gear(selection.gear).to_string()),
+	    Expect::Requested(g) => ("requested", gear(g).to_string(), gear(selection.requested).to_string()),
+	    Expect::Mode(m)      => ("mode", m.name().to_string(), selection.mode.name().to_string()),
+	    Expect::ParkLock(o)  => ("park lock", on(o).to_string(), on(selection.park_lock).to_string()),
+	    Expect::Asleep(o)    => ("asleep", on(o).to_string(), on(asleep).to_string()),
+	    Expect::Led(g, level) => {
+		let got = self.led(g).ok_or_else(|| format!("there's no {} LED", g.name()))?;
+		("LED", format!("{} {}", g.name(), level), format!("{} {}", g.name(), got))
+	    }
+	    Expect::Event(text) => {
+		let events: Vec<String> = self.board.events.borrow_mut().drain(..).map(|e| e.to_string()).collect();
+		if events.iter().any(|e| e.contains(&text)) {
+		    return Ok(());
+		}
+		("event", text, if events.is_empty() { "nothing".to_string() } else { events.join(", ") })
+	    }
+	};
+	match wanted == got {
+	    true  => Ok(()),
+	    false => Err(format!("expected {} {}, got {}", what, wanted, got)),
+	}
+    }
+}
@@ -0,0 +1,71 @@
+//! Every scenario in `scenarios/`, on the tasks the board runs - what
+//! `selector-scenarios scenarios/*.scenario` does.
+
+use std::fs;
+use std::path::Path;
+
+use selector_host::sim;
+
+fn check(name: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios").join(name);
+    if let Err(e) = sim::run(&fs::read_to_string(&path).unwrap()) {
+	panic!("{}: {}", name, e);
+    }
+}
+
+#[test]
+fn interlock() {
+    check("interlock.scenario");
+}
+
+#[test]
+fn not_confirmed() {
+    check("not-confirmed.scenario");
+}
+
+#[test]
+fn park_to_drive() {
+    check("park-to-drive.scenario");
+}
+
+#[test]
+fn short_long_press() {
+    check("short-long-press.scenario");
+}
+
+#[test]
+fn sleep() {
+    check("sleep.scenario");
+}
+
+#[test]
+fn wake_press() {
+    check("wake-press.scenario");
+}
+
+// A new scenario needs a test of its own above.
+#[test]
+fn all_checked() {
+    let mut names: Vec<_> = fs::read_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios")).unwrap()
+	.map(|entry| entry.unwrap().file_name().into_string().unwrap())
+	.collect();
+    names.sort();
+    assert_eq!(names, [
+	"interlock.scenario",
+	"not-confirmed.scenario",
+	"park-to-drive.scenario",
+	"short-long-press.scenario",
+	"sleep.scenario",
+	"wake-press.scenario",
+    ]);
+}
+
+// Expectations that don't hold fail, and say where.
+#[test]
+fn failing() {
+    assert_eq!(sim::run("expect gear P\nexpect gear D\n").unwrap_err(), "line 2 at 0ms: expected gear D, got P");
+    let e = sim::run("tap D\nwait 1000\nexpect event nope\n").unwrap_err();
+    assert!(e.starts_with("line 3 at 1100ms: expected event nope"), "{}", e);
+    assert!(sim::run("frobnicate\n").is_err());
+    assert_eq!(sim::run("expect gear P\nexpect led P on\n"), Ok(2));
+}
@@ -0,0 +1,58 @@
+//! Every trace in `traces/`, through every debouncing algorithm - what
+//! `selector-bounce traces/*.trace` does.
+
+use std::fs;
+use std::path::Path;
+
+use selector::Algorithm;
+use selector_host::bounce::Trace;
+
+fn check(name: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("traces").join(name);
+    let trace = Trace::parse(&fs::read_to_string(&path).unwrap()).unwrap();
+    for algorithm in Algorithm::ALL {
+	let outcome = trace.run(algorithm);
+	assert!(outcome.pass(&trace), "{} with {}: {:?}", name, algorithm.name(), outcome.events);
+    }
+}
+
+#[test]
+fn clean() {
+    check("clean.trace");
+}
+
+#[test]
+fn bouncy() {
+    check("bouncy.trace");
+}
+
+#[test]
+fn dropouts() {
+    check("dropouts.trace");
+}
+
+#[test]
+fn spikes() {
+    check("spikes.trace");
+}
+
+// A new trace needs a test of its own above.
+#[test]
+fn all_checked() {
+    let mut names: Vec<_> = fs::read_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join("traces")).unwrap()
+	.map(|entry| entry.unwrap().file_name().into_string().unwrap())
+	.collect();
+    names.sort();
+    assert_eq!(names, ["bouncy.trace", "clean.trace", "dropouts.trace", "spikes.trace"]);
+}
+
+#[test]
+fn parse() {
+    let trace = Trace::parse("# Comment\n100 1\n\n250 0  # released\nexpect press release\n").unwrap();
+    assert_eq!(trace.edges, [(100, true), (250, false)]);
+    assert_eq!(trace.expect, [true, false]);
+
+    assert!(Trace::parse("100 2\n").is_err());
+    assert!(Trace::parse("expect hold\n").is_err());
+    assert_eq!(Trace::parse("200 1\n100 0\n").err().unwrap(), "line 2: time going backwards");
+}
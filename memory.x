@@ -1,7 +1,11 @@
 MEMORY {
-    BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
-    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
-    RAM   : ORIGIN = 0x20000000, LENGTH = 256K
+    BOOT2  : ORIGIN = 0x10000000, LENGTH = 0x100
+    FLASH  : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100 - 32K
+    /* The event log, the trouble codes and the selected gear, see `src/flash.rs` */
+    EVENTS : ORIGIN = 0x101F8000, LENGTH = 16K
+    DTC    : ORIGIN = 0x101FC000, LENGTH = 8K
+    STATE  : ORIGIN = 0x101FE000, LENGTH = 8K
+    RAM    : ORIGIN = 0x20000000, LENGTH = 256K
 }
 
 EXTERN(BOOT2_FIRMWARE)
@@ -0,0 +1,57 @@
+//! What each gesture on each button actually does.
+
+use crate::gear::{Gear, Mode};
+use crate::gesture::{Gesture, GestureKind};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Select a gear.
+    Select(Gear),
+
+    /// Select P and confirm the park lock.
+    ParkLock,
+
+    /// Toggle a drive sub-mode (only in D).
+    Mode(Mode),
+}
+
+/// One button/gesture combination and its action. Buttons go by the gear
+/// they belong to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Mapping {
+    pub button:  Gear,
+    pub gesture: GestureKind,
+    pub action:  Action,
+}
+
+/// The table of what to do on what. A short press always selects the
+/// button's own gear, unless the table says otherwise.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ActionMap {
+    pub mappings: &'static [Mapping],
+}
+
+pub const DEFAULT_ACTIONS: ActionMap = ActionMap {
+    mappings: &[
+	Mapping { button: Gear::P, gesture: GestureKind::Long,   action: Action::ParkLock },
+	Mapping { button: Gear::D, gesture: GestureKind::Long,   action: Action::Mode(Mode::Sport) },
+	Mapping { button: Gear::D, gesture: GestureKind::Double, action: Action::Mode(Mode::Manual) },
+    ],
+};
+
+impl ActionMap {
+    pub fn action(&self, button: Gear, gesture: Gesture) -> Option<Action> {
+	let kind = gesture.kind();
+	match self.mappings.iter().find(|m| m.button == button && m.gesture == kind) {
+	    Some(mapping) => Some(mapping.action),
+	    None if kind == GestureKind::Short => Some(Action::Select(button)),
+	    None => None,
+	}
+    }
+
+    /// Does this button do anything on a double press? If not, there's no
+    /// point in delaying its short presses waiting for one.
+    pub fn uses(&self, button: Gear, gesture: GestureKind) -> bool {
+	self.mappings.iter().any(|m| m.button == button && m.gesture == gesture)
+    }
+}
@@ -0,0 +1,194 @@
+//! Everything a button reader does with its input - debouncing, the stuck
+//! and shorted checks, and gestures - apart from actually reading it.
+//!
+//! Feed it the raw level on every edge, and whenever `deadline()` have
+//! passed. All times are in ms.
+
+use crate::action::ActionMap;
+use crate::debounce::{DebounceConfig, Debouncer};
+use crate::diag::ButtonCheck;
+use crate::fault::Fault;
+use crate::gear::Gear;
+use crate::gesture::{Gesture, GestureKind, GestureRecognizer, Thresholds};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Edge {
+    Press,
+
+    /// Released after being held this many ms.
+    Release(u64),
+}
+
+/// What came out of an `update()`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ButtonUpdate {
+    /// The debounced button changed.
+    pub edge: Option<Edge>,
+
+    /// Stuck, or shorted to another button.
+    pub fault: Option<Fault>,
+
+    pub gesture: Option<Gesture>,
+}
+
+#[derive(Debug)]
+pub struct ButtonReader {
+    gear:       Gear,
+    debouncer:  Debouncer,
+    thresholds: Thresholds,
+    gestures:   GestureRecognizer,
+    start:      u64,
+}
+
+impl ButtonReader {
+    /// The button of `gear`, released to start with. Only waits for a
+    /// double press if `actions` have something for it.
+    pub fn new(gear: Gear, debounce: DebounceConfig, mut thresholds: Thresholds, actions: &ActionMap) -> Self {
+	if !actions.uses(gear, GestureKind::Double) {
+	    thresholds.double_ms = 0;
+	}
+	Self {
+	    gear,
+	    debouncer: Debouncer::new(debounce, false),
+	    thresholds,
+	    gestures: GestureRecognizer::new(thresholds),
+	    start: 0,
+	}
+    }
+
+    pub fn gear(&self) -> Gear {
+	self.gear
+    }
+
+    pub fn pressed(&self) -> bool {
+	self.debouncer.pressed()
+    }
+
+    /// How many glitches the debouncer have seen.
+    pub fn glitches(&self) -> u32 {
+	self.debouncer.glitches()
+    }
+
+    /// When `update()` needs to be called next, even if the input doesn't
+    /// change - next sample, long press, held-repeat, end of the
+    /// double-press window, stuck check.
+    pub fn deadline(&self, check: &ButtonCheck) -> Option<u64> {
+	[self.debouncer.deadline(), self.gestures.deadline(), check.deadline(self.gear)].into_iter().flatten().min()
+    }
+
+    /// The input is at `raw` (true = pressed) at `now`. `check` is shared
+    /// with the other buttons.
+    pub fn update(&mut self, raw: bool, now: u64, check: &mut ButtonCheck) -> ButtonUpdate {
+	let mut update = ButtonUpdate::default();
+	let edge = self.debouncer.update(raw, now);
+	let ignored = check.ignored(self.gear);
+	match edge {
+	    Some(true) => {
+		self.start = now;
+		update.edge = Some(Edge::Press);
+		update.fault = check.press(self.gear, now);
+	    }
+	    Some(false) => {
+		update.edge = Some(Edge::Release(now - self.start));
+		check.release(self.gear);
+	    }
+	    None => update.fault = check.poll(self.gear, now),
+	}
+
+	// A stuck or shorted button is ignored until it's released, and
+	// whatever the recognizer made of it so far is forgotten.
+	if ignored || check.ignored(self.gear) {
+	    self.gestures = GestureRecognizer::new(self.thresholds);
+	    return update;
+	}
+
+	update.gesture = match edge {
+	    Some(true)  => self.gestures.press(now),
+	    Some(false) => self.gestures.release(now),
+	    None        => self.gestures.poll(now),
+	};
+	update
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::action::DEFAULT_ACTIONS;
+    use crate::debounce::DEFAULT_DEBOUNCE;
+    use crate::diag::DEFAULT_DIAG;
+    use crate::gesture::DEFAULT_THRESHOLDS;
+
+    // The raw input goes to each level at the given time. Returns all the
+    // updates with something in them, and when.
+    fn run(gear: Gear, edges: &[(u64, bool)], until: u64) -> Vec<(u64, ButtonUpdate)> {
+	let mut reader = ButtonReader::new(gear, DEFAULT_DEBOUNCE, DEFAULT_THRESHOLDS, &DEFAULT_ACTIONS);
+	let mut check = ButtonCheck::new(DEFAULT_DIAG);
+	let mut updates = Vec::new();
+	let mut raw = false;
+	let mut update = |reader: &mut ButtonReader, check: &mut ButtonCheck, raw, at| {
+	    let update = reader.update(raw, at, check);
+	    if update != ButtonUpdate::default() {
+		updates.push((at, update));
+	    }
+	};
+	for &(at, level) in edges.iter().chain([(until, false)].iter()) {
+	    while let Some(deadline) = reader.deadline(&check).filter(|d| *d < at) {
+		update(&mut reader, &mut check, raw, deadline);
+	    }
+	    if at < until {
+		raw = level;
+		update(&mut reader, &mut check, raw, at);
+	    }
+	}
+	updates
+    }
+
+    fn gestures(updates: &[(u64, ButtonUpdate)]) -> Vec<(u64, Gesture)> {
+	updates.iter().filter_map(|(at, u)| u.gesture.map(|g| (*at, g))).collect()
+    }
+
+    fn edges(updates: &[(u64, ButtonUpdate)]) -> Vec<(u64, Edge)> {
+	updates.iter().filter_map(|(at, u)| u.edge.map(|e| (*at, e))).collect()
+    }
+
+    #[test]
+    fn bouncy_short_press() {
+	// N has no double press, so it's reported on the (debounced) release.
+	let updates = run(Gear::N, &[(0, true), (2, false), (3, true), (100, false), (101, true), (102, false)], 5000);
+	assert_eq!(edges(&updates), [(23, Edge::Press), (122, Edge::Release(99))]);
+	assert_eq!(gestures(&updates), [(122, Gesture::Short)]);
+    }
+
+    #[test]
+    fn short_long_split() {
+	// R for 30ms, and a while later D for 1.5s.
+	let updates = run(Gear::R, &[(0, true), (30, false)], 5000);
+	assert_eq!(gestures(&updates), [(50, Gesture::Short)]);
+
+	let updates = run(Gear::D, &[(2000, true), (3500, false)], 5000);
+	assert_eq!(gestures(&updates), [(3020, Gesture::Repeat(1)), (3520, Gesture::Long(1500))]);
+    }
+
+    #[test]
+    fn double_press_waits() {
+	// D has a double press, so a short one waits for the window.
+	let updates = run(Gear::D, &[(0, true), (50, false)], 5000);
+	assert_eq!(gestures(&updates), [(320, Gesture::Short)]);
+
+	let updates = run(Gear::D, &[(0, true), (50, false), (150, true), (200, false)], 5000);
+	assert_eq!(gestures(&updates), [(170, Gesture::Double)]);
+    }
+
+    #[test]
+    fn stuck() {
+	// Held past the stuck limit - a fault, and no long press on release.
+	let limit = DEFAULT_DIAG.stuck_ms;
+	let updates = run(Gear::N, &[(0, true), (limit + 1000, false)], limit + 5000);
+	assert!(updates.iter().any(|(_, u)| u.fault == Some(Fault::StuckButton(Gear::N))));
+	assert!(!gestures(&updates).iter().any(|(_, g)| matches!(g, Gesture::Long(_))));
+    }
+}
@@ -0,0 +1,230 @@
+//! CAN frames to and from the transmission controller.
+//!
+//! We send the selected gear (periodically and whenever it changes) and
+//! the raw button presses/releases, and receive the transmission's
+//! acknowledgement of the gear it engaged. Which IDs to use and how the
+//! gears are coded on the bus is up to the `FrameLayout`.
+//!
+//! Gear frame:   `[gear code, mode, flags, counter]` - the gear we want to
+//!               be in, which is the requested one if there is one. Flags
+//!               bit 0 is the park lock, bit 1 set while we're still waiting
+//!               for the ack. The counter is incremented for every frame.
+//! Button frame: `[gear code of the button, 1 = pressed / 0 = released]`
+//! Ack frame:    `[gear code]`
+//!
+//! A gear code of `0xFF` means no gear selected.
+//!
+//! A tester can also clear our trouble codes, with a UDS style single frame
+//! Clear Diagnostic Information request, `[0x04, 0x14, 0xFF, 0xFF, 0xFF]`.
+//! It's answered with `[0x01, 0x54]`, anything else with `[0x03, 0x7F,
+//! service, 0x11]` (service not supported).
+
+use crate::gear::{Gear, Selection};
+
+pub const NO_GEAR: u8 = 0xFF;
+
+const CLEAR_DTCS: u8 = 0x14;
+const NEGATIVE: u8 = 0x7F;
+const NOT_SUPPORTED: u8 = 0x11;
+
+/// What a tester asked for.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DiagRequest {
+    ClearDtcs,
+
+    /// A service we don't do.
+    Unsupported(u8),
+}
+
+/// A standard (11 bit ID) CAN frame.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub id:   u16,
+    pub len:  u8,
+    pub data: [u8; 8],
+}
+
+impl Frame {
+    /// At most 8 bytes of `data` is used.
+    pub fn new(id: u16, data: &[u8]) -> Self {
+	let len = data.len().min(8);
+	let mut frame = Self { id, len: len as u8, data: [0; 8] };
+	frame.data[..len].copy_from_slice(&data[..len]);
+	frame
+    }
+
+    pub fn data(&self) -> &[u8] {
+	&self.data[..self.len as usize]
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FrameLayout {
+    pub gear_id:   u16,
+    pub button_id: u16,
+    pub ack_id:    u16,
+
+    /// Diagnostic requests from a tester, and our replies.
+    pub diag_id:       u16,
+    pub diag_reply_id: u16,
+
+    /// How often (in ms) to send the gear, even if it didn't change.
+    pub period_ms: u64,
+
+    /// How each gear is coded on the bus, indexed by `gear as usize`.
+    pub gear_codes: [u8; Gear::COUNT],
+}
+
+/// The gears as ASCII - easy to spot when sniffing the bus.
+pub const DEFAULT_LAYOUT: FrameLayout = FrameLayout {
+    gear_id:       0x3E0,
+    button_id:     0x3E1,
+    ack_id:        0x3E8,
+    diag_id:       0x7E5,
+    diag_reply_id: 0x7ED,
+    period_ms:     100,
+    gear_codes:    [b'P', b'N', b'R', b'D', b'L', b'2', b'1', b'S', b'M', b'B'],
+};
+
+impl FrameLayout {
+    pub fn encode_gear(&self, selection: &Selection, counter: u8) -> Frame {
+	let code = match selection.target() {
+	    Some(gear) => self.gear_codes[gear as usize],
+	    None => NO_GEAR,
+	};
+	let flags = selection.park_lock as u8 | (selection.requested.is_some() as u8) << 1;
+	Frame::new(self.gear_id, &[code, selection.mode as u8, flags, counter])
+    }
+
+    pub fn encode_button(&self, button: Gear, pressed: bool) -> Frame {
+	Frame::new(self.button_id, &[self.gear_codes[button as usize], pressed as u8])
+    }
+
+    /// What the tester wants, if this is a diagnostic request.
+    pub fn decode_diag(&self, frame: &Frame) -> Option<DiagRequest> {
+	if frame.id != self.diag_id || frame.len < 2 {
+	    return None;
+	}
+
+	// Single frame, length in the low nibble.
+	let data = frame.data();
+	let len = data[0] as usize;
+	if data[0] & 0xF0 != 0 || len == 0 || len >= data.len() {
+	    return None;
+	}
+	match &data[1..=len] {
+	    [CLEAR_DTCS, 0xFF, 0xFF, 0xFF] => Some(DiagRequest::ClearDtcs),
+	    [service, ..] => Some(DiagRequest::Unsupported(*service)),
+	    [] => None,
+	}
+    }
+
+    /// The answer to `request`, once it's done.
+    pub fn encode_diag_reply(&self, request: DiagRequest) -> Frame {
+	match request {
+	    DiagRequest::ClearDtcs => Frame::new(self.diag_reply_id, &[0x01, CLEAR_DTCS + 0x40]),
+	    DiagRequest::Unsupported(service) => Frame::new(self.diag_reply_id, &[0x03, NEGATIVE, service, NOT_SUPPORTED]),
+	}
+    }
+
+    /// The gear the transmission says it's in, if this is an ack frame.
+    /// `Some(None)` if it's in no gear at all.
+    pub fn decode_ack(&self, frame: &Frame) -> Option<Option<Gear>> {
+	if frame.id != self.ack_id || frame.len < 1 {
+	    return None;
+	}
+
+	match frame.data[0] {
+	    NO_GEAR => Some(None),
+	    code => {
+		let gear = self.gear_codes.iter().position(|c| *c == code)?;
+		Some(Gear::from_u8(gear as u8))
+	    }
+	}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gear::Mode;
+
+    fn selection(gear: Option<Gear>, requested: Option<Gear>) -> Selection {
+	Selection { gear, requested, mode: Mode::Normal, park_lock: false }
+    }
+
+    #[test]
+    fn gear_frame() {
+	let frame = DEFAULT_LAYOUT.encode_gear(&selection(Some(Gear::D), None), 7);
+	assert_eq!((frame.id, frame.data()), (0x3E0, &[b'D', 0, 0, 7][..]));
+
+	// The requested gear, still waiting for the ack.
+	let frame = DEFAULT_LAYOUT.encode_gear(&selection(Some(Gear::P), Some(Gear::R)), 8);
+	assert_eq!(frame.data(), [b'R', 0, 0x02, 8]);
+
+	let parked = Selection { park_lock: true, ..selection(Some(Gear::P), None) };
+	assert_eq!(DEFAULT_LAYOUT.encode_gear(&parked, 9).data(), [b'P', 0, 0x01, 9]);
+
+	let sport = Selection { mode: Mode::Sport, ..selection(Some(Gear::D), None) };
+	assert_eq!(DEFAULT_LAYOUT.encode_gear(&sport, 0).data(), [b'D', Mode::Sport as u8, 0, 0]);
+
+	assert_eq!(DEFAULT_LAYOUT.encode_gear(&selection(None, None), 0).data(), [NO_GEAR, 0, 0, 0]);
+    }
+
+    #[test]
+    fn button_frame() {
+	let frame = DEFAULT_LAYOUT.encode_button(Gear::N, true);
+	assert_eq!((frame.id, frame.data()), (0x3E1, &[b'N', 1][..]));
+	assert_eq!(DEFAULT_LAYOUT.encode_button(Gear::N, false).data(), [b'N', 0]);
+    }
+
+    #[test]
+    fn ack() {
+	for gear in Gear::ALL {
+	    let frame = Frame::new(0x3E8, &[DEFAULT_LAYOUT.gear_codes[gear as usize]]);
+	    assert_eq!(DEFAULT_LAYOUT.decode_ack(&frame), Some(Some(gear)));
+	}
+	assert_eq!(DEFAULT_LAYOUT.decode_ack(&Frame::new(0x3E8, &[NO_GEAR])), Some(None));
+
+	// Unknown code, wrong ID, empty.
+	assert_eq!(DEFAULT_LAYOUT.decode_ack(&Frame::new(0x3E8, b"X")), None);
+	assert_eq!(DEFAULT_LAYOUT.decode_ack(&Frame::new(0x3E9, b"D")), None);
+	assert_eq!(DEFAULT_LAYOUT.decode_ack(&Frame::new(0x3E8, &[])), None);
+    }
+
+    #[test]
+    fn other_layout() {
+	let mut layout = FrameLayout { ack_id: 0x100, ..DEFAULT_LAYOUT };
+	layout.gear_codes[Gear::D as usize] = 4;
+	assert_eq!(layout.decode_ack(&Frame::new(0x100, &[4])), Some(Some(Gear::D)));
+	assert_eq!(layout.encode_button(Gear::D, true).data(), [4, 1]);
+    }
+
+    #[test]
+    fn diag() {
+	let clear = Frame::new(0x7E5, &[0x04, 0x14, 0xFF, 0xFF, 0xFF, 0, 0, 0]);
+	assert_eq!(DEFAULT_LAYOUT.decode_diag(&clear), Some(DiagRequest::ClearDtcs));
+	let reply = DEFAULT_LAYOUT.encode_diag_reply(DiagRequest::ClearDtcs);
+	assert_eq!((reply.id, reply.data()), (0x7ED, &[0x01, 0x54][..]));
+
+	let other = Frame::new(0x7E5, &[0x02, 0x19, 0x02]);
+	assert_eq!(DEFAULT_LAYOUT.decode_diag(&other), Some(DiagRequest::Unsupported(0x19)));
+	let reply = DEFAULT_LAYOUT.encode_diag_reply(DiagRequest::Unsupported(0x19));
+	assert_eq!(reply.data(), [0x03, 0x7F, 0x19, 0x11]);
+    }
+
+    #[test]
+    fn not_diag() {
+	// Wrong ID, not a single frame, length past the end, nothing in it.
+	assert_eq!(DEFAULT_LAYOUT.decode_diag(&Frame::new(0x7E6, &[0x01, 0x14])), None);
+	assert_eq!(DEFAULT_LAYOUT.decode_diag(&Frame::new(0x7E5, &[0x10, 0x14])), None);
+	assert_eq!(DEFAULT_LAYOUT.decode_diag(&Frame::new(0x7E5, &[0x04, 0x14])), None);
+	assert_eq!(DEFAULT_LAYOUT.decode_diag(&Frame::new(0x7E5, &[0x00, 0x14])), None);
+    }
+
+    #[test]
+    fn frame_len() {
+	let frame = Frame::new(1, &[1, 2, 3, 4, 5, 6, 7, 8, 9]);
+	assert_eq!(frame.data(), [1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+}
@@ -0,0 +1,541 @@
+//! A line based console, for poking at a unit with nothing but a USB
+//! cable and a terminal. See `HELP` for the commands.
+
+use core::fmt::{self, Write};
+
+use crate::dtc::Dtc;
+use crate::events::{LogEntry, LogEvent};
+use crate::fault::Fault;
+use crate::gear::{Gear, Selection};
+use crate::latency::LatencyStats;
+use crate::watchdog::Task;
+
+pub const HELP: &str = "\
+help               this
+status             gear, inputs, boot count etc
+gear <gear>        select a gear
+led <n> <on|off>   turn a LED on or off, until the next gear change
+log [level]        show or set the log level (off, error, warn, info, debug)
+dtc [clear]        show or clear the trouble codes
+events [flash]     show the event log, in RAM or flash
+latency [clear]    show or clear the button to LED times
+reset              reboot
+";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Help,
+    Status,
+    Gear(Gear),
+    Led(usize, bool),
+    Log(Option<LogLevel>),
+    Dtcs,
+    ClearDtcs,
+    Events(bool),
+    Latency,
+    ClearLatency,
+    Reset,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    Unknown,
+
+    /// Known command, wrong arguments - this is how it should look.
+    Usage(&'static str),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	match self {
+	    ParseError::Unknown => f.write_str("unknown command, try 'help'"),
+	    ParseError::Usage(usage) => write!(f, "usage: {}", usage),
+	}
+    }
+}
+
+/// Parse a line. `Ok(None)` for an empty one.
+pub fn parse(line: &str) -> Result<Option<Command>, ParseError> {
+    let mut words = line.split_ascii_whitespace();
+    let Some(name) = words.next() else {
+	return Ok(None);
+    };
+    let usage = match name {
+	"help"    => "help",
+	"status"  => "status",
+	"gear"    => "gear <gear>",
+	"led"     => "led <n> <on|off>",
+	"log"     => "log [off|error|warn|info|debug]",
+	"dtc"     => "dtc [clear]",
+	"events"  => "events [flash]",
+	"latency" => "latency [clear]",
+	"reset"   => "reset",
+	_ => return Err(ParseError::Unknown),
+    };
+
+    let args = (words.next(), words.next(), words.next());
+    let command = match (name, args) {
+	("help", (None, ..))   => Some(Command::Help),
+	("status", (None, ..)) => Some(Command::Status),
+	("reset", (None, ..))  => Some(Command::Reset),
+	("gear", (Some(gear), None, _)) => Gear::from_name(gear).map(Command::Gear),
+	("led", (Some(n), Some(on), None)) => match (n.parse(), on) {
+	    (Ok(n), "on")  => Some(Command::Led(n, true)),
+	    (Ok(n), "off") => Some(Command::Led(n, false)),
+	    _ => None,
+	},
+	("log", (None, ..)) => Some(Command::Log(None)),
+	("log", (Some(level), None, _)) => LogLevel::from_name(level).map(|level| Command::Log(Some(level))),
+	("dtc", (None, ..)) => Some(Command::Dtcs),
+	("dtc", (Some("clear"), None, _)) => Some(Command::ClearDtcs),
+	("events", (None, ..)) => Some(Command::Events(false)),
+	("events", (Some("flash"), None, _)) => Some(Command::Events(true)),
+	("latency", (None, ..)) => Some(Command::Latency),
+	("latency", (Some("clear"), None, _)) => Some(Command::ClearLatency),
+	_ => None,
+    };
+    command.map(Some).ok_or(ParseError::Usage(usage))
+}
+
+/// Which events get printed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel { Off, Error, Warn, Info, Debug }
+
+impl LogLevel {
+    const ALL: [LogLevel; 5] = [LogLevel::Off, LogLevel::Error, LogLevel::Warn, LogLevel::Info, LogLevel::Debug];
+
+    pub fn name(self) -> &'static str {
+	match self {
+	    LogLevel::Off   => "off",
+	    LogLevel::Error => "error",
+	    LogLevel::Warn  => "warn",
+	    LogLevel::Info  => "info",
+	    LogLevel::Debug => "debug",
+	}
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+	Self::ALL.into_iter().find(|l| l.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// Things that happen, that the console might want to print.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    Button(Gear, bool),
+    GearChanged(Selection),
+    Fault(Fault),
+}
+
+impl Event {
+    pub fn level(&self) -> LogLevel {
+	match self {
+	    Event::Button(..)     => LogLevel::Debug,
+	    Event::GearChanged(_) => LogLevel::Info,
+	    Event::Fault(_)       => LogLevel::Warn,
+	}
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	match self {
+	    Event::Button(gear, pressed) => {
+		write!(f, "button {} {}", gear.name(), if *pressed { "pressed" } else { "released" })
+	    }
+	    Event::GearChanged(selection) => write!(f, "gear {}", DisplaySelection(selection)),
+	    Event::Fault(fault) => write!(f, "{:04X} {}", fault.code(), DisplayFault(*fault)),
+	}
+    }
+}
+
+/// `Display` for a `Fault`, without the code - "button D stuck" etc.
+pub struct DisplayFault(pub Fault);
+
+impl fmt::Display for DisplayFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	match self.0 {
+	    Fault::Rejected(rejection) => write!(f, "rejected: {}", rejection.reason()),
+	    Fault::NotConfirmed(gear) => write!(f, "{} not confirmed", gear.name()),
+	    Fault::WatchdogReset(task) => write!(f, "watchdog reset, {}", DisplayTask(task)),
+	    fault @ (Fault::SpawnFailed(s) | Fault::ChannelFull(s) | Fault::InitFailed(s)) => {
+		write!(f, "{}: {}", s.name(), fault.name())
+	    }
+	    Fault::StuckButton(gear) => write!(f, "button {} stuck", gear.name()),
+	    Fault::Panic => f.write_str("panicked before the last reset"),
+	    Fault::ShortedButtons(a, b) => write!(f, "buttons {} and {} shorted", a.name(), b.name()),
+	    Fault::OpenLed(gear) => write!(f, "LED {} open", gear.name()),
+	}
+    }
+}
+
+/// `Display` for the task that stopped - "led 2" etc.
+pub struct DisplayTask(pub Option<Task>);
+
+impl fmt::Display for DisplayTask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	match self.0 {
+	    None => f.write_str("unknown task"),
+	    Some(task) => match task.index() {
+		Some(n) => write!(f, "{} {}", task.name(), n),
+		None => f.write_str(task.name()),
+	    },
+	}
+    }
+}
+
+/// `Display` for a trouble code, with its freeze frame - "0601 button D
+/// stuck, 3 times, ..." etc.
+pub struct DisplayDtc<'a>(pub &'a Dtc);
+
+impl fmt::Display for DisplayDtc<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	let dtc = self.0;
+	match dtc.fault() {
+	    Some(fault) => write!(f, "{:04X} {}", dtc.code, DisplayFault(fault))?,
+	    None => write!(f, "{:04X} unknown", dtc.code)?,
+	}
+	write!(f, ", {} time{}, first at {}ms, last at {}ms (boot #{})", dtc.count, if dtc.count == 1 { "" } else { "s" },
+	       dtc.first, dtc.last, dtc.boot)?;
+
+	let frame = &dtc.frame;
+	write!(f, "\n     gear {}, buttons", frame.gear.map_or("-", Gear::name))?;
+	if frame.buttons == 0 {
+	    f.write_str(" -")?;
+	}
+	for gear in Gear::ALL.into_iter().filter(|g| frame.buttons & 1 << *g as u8 != 0) {
+	    write!(f, " {}", gear.name())?;
+	}
+	write!(f, ", up {}ms", frame.uptime)
+    }
+}
+
+/// `Display` for an event log entry - "boot #12 at 5310ms: released D after
+/// 120ms" etc.
+pub struct DisplayEntry<'a>(pub &'a LogEntry);
+
+impl fmt::Display for DisplayEntry<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	let entry = self.0;
+	write!(f, "boot #{} at {}ms: ", entry.boot, entry.at)?;
+	match entry.event {
+	    LogEvent::Press(gear)          => write!(f, "pressed {}", gear.name()),
+	    LogEvent::Release(gear, held)  => write!(f, "released {} after {}ms", gear.name(), held),
+	    LogEvent::LongHold(gear, held) => write!(f, "held {} for {}ms", gear.name(), held),
+	    LogEvent::GearChanged(s)       => write!(f, "gear {}", DisplaySelection(&s)),
+	    LogEvent::Rejected(rejection)  => write!(f, "rejected: {}", rejection.reason()),
+	    LogEvent::Fault(fault)         => write!(f, "{:04X} {}", fault.code(), DisplayFault(fault)),
+	}
+    }
+}
+
+/// `Display` for the latency of a button - "D: 12 changes, min 0.21ms, avg
+/// 0.35ms, max 1.20ms, p99 0.98ms".
+pub struct DisplayLatency(pub Gear, pub LatencyStats);
+
+impl fmt::Display for DisplayLatency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	let DisplayLatency(gear, stats) = self;
+	let ms = |us: u32| (us / 1000, us % 1000 / 10);
+	write!(f, "{}: ", gear.name())?;
+	if stats.count == 0 {
+	    return f.write_str("nothing yet");
+	}
+	write!(f, "{} change{}", stats.count, if stats.count == 1 { "" } else { "s" })?;
+	for (name, us) in [("min", stats.min), ("avg", stats.avg), ("max", stats.max), ("p99", stats.p99)] {
+	    let (ms, hundredths) = ms(us);
+	    write!(f, ", {} {}.{:02}ms", name, ms, hundredths)?;
+	}
+	Ok(())
+    }
+}
+
+/// `Display` for a `Selection` - "D (requested R, sport)" etc.
+pub struct DisplaySelection<'a>(pub &'a Selection);
+
+impl fmt::Display for DisplaySelection<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	let selection = self.0;
+	write!(f, "{} ({}", selection.gear.map_or("-", Gear::name), selection.mode.name())?;
+	if let Some(requested) = selection.requested {
+	    write!(f, ", requested {}", requested.name())?;
+	}
+	if selection.park_lock {
+	    f.write_str(", park lock")?;
+	}
+	f.write_str(")")
+    }
+}
+
+// ================================================================================
+
+/// What a key did to the line.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Key<'a> {
+    /// Added to the line - echo it.
+    Char(u8),
+
+    /// Backspace - erase the last character on the terminal.
+    Erase,
+
+    /// Enter - here's the line.
+    Line(&'a str),
+
+    /// Enter, but the line didn't fit - it's thrown away, rather than
+    /// running whatever the start of it happens to say.
+    TooLong,
+
+    /// Nothing happened (line full, not printable etc).
+    Ignored,
+}
+
+/// Collects typed characters into a line.
+#[derive(Debug)]
+pub struct LineBuffer<const N: usize> {
+    buf:      [u8; N],
+    len:      usize,
+    last_cr:  bool,
+    overflow: bool,
+}
+
+impl<const N: usize> Default for LineBuffer<N> {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+impl<const N: usize> LineBuffer<N> {
+    pub const fn new() -> Self {
+	Self { buf: [0; N], len: 0, last_cr: false, overflow: false }
+    }
+
+    pub fn push(&mut self, byte: u8) -> Key<'_> {
+	let last_cr = core::mem::replace(&mut self.last_cr, byte == b'\r');
+	match byte {
+	    // CR LF is one enter, not two.
+	    b'\n' if last_cr => Key::Ignored,
+	    b'\r' | b'\n' => {
+		let len = core::mem::take(&mut self.len);
+		if core::mem::take(&mut self.overflow) {
+		    return Key::TooLong;
+		}
+		// Only ASCII gets in, so this can't fail.
+		Key::Line(core::str::from_utf8(&self.buf[..len]).unwrap_or(""))
+	    }
+	    0x08 | 0x7F if self.len > 0 => {
+		self.len -= 1;
+		Key::Erase
+	    }
+	    b' '..=b'~' if self.len < N => {
+		self.buf[self.len] = byte;
+		self.len += 1;
+		Key::Char(byte)
+	    }
+	    b' '..=b'~' => {
+		self.overflow = true;
+		Key::Ignored
+	    }
+	    _ => Key::Ignored,
+	}
+    }
+}
+
+/// Formats into a fixed buffer. Anything that doesn't fit is dropped.
+#[derive(Debug)]
+pub struct Text<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> Default for Text<N> {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+impl<const N: usize> Text<N> {
+    pub const fn new() -> Self {
+	Self { buf: [0; N], len: 0 }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+	&self.buf[..self.len]
+    }
+
+    pub fn clear(&mut self) {
+	self.len = 0;
+    }
+
+    fn push(&mut self, byte: u8) -> fmt::Result {
+	let b = self.buf.get_mut(self.len).ok_or(fmt::Error)?;
+	*b = byte;
+	self.len += 1;
+	Ok(())
+    }
+}
+
+impl<const N: usize> Write for Text<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+	// Terminals want CR LF.
+	for byte in s.bytes() {
+	    if byte == b'\n' {
+		self.push(b'\r')?;
+	    }
+	    self.push(byte)?;
+	}
+	Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::string::String;
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::gear::Mode;
+
+    // Everything the buffer says about `bytes`, lines as `Line` keys.
+    fn keys<const N: usize>(bytes: &[u8]) -> Vec<Result<String, Key<'static>>> {
+	let mut line = LineBuffer::<N>::new();
+	bytes.iter().map(|b| match line.push(*b) {
+	    Key::Line(l) => Ok(String::from(l)),
+	    Key::Char(c) => Err(Key::Char(c)),
+	    Key::Erase => Err(Key::Erase),
+	    Key::TooLong => Err(Key::TooLong),
+	    Key::Ignored => Err(Key::Ignored),
+	}).collect()
+    }
+
+    fn lines<const N: usize>(bytes: &[u8]) -> Vec<Result<String, Key<'static>>> {
+	keys::<N>(bytes).into_iter().filter(|k| matches!(k, Ok(_) | Err(Key::TooLong))).collect()
+    }
+
+    #[test]
+    fn commands() {
+	for (line, command) in [
+	    ("help", Command::Help),
+	    ("status", Command::Status),
+	    ("gear D", Command::Gear(Gear::D)),
+	    ("gear r", Command::Gear(Gear::R)),
+	    ("gear 2", Command::Gear(Gear::Two)),
+	    ("led 0 on", Command::Led(0, true)),
+	    ("led 3 off", Command::Led(3, false)),
+	    ("log", Command::Log(None)),
+	    ("log debug", Command::Log(Some(LogLevel::Debug))),
+	    ("log OFF", Command::Log(Some(LogLevel::Off))),
+	    ("dtc", Command::Dtcs),
+	    ("dtc clear", Command::ClearDtcs),
+	    ("events", Command::Events(false)),
+	    ("events flash", Command::Events(true)),
+	    ("latency", Command::Latency),
+	    ("latency clear", Command::ClearLatency),
+	    ("reset", Command::Reset),
+	    ("  gear\t N  ", Command::Gear(Gear::N)),
+	] {
+	    assert_eq!(parse(line), Ok(Some(command)), "{:?}", line);
+	}
+    }
+
+    #[test]
+    fn empty() {
+	assert_eq!(parse(""), Ok(None));
+	assert_eq!(parse("   "), Ok(None));
+    }
+
+    #[test]
+    fn unknown() {
+	for line in ["gears D", "Help", "x", "reboot now"] {
+	    assert_eq!(parse(line), Err(ParseError::Unknown), "{:?}", line);
+	}
+    }
+
+    #[test]
+    fn bad_arguments() {
+	for (line, usage) in [
+	    ("help me", "help"),
+	    ("status now", "status"),
+	    ("reset 1", "reset"),
+	    ("gear", "gear <gear>"),
+	    ("gear X", "gear <gear>"),
+	    ("gear D R", "gear <gear>"),
+	    ("led", "led <n> <on|off>"),
+	    ("led 1", "led <n> <on|off>"),
+	    ("led x on", "led <n> <on|off>"),
+	    ("led -1 on", "led <n> <on|off>"),
+	    ("led 1 dim", "led <n> <on|off>"),
+	    ("led 1 on off", "led <n> <on|off>"),
+	    ("log loud", "log [off|error|warn|info|debug]"),
+	    ("dtc erase", "dtc [clear]"),
+	    ("events ram", "events [flash]"),
+	    ("latency clear now", "latency [clear]"),
+	] {
+	    assert_eq!(parse(line), Err(ParseError::Usage(usage)), "{:?}", line);
+	}
+    }
+
+    #[test]
+    fn errors() {
+	let mut text = Text::<64>::new();
+	write!(text, "{}", ParseError::Usage("gear <gear>")).unwrap();
+	assert_eq!(text.as_bytes(), b"usage: gear <gear>");
+    }
+
+    #[test]
+    fn line_endings() {
+	// CR, LF and CR LF are all one enter.
+	assert_eq!(lines::<16>(b"a\rb\nc\r\nd\n"), [Ok("a".into()), Ok("b".into()), Ok("c".into()), Ok("d".into())]);
+	assert_eq!(lines::<16>(b"\r\n\r\n"), [Ok("".into()), Ok("".into())]);
+    }
+
+    #[test]
+    fn editing() {
+	assert_eq!(lines::<16>(b"gear X\x08D\r"), [Ok("gear D".into())]);
+	assert_eq!(lines::<16>(b"ab\x7f\x7f\x7fc\r"), [Ok("c".into())]);
+
+	// Backspace on an empty line, and control characters.
+	let keys = keys::<16>(b"\x08\x1b\t");
+	assert_eq!(keys, [Err(Key::Ignored), Err(Key::Ignored), Err(Key::Ignored)]);
+    }
+
+    #[test]
+    fn overlong() {
+	// Thrown away, not cut short - "gear D" and then some isn't "gear D".
+	assert_eq!(lines::<8>(b"gear D please\r"), [Err(Key::TooLong)]);
+
+	// Fits exactly, and the next line is fine again.
+	assert_eq!(lines::<8>(b"gear D12\rgear D\r"), [Ok("gear D12".into()), Ok("gear D".into())]);
+	assert_eq!(lines::<8>(b"gear D123\rgear D\r"), [Err(Key::TooLong), Ok("gear D".into())]);
+    }
+
+    #[test]
+    fn text() {
+	let mut text = Text::<8>::new();
+	text.write_str("a\nb").unwrap();
+	assert_eq!(text.as_bytes(), b"a\r\nb");
+
+	// Whatever doesn't fit is dropped.
+	assert!(text.write_str("cdefgh").is_err());
+	assert_eq!(text.as_bytes(), b"a\r\nbcdef");
+	text.clear();
+	assert_eq!(text.as_bytes(), b"");
+    }
+
+    #[test]
+    fn display() {
+	let mut text = Text::<128>::new();
+	let selection = Selection { gear: Some(Gear::D), requested: Some(Gear::R), mode: Mode::Sport, park_lock: false };
+	write!(text, "{}", Event::GearChanged(selection)).unwrap();
+	assert_eq!(text.as_bytes(), b"gear D (sport, requested R)");
+
+	text.clear();
+	write!(text, "{}", Event::Fault(Fault::StuckButton(Gear::D))).unwrap();
+	assert_eq!(text.as_bytes(), b"0603 button D stuck");
+
+	text.clear();
+	let stats = LatencyStats { count: 1, min: 210, avg: 350, max: 1200, p99: 980 };
+	write!(text, "{}", DisplayLatency(Gear::D, stats)).unwrap();
+	assert_eq!(text.as_bytes(), b"D: 1 change, min 0.21ms, avg 0.35ms, max 1.20ms, p99 0.98ms");
+    }
+}
@@ -0,0 +1,16 @@
+//! Checksums.
+
+/// CRC-32 (IEEE 802.3, the one zlib uses).
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+	crc ^= *byte as u32;
+	for _ in 0..8 {
+	    crc = match crc & 1 {
+		1 => (crc >> 1) ^ 0xEDB8_8320,
+		_ => crc >> 1,
+	    };
+	}
+    }
+    !crc
+}
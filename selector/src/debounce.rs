@@ -0,0 +1,295 @@
+//! Button debouncing.
+//!
+//! Like the gesture recognizer, the debouncer is fed the raw level of the
+//! input with a timestamp (in ms) - on every edge, and whenever
+//! `deadline()` have passed - and says when the button is pressed or
+//! released. How it decides depends on the algorithm:
+//!
+//! - `Integrator` counts up for every sample that differs from the
+//!   debounced level and down for every one that doesn't, and changes
+//!   when the count gets high enough. Rides through the odd bounce.
+//! - `Consecutive` needs that many samples in a row of the new level, any
+//!   other one starts it over.
+//! - `Window` needs the input to stay at the new level for that long, no
+//!   sampling.
+//!
+//! Pressing and releasing have separate timings, contacts often bounce
+//! more one way than the other. Whenever the input went away from the
+//! debounced level and came back without changing it, that's a glitch -
+//! they're counted, a button with lots of them is on its way out.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Algorithm { Integrator, Consecutive, Window }
+
+impl Algorithm {
+    pub const ALL: [Algorithm; 3] = [Algorithm::Integrator, Algorithm::Consecutive, Algorithm::Window];
+
+    pub fn name(self) -> &'static str {
+	match self {
+	    Algorithm::Integrator  => "integrator",
+	    Algorithm::Consecutive => "consecutive",
+	    Algorithm::Window      => "window",
+	}
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DebounceConfig {
+    pub algorithm: Algorithm,
+
+    /// How long it takes to go pressed, and released. For the sampling
+    /// algorithms that's this many ms worth of samples.
+    pub press_ms:   u64,
+    pub release_ms: u64,
+
+    /// Time between samples, for `Integrator` and `Consecutive`.
+    pub sample_ms:  u64,
+}
+
+pub const DEFAULT_DEBOUNCE: DebounceConfig = DebounceConfig {
+    algorithm:  Algorithm::Window,
+    press_ms:   20,
+    release_ms: 20,
+    sample_ms:  1,
+};
+
+#[derive(Debug)]
+pub struct Debouncer {
+    config:  DebounceConfig,
+    pressed: bool,
+
+    // The last raw level, and when it changed to it.
+    raw:   bool,
+    since: u64,
+
+    // Samples towards changing, and when to take the next one. `None` when
+    // there's nothing going on.
+    count: u64,
+    next:  Option<u64>,
+
+    glitches: u32,
+}
+
+impl Debouncer {
+    /// Starting out `pressed` (or not).
+    pub const fn new(config: DebounceConfig, pressed: bool) -> Self {
+	Self { config, pressed, raw: pressed, since: 0, count: 0, next: None, glitches: 0 }
+    }
+
+    pub fn pressed(&self) -> bool {
+	self.pressed
+    }
+
+    /// How many glitches there have been.
+    pub fn glitches(&self) -> u32 {
+	self.glitches
+    }
+
+    /// When `update()` should be called next, even if the input doesn't
+    /// change. `None` if it's settled.
+    pub fn deadline(&self) -> Option<u64> {
+	match self.config.algorithm {
+	    Algorithm::Window => (self.raw != self.pressed).then(|| self.since + self.time()),
+	    _ => self.next,
+	}
+    }
+
+    /// The input is at `raw` (true = pressed) at `now`. Returns the new
+    /// debounced level, if it changed.
+    pub fn update(&mut self, raw: bool, now: u64) -> Option<bool> {
+	let edge = raw != self.raw;
+	if edge {
+	    self.raw = raw;
+	    self.since = now;
+	}
+	match self.config.algorithm {
+	    Algorithm::Window => self.window(edge, now),
+	    _ if self.next.is_none_or(|next| now >= next) => self.sample(now),
+	    _ => None,
+	}
+    }
+
+    fn window(&mut self, edge: bool, now: u64) -> Option<bool> {
+	if self.raw == self.pressed {
+	    // Back before the time was up.
+	    if edge {
+		self.glitches += 1;
+	    }
+	    return None;
+	}
+	if now - self.since < self.time() {
+	    return None;
+	}
+	self.pressed = self.raw;
+	Some(self.pressed)
+    }
+
+    fn sample(&mut self, now: u64) -> Option<bool> {
+	let busy = self.count > 0;
+	self.count = match (self.config.algorithm, self.raw == self.pressed) {
+	    (_, false)                    => self.count + 1,
+	    (Algorithm::Integrator, true) => self.count.saturating_sub(1),
+	    (_, true)                     => 0,
+	};
+
+	let changed = self.count >= self.samples();
+	if changed {
+	    self.pressed = self.raw;
+	    self.count = 0;
+	} else if busy && self.count == 0 {
+	    self.glitches += 1;
+	}
+
+	// Keep sampling until there's nothing to count.
+	self.next = (self.count > 0 || self.raw != self.pressed).then_some(now + self.config.sample_ms);
+	changed.then_some(self.pressed)
+    }
+
+    // How long (or how many samples) to change the way we're not.
+    fn time(&self) -> u64 {
+	match self.pressed {
+	    false => self.config.press_ms,
+	    true  => self.config.release_ms,
+	}
+    }
+
+    fn samples(&self) -> u64 {
+	(self.time() / self.config.sample_ms.max(1)).max(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::vec::Vec;
+
+    use super::*;
+
+    fn config(algorithm: Algorithm, press_ms: u64, release_ms: u64) -> DebounceConfig {
+	DebounceConfig { algorithm, press_ms, release_ms, sample_ms: 1 }
+    }
+
+    // Like `read_button` - update on every edge, and whenever the deadline
+    // have passed. The presses and releases, with when they happened.
+    fn run(debouncer: &mut Debouncer, edges: &[(u64, bool)]) -> Vec<(u64, bool)> {
+	let mut edges = edges.iter().peekable();
+	let mut level = debouncer.pressed();
+	let mut events = Vec::new();
+	loop {
+	    let now = match (edges.peek(), debouncer.deadline()) {
+		(Some((at, _)), Some(deadline)) if deadline < *at => deadline,
+		(Some((at, new)), _) => {
+		    level = *new;
+		    edges.next();
+		    *at
+		}
+		(None, Some(deadline)) => deadline,
+		(None, None) => break,
+	    };
+	    if let Some(pressed) = debouncer.update(level, now) {
+		events.push((now, pressed));
+	    }
+	}
+	events
+    }
+
+    fn debounce(config: DebounceConfig, edges: &[(u64, bool)]) -> (Vec<(u64, bool)>, u32) {
+	let mut debouncer = Debouncer::new(config, false);
+	let events = run(&mut debouncer, edges);
+	assert_eq!(debouncer.deadline(), None);
+	(events, debouncer.glitches())
+    }
+
+    // Pressed at 100 with a 1ms dropout, released at 200.
+    const DROPOUT: &[(u64, bool)] = &[(100, true), (102, false), (103, true), (200, false)];
+
+    // Pressed for less than the debounce time.
+    const SPIKE: &[(u64, bool)] = &[(100, true), (103, false)];
+
+    #[test]
+    fn clean() {
+	// The sampling ones count the sample at the edge, so it's five samples
+	// 4ms apart.
+	for (algorithm, events) in [
+	    (Algorithm::Integrator,  [(104, true), (209, false)]),
+	    (Algorithm::Consecutive, [(104, true), (209, false)]),
+	    (Algorithm::Window,      [(105, true), (210, false)]),
+	] {
+	    let (actual, glitches) = debounce(config(algorithm, 5, 10), &[(100, true), (200, false)]);
+	    assert_eq!(actual, events, "{}", algorithm.name());
+	    assert_eq!(glitches, 0);
+	}
+    }
+
+    #[test]
+    fn starts_pressed() {
+	for (algorithm, release) in [(Algorithm::Integrator, 109), (Algorithm::Consecutive, 109), (Algorithm::Window, 110)] {
+	    let mut debouncer = Debouncer::new(config(algorithm, 5, 10), true);
+	    assert!(debouncer.pressed());
+	    assert_eq!(debouncer.deadline(), None);
+	    assert_eq!(run(&mut debouncer, &[(100, false)]), [(release, false)], "{}", algorithm.name());
+	}
+    }
+
+    #[test]
+    fn window() {
+	// From after the dropout.
+	assert_eq!(debounce(config(Algorithm::Window, 5, 5), DROPOUT), ([(108, true), (205, false)].into(), 1));
+	assert_eq!(debounce(config(Algorithm::Window, 5, 5), SPIKE), ([].into(), 1));
+    }
+
+    #[test]
+    fn window_deadline() {
+	let mut debouncer = Debouncer::new(config(Algorithm::Window, 5, 5), false);
+	assert_eq!(debouncer.update(true, 100), None);
+	assert_eq!(debouncer.deadline(), Some(105));
+	assert_eq!(debouncer.update(true, 104), None);
+	assert_eq!(debouncer.update(true, 105), Some(true));
+	assert_eq!(debouncer.deadline(), None);
+    }
+
+    #[test]
+    fn consecutive() {
+	// The dropout starts the count over.
+	assert_eq!(debounce(config(Algorithm::Consecutive, 5, 5), DROPOUT), ([(107, true), (204, false)].into(), 1));
+	assert_eq!(debounce(config(Algorithm::Consecutive, 5, 5), SPIKE), ([].into(), 1));
+    }
+
+    #[test]
+    fn integrator() {
+	// Rides through the dropout, it only takes a sample off the count.
+	assert_eq!(debounce(config(Algorithm::Integrator, 5, 5), DROPOUT), ([(106, true), (204, false)].into(), 0));
+
+	// Counts back down to nothing.
+	assert_eq!(debounce(config(Algorithm::Integrator, 5, 5), SPIKE), ([].into(), 1));
+    }
+
+    #[test]
+    fn deadline() {
+	let mut debouncer = Debouncer::new(config(Algorithm::Integrator, 5, 5), false);
+	assert_eq!(debouncer.update(true, 100), None);
+	assert_eq!(debouncer.deadline(), Some(101));
+
+	// Not sampled before it's time.
+	assert_eq!(debouncer.update(true, 100), None);
+	assert_eq!(debouncer.deadline(), Some(101));
+	for now in 101..104 {
+	    assert_eq!(debouncer.update(true, now), None);
+	}
+	assert_eq!(debouncer.update(true, 104), Some(true));
+	assert_eq!(debouncer.deadline(), None);
+    }
+
+    #[test]
+    fn sample_time() {
+	// 20ms at 4ms a sample is 5 samples.
+	let config = DebounceConfig { algorithm: Algorithm::Consecutive, press_ms: 20, release_ms: 20, sample_ms: 4 };
+	let (events, _) = debounce(config, &[(100, true), (200, false)]);
+	assert_eq!(events, [(116, true), (216, false)]);
+
+	// At least one sample, whatever the timings.
+	let config = DebounceConfig { algorithm: Algorithm::Integrator, press_ms: 0, release_ms: 0, sample_ms: 0 };
+	let (events, _) = debounce(config, &[(100, true), (200, false)]);
+	assert_eq!(events, [(100, true), (200, false)]);
+    }
+}
@@ -0,0 +1,298 @@
+//! Wiring diagnostics - stuck buttons, shorted buttons and open LEDs.
+//!
+//! A button held for longer than anyone would is stuck (or shorted to
+//! ground), and two buttons going down at the very same time are shorted
+//! to each other. Either way they're ignored until released, so they can't
+//! select anything. An LED is open if it's on, but its sense input doesn't
+//! see any current - for `open_ms` of on-time, however it's blinking or
+//! fading. All times are in ms.
+
+use crate::fault::Fault;
+use crate::gear::Gear;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DiagConfig {
+    /// A button held this long is stuck.
+    pub stuck_ms: u64,
+
+    /// Two buttons pressed within this long of each other are shorted. A
+    /// person can't do it this fast, even trying.
+    pub short_ms: u64,
+
+    /// An LED on without current for this long, added up over however
+    /// many times it's been on, is open.
+    pub open_ms: u64,
+
+    /// Only check LEDs at least this bright - the sense input is filtered,
+    /// and won't see a dim PWM output.
+    pub open_level: u8,
+
+    /// How often to read the sense input, while the LED is on.
+    pub sample_ms: u64,
+}
+
+pub const DEFAULT_DIAG: DiagConfig = DiagConfig {
+    stuck_ms:   10_000,
+    short_ms:   10,
+    open_ms:    1000,
+    open_level: 128,
+    sample_ms:  50,
+};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+struct Button {
+    pressed: Option<u64>,
+    ignored: bool,
+}
+
+/// The state of every button, shared between the button readers.
+#[derive(Debug)]
+pub struct ButtonCheck {
+    config:  DiagConfig,
+    buttons: [Button; Gear::COUNT],
+}
+
+impl ButtonCheck {
+    pub const fn new(config: DiagConfig) -> Self {
+	Self { config, buttons: [Button { pressed: None, ignored: false }; Gear::COUNT] }
+    }
+
+    /// `gear`'s button was pressed at `now`. If another one went down at
+    /// the same time, they're both ignored and that's the fault.
+    pub fn press(&mut self, gear: Gear, now: u64) -> Option<Fault> {
+	self.buttons[gear as usize].pressed = Some(now);
+
+	let other = Gear::ALL.into_iter().find(|g| {
+	    *g != gear && self.buttons[*g as usize].pressed.is_some_and(|at| now.abs_diff(at) <= self.config.short_ms)
+	})?;
+	self.buttons[gear as usize].ignored = true;
+	self.buttons[other as usize].ignored = true;
+	Some(Fault::ShortedButtons(other, gear))
+    }
+
+    /// `gear`'s button was released, so it's not ignored any more.
+    pub fn release(&mut self, gear: Gear) {
+	self.buttons[gear as usize] = Button::default();
+    }
+
+    /// When `poll()` should be called next, if at all.
+    pub fn deadline(&self, gear: Gear) -> Option<u64> {
+	match self.buttons[gear as usize] {
+	    Button { pressed: Some(at), ignored: false } => Some(at + self.config.stuck_ms),
+	    _ => None,
+	}
+    }
+
+    /// Time have passed - is `gear`'s button stuck now? Only reported once,
+    /// after that it's ignored.
+    pub fn poll(&mut self, gear: Gear, now: u64) -> Option<Fault> {
+	if now < self.deadline(gear)? {
+	    return None;
+	}
+	self.buttons[gear as usize].ignored = true;
+	Some(Fault::StuckButton(gear))
+    }
+
+    /// Bit `gear as u8` set for every button that's pressed, ignored or not.
+    pub fn pressed(&self) -> u16 {
+	Gear::ALL.into_iter().filter(|g| self.buttons[*g as usize].pressed.is_some()).fold(0, |b, g| b | 1 << g as u8)
+    }
+
+    /// Should presses and releases of `gear`'s button be ignored?
+    pub fn ignored(&self, gear: Gear) -> bool {
+	self.buttons[gear as usize].ignored
+    }
+}
+
+// ================================================================================
+
+/// Open load check of one LED, told what it's set to and fed with what its
+/// sense input says.
+#[derive(Debug)]
+pub struct LedCheck {
+    config: DiagConfig,
+    level:  u8,
+    since:  u64,
+
+    // On since the last sample, and on without current since there last
+    // was any.
+    on:     u64,
+    dark:   u64,
+    open:   bool,
+}
+
+impl LedCheck {
+    pub const fn new(config: DiagConfig, now: u64) -> Self {
+	Self { config, level: 0, since: now, on: 0, dark: 0, open: false }
+    }
+
+    /// The LED was set to `level` at `now`.
+    pub fn set(&mut self, level: u8, now: u64) {
+	self.count(now);
+	self.level = level;
+    }
+
+    /// When to `sample()` next, after one at `last` - `None` while the LED
+    /// is too dim to check.
+    pub fn next_sample(&self, last: u64) -> Option<u64> {
+	match self.level >= self.config.open_level {
+	    true  => Some(last + self.config.sample_ms),
+	    false => None,
+	}
+    }
+
+    /// The sense input does (or doesn't) see `current` at `now`. Returns
+    /// true when the LED is found to be open, once - until it sees current
+    /// again. A sample while the LED is off (or dim) proves nothing either
+    /// way, the on-time up to it is counted at the next one.
+    pub fn sample(&mut self, current: bool, now: u64) -> bool {
+	self.count(now);
+	if current {
+	    self.on = 0;
+	    self.dark = 0;
+	    self.open = false;
+	    return false;
+	}
+	if self.level < self.config.open_level {
+	    return false;
+	}
+	self.dark += core::mem::take(&mut self.on);
+	if self.open || self.dark < self.config.open_ms {
+	    return false;
+	}
+	self.open = true;
+	true
+    }
+
+    fn count(&mut self, now: u64) {
+	if self.level >= self.config.open_level {
+	    self.on += now.saturating_sub(self.since);
+	}
+	self.since = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shorted() {
+	let mut check = ButtonCheck::new(DEFAULT_DIAG);
+	assert_eq!(check.press(Gear::P, 1000), None);
+	assert_eq!(check.press(Gear::D, 1010), Some(Fault::ShortedButtons(Gear::P, Gear::D)));
+	assert!(check.ignored(Gear::P) && check.ignored(Gear::D));
+	assert_eq!(check.pressed(), 1 << Gear::P as u8 | 1 << Gear::D as u8);
+
+	// Ignored until released - no stuck on top of it.
+	assert_eq!(check.deadline(Gear::P), None);
+	assert_eq!(check.poll(Gear::P, 100_000), None);
+	check.release(Gear::P);
+	assert!(!check.ignored(Gear::P));
+	assert!(check.ignored(Gear::D));
+	check.release(Gear::D);
+	assert_eq!(check.pressed(), 0);
+    }
+
+    #[test]
+    fn not_shorted() {
+	// Just over `short_ms` apart is a person.
+	let mut check = ButtonCheck::new(DEFAULT_DIAG);
+	assert_eq!(check.press(Gear::P, 1000), None);
+	assert_eq!(check.press(Gear::D, 1011), None);
+	assert!(!check.ignored(Gear::P) && !check.ignored(Gear::D));
+
+	// Released ones don't count either.
+	check.release(Gear::P);
+	check.release(Gear::D);
+	assert_eq!(check.press(Gear::R, 1011), None);
+    }
+
+    #[test]
+    fn stuck() {
+	let mut check = ButtonCheck::new(DEFAULT_DIAG);
+	check.press(Gear::N, 500);
+	assert_eq!(check.deadline(Gear::N), Some(10_500));
+	assert_eq!(check.poll(Gear::N, 10_499), None);
+	assert!(!check.ignored(Gear::N));
+	assert_eq!(check.poll(Gear::N, 10_500), Some(Fault::StuckButton(Gear::N)));
+
+	// Only once, and ignored from then on.
+	assert!(check.ignored(Gear::N));
+	assert_eq!(check.deadline(Gear::N), None);
+	assert_eq!(check.poll(Gear::N, 20_000), None);
+
+	// Until it's let go, and then it's a button like any other.
+	check.release(Gear::N);
+	assert!(!check.ignored(Gear::N));
+	assert_eq!(check.poll(Gear::N, 30_000), None);
+	check.press(Gear::N, 30_000);
+	assert_eq!(check.deadline(Gear::N), Some(40_000));
+    }
+
+    // Samples every `sample_ms` from `from` to `to`, returns when it was
+    // found open.
+    fn run(check: &mut LedCheck, current: bool, from: u64, to: u64) -> Option<u64> {
+	let mut found = None;
+	for now in (from..=to).step_by(DEFAULT_DIAG.sample_ms as usize) {
+	    if check.sample(current, now) {
+		assert!(found.is_none(), "reported twice");
+		found = Some(now);
+	    }
+	}
+	found
+    }
+
+    #[test]
+    fn open() {
+	let mut check = LedCheck::new(DEFAULT_DIAG, 0);
+	check.set(255, 0);
+	assert_eq!(check.next_sample(0), Some(50));
+	assert_eq!(run(&mut check, false, 50, 5000), Some(1000));
+    }
+
+    #[test]
+    fn current() {
+	// Any current starts it over, and clears an open one.
+	let mut check = LedCheck::new(DEFAULT_DIAG, 0);
+	check.set(255, 0);
+	assert_eq!(run(&mut check, false, 50, 900), None);
+	assert!(!check.sample(true, 950));
+	assert_eq!(run(&mut check, false, 1000, 1900), None);
+	assert_eq!(run(&mut check, false, 1950, 3000), Some(1950));
+	assert!(!check.sample(true, 3050));
+	assert_eq!(run(&mut check, false, 3100, 5000), Some(4050));
+    }
+
+    #[test]
+    fn blinking() {
+	// On and off every 250ms, never any current - the second of on-time is
+	// up at 1750, when it's off, so it's found on the next sample.
+	let mut check = LedCheck::new(DEFAULT_DIAG, 0);
+	let mut found = None;
+	for phase in 0..20u64 {
+	    let start = phase * 250;
+	    check.set(if phase % 2 == 0 { 255 } else { 0 }, start);
+	    for now in (start..start + 250).step_by(50).skip(1) {
+		if check.sample(false, now) {
+		    assert!(found.is_none(), "reported twice");
+		    found = Some(now);
+		}
+	    }
+	}
+	assert_eq!(found, Some(2050));
+    }
+
+    #[test]
+    fn dim() {
+	// Too dim for the sense input to see - never open, and not sampled.
+	let mut check = LedCheck::new(DEFAULT_DIAG, 0);
+	check.set(DEFAULT_DIAG.open_level - 1, 0);
+	assert_eq!(check.next_sample(0), None);
+	assert_eq!(run(&mut check, false, 50, 10_000), None);
+
+	// Time spent dim doesn't count once it's bright.
+	check.set(DEFAULT_DIAG.open_level, 10_000);
+	assert_eq!(run(&mut check, false, 10_050, 20_000), Some(11_000));
+    }
+}
@@ -0,0 +1,185 @@
+//! Global brightness of the gear LEDs.
+//!
+//! The dashboard dimmer sets an overall level, which scales both the
+//! brightness of the selected gear and the backlight of the others. Level
+//! changes are ramped rather than jumped to. All times are in ms.
+
+// How often to update while ramping.
+const RAMP_STEP_MS: u64 = 20;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DimmerConfig {
+    /// Selected gear, at full dimmer level.
+    pub selected: u8,
+
+    /// The other gears, at full dimmer level.
+    pub unselected: u8,
+
+    /// How long a ramp from 0 to 255 (or back) takes.
+    pub ramp_ms: u64,
+}
+
+pub const DEFAULT_DIMMER: DimmerConfig = DimmerConfig {
+    selected:   255,
+    unselected: 24,
+    ramp_ms:    500,
+};
+
+#[derive(Debug)]
+pub struct Dimmer {
+    config: DimmerConfig,
+    from:   u8,
+    to:     u8,
+    start:  u64,
+}
+
+impl Dimmer {
+    pub const fn new(config: DimmerConfig, level: u8) -> Self {
+	Self { config, from: level, to: level, start: 0 }
+    }
+
+    /// Where we're ramping to (or are).
+    pub fn target(&self) -> u8 {
+	self.to
+    }
+
+    /// Start ramping from wherever we are to `level`.
+    pub fn set(&mut self, level: u8, now: u64) {
+	self.from = self.level(now);
+	self.to = level;
+	self.start = now;
+    }
+
+    /// Overall level at `now`.
+    pub fn level(&self, now: u64) -> u8 {
+	let ms = self.ramp_len();
+	let t = now - self.start;
+	if t >= ms {
+	    return self.to;
+	}
+	(self.from as i64 + (self.to as i64 - self.from as i64) * t as i64 / ms as i64) as u8
+    }
+
+    /// When the level changes next, `None` if we're not ramping.
+    pub fn next_update(&self, now: u64) -> Option<u64> {
+	let end = self.start + self.ramp_len();
+	match now < end {
+	    true  => Some((now + RAMP_STEP_MS).min(end)),
+	    false => None,
+	}
+    }
+
+    /// Brightness of the selected and of the unselected gears at `now`.
+    pub fn brightness(&self, now: u64) -> (u8, u8) {
+	let level = self.level(now) as u16;
+	let scale = |b: u8| (b as u16 * level / 255) as u8;
+	(scale(self.config.selected), scale(self.config.unselected))
+    }
+
+    fn ramp_len(&self) -> u64 {
+	self.config.ramp_ms * self.from.abs_diff(self.to) as u64 / 255
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::vec::Vec;
+
+    use super::*;
+
+    // The level at every update from `now` until the ramp is over.
+    fn ramp(dimmer: &Dimmer, mut now: u64) -> Vec<(u64, u8)> {
+	let mut levels = Vec::new();
+	while let Some(next) = dimmer.next_update(now) {
+	    now = next;
+	    levels.push((now, dimmer.level(now)));
+	}
+	levels
+    }
+
+    #[test]
+    fn steady() {
+	let dimmer = Dimmer::new(DEFAULT_DIMMER, 128);
+	assert_eq!(dimmer.level(0), 128);
+	assert_eq!(dimmer.level(1_000_000), 128);
+	assert_eq!(dimmer.next_update(0), None);
+    }
+
+    #[test]
+    fn ramp_up() {
+	let mut dimmer = Dimmer::new(DEFAULT_DIMMER, 0);
+	dimmer.set(255, 1000);
+	assert_eq!(dimmer.target(), 255);
+	assert_eq!(dimmer.level(1000), 0);
+	assert_eq!(dimmer.level(1250), 127);
+
+	// Every `RAMP_STEP_MS`, always up, and exactly there at the end.
+	let levels = ramp(&dimmer, 1000);
+	assert_eq!(levels.len(), 25);
+	assert!(levels.windows(2).all(|w| w[1].0 - w[0].0 == RAMP_STEP_MS && w[1].1 > w[0].1));
+	assert_eq!(levels.last(), Some(&(1500, 255)));
+	assert_eq!(dimmer.level(5000), 255);
+    }
+
+    #[test]
+    fn ramp_down() {
+	let mut dimmer = Dimmer::new(DEFAULT_DIMMER, 255);
+	dimmer.set(0, 0);
+	let levels = ramp(&dimmer, 0);
+	assert!(levels.windows(2).all(|w| w[1].1 < w[0].1));
+	assert_eq!(levels.last(), Some(&(500, 0)));
+	assert_eq!(dimmer.next_update(500), None);
+    }
+
+    #[test]
+    fn partial_ramp() {
+	// Half way takes half as long, the last step is short.
+	let mut dimmer = Dimmer::new(DEFAULT_DIMMER, 100);
+	dimmer.set(200, 0);
+	assert_eq!(dimmer.next_update(180), Some(196));
+	assert_eq!(dimmer.level(196), 200);
+
+	// Changing direction half way starts from where it got to.
+	let mut dimmer = Dimmer::new(DEFAULT_DIMMER, 0);
+	dimmer.set(255, 0);
+	dimmer.set(0, 250);
+	assert_eq!(dimmer.level(250), 127);
+	assert_eq!(ramp(&dimmer, 250).last(), Some(&(499, 0)));
+    }
+
+    #[test]
+    fn limits() {
+	// No ramp at all - straight there, nothing to update.
+	let mut dimmer = Dimmer::new(DimmerConfig { ramp_ms: 0, ..DEFAULT_DIMMER }, 0);
+	dimmer.set(255, 100);
+	assert_eq!(dimmer.level(100), 255);
+	assert_eq!(dimmer.next_update(100), None);
+
+	// A ramp much longer than a step still steps every `RAMP_STEP_MS`.
+	let mut dimmer = Dimmer::new(DimmerConfig { ramp_ms: 60_000, ..DEFAULT_DIMMER }, 0);
+	dimmer.set(255, 0);
+	assert_eq!(dimmer.next_update(0), Some(RAMP_STEP_MS));
+	assert_eq!(dimmer.level(30_000), 127);
+	assert_eq!(dimmer.next_update(59_990), Some(60_000));
+	assert_eq!(dimmer.level(60_000), 255);
+
+	// Setting where it already is isn't a ramp.
+	let mut dimmer = Dimmer::new(DEFAULT_DIMMER, 42);
+	dimmer.set(42, 0);
+	assert_eq!(dimmer.next_update(0), None);
+    }
+
+    #[test]
+    fn brightness() {
+	// Full level is the config as is, zero is dark, in between scales
+	// both the same.
+	let config = DimmerConfig { selected: 200, unselected: 40, ramp_ms: 0 };
+	assert_eq!(Dimmer::new(config, 255).brightness(0), (200, 40));
+	assert_eq!(Dimmer::new(config, 0).brightness(0), (0, 0));
+	assert_eq!(Dimmer::new(config, 128).brightness(0), (100, 20));
+
+	// Full config at full level can't overflow.
+	assert_eq!(Dimmer::new(DimmerConfig { selected: 255, unselected: 255, ramp_ms: 0 }, 255).brightness(0), (255, 255));
+    }
+}
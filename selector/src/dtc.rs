@@ -0,0 +1,483 @@
+//! Diagnostic trouble codes - the faults we've had, kept across resets.
+//!
+//! Every fault code is only in the store once, with how many times it
+//! happened, the first and last time (uptime in ms, of whichever boot it
+//! was) and a freeze frame of what things looked like the first time. A
+//! code that haven't come back for `aging` boots is dropped. When the store
+//! is full, the code that was last seen the longest ago makes room.
+//!
+//! The store is kept in flash by `DtcArea`, as one image in either of two
+//! sectors - written to the other one each time, so there's always a good
+//! one left if the power goes while writing.
+
+use core::cmp::Reverse;
+
+use crate::crc::crc32;
+use crate::fault::Fault;
+use crate::gear::Gear;
+use crate::storage::Flash;
+
+/// How many codes there's room for.
+pub const MAX_DTCS: usize = 16;
+
+/// Drop codes that haven't come back for this many boots.
+pub const DEFAULT_AGING: u32 = 40;
+
+/// What things looked like when a fault happened.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FreezeFrame {
+    pub gear: Option<Gear>,
+
+    /// Bit `gear as u8` set for every button that was pressed.
+    pub buttons: u16,
+
+    pub uptime: u64,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Dtc {
+    /// `Fault::code()`.
+    pub code:  u16,
+    pub count: u16,
+    pub first: u64,
+    pub last:  u64,
+
+    /// The boot it was last seen in.
+    pub boot:  u32,
+    pub frame: FreezeFrame,
+}
+
+impl Dtc {
+    pub fn fault(&self) -> Option<Fault> {
+	Fault::from_code(self.code)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DtcStore {
+    dtcs:  [Option<Dtc>; MAX_DTCS],
+    aging: u32,
+    boot:  u32,
+}
+
+impl DtcStore {
+    pub const fn new(aging: u32) -> Self {
+	Self { dtcs: [None; MAX_DTCS], aging, boot: 0 }
+    }
+
+    /// We've booted (this is boot number `boot`), drop the codes that are
+    /// too old. Returns true if any were dropped.
+    pub fn start(&mut self, boot: u32) -> bool {
+	self.boot = boot;
+
+	let mut dropped = false;
+	for slot in &mut self.dtcs {
+	    if slot.is_some_and(|dtc| boot.wrapping_sub(dtc.boot) >= self.aging) {
+		*slot = None;
+		dropped = true;
+	    }
+	}
+	dropped
+    }
+
+    /// `fault` happened at `now`.
+    pub fn record(&mut self, fault: Fault, now: u64, frame: FreezeFrame) {
+	let code = fault.code();
+	if let Some(dtc) = self.dtcs.iter_mut().flatten().find(|dtc| dtc.code == code) {
+	    dtc.count = dtc.count.saturating_add(1);
+	    dtc.last = now;
+	    dtc.boot = self.boot;
+	    return;
+	}
+
+	let dtc = Dtc { code, count: 1, first: now, last: now, boot: self.boot, frame };
+	let slot = match self.dtcs.iter().position(Option::is_none) {
+	    Some(slot) => slot,
+	    None => self.oldest(),
+	};
+	self.dtcs[slot] = Some(dtc);
+    }
+
+    pub fn clear(&mut self) {
+	self.dtcs = [None; MAX_DTCS];
+    }
+
+    pub fn len(&self) -> usize {
+	self.dtcs.iter().flatten().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+	self.len() == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Dtc> {
+	self.dtcs.iter().flatten()
+    }
+
+    // Last seen the longest ago - in the oldest boot, and the earliest in it.
+    fn oldest(&self) -> usize {
+	let age = |dtc: &Dtc| (self.boot.wrapping_sub(dtc.boot), Reverse(dtc.last));
+	self.dtcs.iter().enumerate()
+	    .filter_map(|(i, dtc)| Some((i, age(dtc.as_ref()?))))
+	    .max_by_key(|(_, age)| *age)
+	    .map_or(0, |(i, _)| i)
+    }
+}
+
+// ================================================================================
+
+const MAGIC: u32 = 0x4454_4331; // "DTC1"
+
+const DTC_SIZE: usize = 28;
+
+/// One image, as stored:
+///
+/// | bytes    | field                        |
+/// |----------|------------------------------|
+/// | 0..4     | magic                        |
+/// | 4..8     | sequence number              |
+/// | 8..      | `MAX_DTCS` codes, see below  |
+/// | last 4   | CRC-32 of everything before  |
+///
+/// and each code, with an all `0xFF` code for an empty slot:
+///
+/// | bytes  | field                             |
+/// |--------|-----------------------------------|
+/// | 0..2   | code                              |
+/// | 2..4   | count                             |
+/// | 4..8   | first (ms)                        |
+/// | 8..12  | last (ms)                         |
+/// | 12..16 | boot                              |
+/// | 16     | freeze frame gear (0xFF = none)   |
+/// | 17     | reserved                          |
+/// | 18..20 | freeze frame buttons              |
+/// | 20..24 | freeze frame uptime (ms)          |
+/// | 24..28 | reserved                          |
+///
+/// All little endian.
+pub const IMAGE_SIZE: usize = 8 + MAX_DTCS * DTC_SIZE + 4;
+
+const EMPTY: u16 = 0xFFFF;
+const NO_GEAR: u8 = 0xFF;
+
+impl DtcStore {
+    pub fn encode(&self, seq: u32) -> [u8; IMAGE_SIZE] {
+	let mut buf = [0xFF; IMAGE_SIZE];
+	buf[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+	buf[4..8].copy_from_slice(&seq.to_le_bytes());
+
+	for (slot, dtc) in buf[8..].chunks_exact_mut(DTC_SIZE).zip(&self.dtcs) {
+	    let Some(dtc) = dtc else {
+		continue;
+	    };
+	    slot[0..2].copy_from_slice(&dtc.code.to_le_bytes());
+	    slot[2..4].copy_from_slice(&dtc.count.to_le_bytes());
+	    slot[4..8].copy_from_slice(&(dtc.first as u32).to_le_bytes());
+	    slot[8..12].copy_from_slice(&(dtc.last as u32).to_le_bytes());
+	    slot[12..16].copy_from_slice(&dtc.boot.to_le_bytes());
+	    slot[16] = dtc.frame.gear.map_or(NO_GEAR, |g| g as u8);
+	    slot[18..20].copy_from_slice(&dtc.frame.buttons.to_le_bytes());
+	    slot[20..24].copy_from_slice(&(dtc.frame.uptime as u32).to_le_bytes());
+	}
+
+	let crc = crc32(&buf[..IMAGE_SIZE - 4]);
+	buf[IMAGE_SIZE - 4..].copy_from_slice(&crc.to_le_bytes());
+	buf
+    }
+
+    /// The sequence number, if `buf` is a good image. The codes are only
+    /// replaced if it is.
+    pub fn decode(&mut self, buf: &[u8; IMAGE_SIZE]) -> Option<u32> {
+	if u32_at(buf, 0) != MAGIC || u32_at(buf, IMAGE_SIZE - 4) != crc32(&buf[..IMAGE_SIZE - 4]) {
+	    return None;
+	}
+
+	let mut dtcs = [None; MAX_DTCS];
+	for (dtc, slot) in dtcs.iter_mut().zip(buf[8..].chunks_exact(DTC_SIZE)) {
+	    let code = u16::from_le_bytes([slot[0], slot[1]]);
+	    if code == EMPTY {
+		continue;
+	    }
+	    let gear = match slot[16] {
+		NO_GEAR => None,
+		g => Some(Gear::from_u8(g)?),
+	    };
+	    *dtc = Some(Dtc {
+		code,
+		count: u16::from_le_bytes([slot[2], slot[3]]),
+		first: u32_at(slot, 4) as u64,
+		last:  u32_at(slot, 8) as u64,
+		boot:  u32_at(slot, 12),
+		frame: FreezeFrame {
+		    gear,
+		    buttons: u16::from_le_bytes([slot[18], slot[19]]),
+		    uptime:  u32_at(slot, 20) as u64,
+		},
+	    });
+	}
+	self.dtcs = dtcs;
+	Some(u32_at(buf, 4))
+    }
+}
+
+fn u32_at(buf: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]])
+}
+
+/// Where the codes are kept - two erase sectors, starting at `offset`.
+#[derive(Debug)]
+pub struct DtcArea {
+    offset: u32,
+    seq:    u32,
+    sector: u32, // The one the newest image is in.
+}
+
+impl DtcArea {
+    pub const fn new(offset: u32) -> Self {
+	Self { offset, seq: 0, sector: 1 }
+    }
+
+    /// Load the newest good image into `dtcs`. Returns false if there
+    /// isn't one - `dtcs` is left alone then.
+    pub fn load<F: Flash>(&mut self, flash: &mut F, dtcs: &mut DtcStore) -> Result<bool, F::Error> {
+	let mut newest = None;
+	for sector in 0..2 {
+	    let mut buf = [0; IMAGE_SIZE];
+	    flash.read(self.offset + sector * F::SECTOR_SIZE, &mut buf)?;
+
+	    let mut image = DtcStore::new(dtcs.aging);
+	    if let Some(seq) = image.decode(&buf) {
+		if newest.as_ref().is_none_or(|(s, _, _)| seq.wrapping_sub(*s) as i32 > 0) {
+		    newest = Some((seq, sector, image));
+		}
+	    }
+	}
+
+	let Some((seq, sector, image)) = newest else {
+	    return Ok(false);
+	};
+	self.seq = seq;
+	self.sector = sector;
+	dtcs.dtcs = image.dtcs;
+	Ok(true)
+    }
+
+    /// Write `dtcs` to the other sector.
+    pub fn store<F: Flash>(&mut self, flash: &mut F, dtcs: &DtcStore) -> Result<(), F::Error> {
+	let sector = 1 - self.sector;
+	let from = self.offset + sector * F::SECTOR_SIZE;
+	flash.erase(from, from + F::SECTOR_SIZE)?;
+	flash.write(from, &dtcs.encode(self.seq.wrapping_add(1)))?;
+
+	self.seq = self.seq.wrapping_add(1);
+	self.sector = sector;
+	Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::fault::Subsystem;
+    use crate::storage::MemFlash;
+
+    const SIZE: usize = 2 * MemFlash::<0>::SECTOR_SIZE as usize;
+
+    fn frame(gear: Gear, uptime: u64) -> FreezeFrame {
+	FreezeFrame { gear: Some(gear), buttons: 1 << gear as u8, uptime }
+    }
+
+    // `MAX_DTCS` different faults.
+    fn faults() -> impl Iterator<Item = Fault> {
+	Gear::ALL.into_iter().map(Fault::StuckButton).chain(Gear::ALL.into_iter().map(Fault::OpenLed)).take(MAX_DTCS)
+    }
+
+    fn codes(dtcs: &DtcStore) -> Vec<u16> {
+	let mut codes: Vec<u16> = dtcs.iter().map(|dtc| dtc.code).collect();
+	codes.sort();
+	codes
+    }
+
+    #[test]
+    fn record_once() {
+	let mut dtcs = DtcStore::new(DEFAULT_AGING);
+	dtcs.start(3);
+	dtcs.record(Fault::StuckButton(Gear::D), 100, frame(Gear::D, 100));
+	dtcs.record(Fault::StuckButton(Gear::D), 500, frame(Gear::R, 500));
+
+	assert_eq!(dtcs.len(), 1);
+	let dtc = dtcs.iter().next().unwrap();
+	assert_eq!(dtc.fault(), Some(Fault::StuckButton(Gear::D)));
+	assert_eq!((dtc.count, dtc.first, dtc.last, dtc.boot), (2, 100, 500, 3));
+
+	// The freeze frame is from the first time.
+	assert_eq!(dtc.frame, frame(Gear::D, 100));
+
+	dtcs.record(Fault::OpenLed(Gear::D), 600, frame(Gear::D, 600));
+	assert_eq!(dtcs.len(), 2);
+    }
+
+    #[test]
+    fn count_saturates() {
+	let mut dtcs = DtcStore::new(DEFAULT_AGING);
+	for now in 0..u16::MAX as u64 + 10 {
+	    dtcs.record(Fault::Panic, now, frame(Gear::P, now));
+	}
+	assert_eq!(dtcs.iter().next().unwrap().count, u16::MAX);
+    }
+
+    #[test]
+    fn aging() {
+	let mut dtcs = DtcStore::new(3);
+	dtcs.start(10);
+	dtcs.record(Fault::Panic, 0, frame(Gear::P, 0));
+	dtcs.start(11);
+	dtcs.record(Fault::InitFailed(Subsystem::Can), 0, frame(Gear::P, 0));
+
+	assert!(!dtcs.start(12));
+	assert_eq!(dtcs.len(), 2);
+
+	// Three boots without it.
+	assert!(dtcs.start(13));
+	assert_eq!(dtcs.iter().map(|dtc| dtc.fault()).collect::<Vec<_>>(), [Some(Fault::InitFailed(Subsystem::Can))]);
+
+	// Seen again, it starts aging all over.
+	dtcs.record(Fault::InitFailed(Subsystem::Can), 0, frame(Gear::P, 0));
+	assert!(!dtcs.start(15));
+	assert!(dtcs.start(16));
+	assert!(dtcs.is_empty());
+    }
+
+    #[test]
+    fn aging_wraps() {
+	let mut dtcs = DtcStore::new(3);
+	dtcs.start(u32::MAX);
+	dtcs.record(Fault::Panic, 0, frame(Gear::P, 0));
+	assert!(!dtcs.start(1));
+	assert!(dtcs.start(2));
+    }
+
+    #[test]
+    fn overflow() {
+	let mut dtcs = DtcStore::new(DEFAULT_AGING);
+	dtcs.start(1);
+	for (i, fault) in faults().enumerate() {
+	    dtcs.record(fault, 100 * i as u64, frame(Gear::P, 0));
+	}
+	assert_eq!(dtcs.len(), MAX_DTCS);
+
+	// Seeing the first one again makes the second the oldest.
+	dtcs.record(Fault::StuckButton(Gear::P), 5000, frame(Gear::P, 0));
+	dtcs.record(Fault::Panic, 6000, frame(Gear::P, 0));
+	assert_eq!(dtcs.len(), MAX_DTCS);
+	let mut expected: Vec<u16> = faults().filter(|f| *f != Fault::StuckButton(Gear::N)).map(Fault::code).collect();
+	expected.push(Fault::Panic.code());
+	expected.sort();
+	assert_eq!(codes(&dtcs), expected);
+
+	// An older boot is older, whatever the uptime.
+	let mut dtcs = DtcStore::new(DEFAULT_AGING);
+	dtcs.start(1);
+	dtcs.record(Fault::Panic, 9000, frame(Gear::P, 0));
+	dtcs.start(2);
+	for fault in faults().skip(1) {
+	    dtcs.record(fault, 100, frame(Gear::P, 0));
+	}
+	dtcs.record(Fault::StuckButton(Gear::P), 200, frame(Gear::P, 0));
+	assert_eq!(codes(&dtcs), faults().map(Fault::code).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn clear() {
+	let mut dtcs = DtcStore::new(DEFAULT_AGING);
+	dtcs.record(Fault::Panic, 0, frame(Gear::P, 0));
+	dtcs.clear();
+	assert!(dtcs.is_empty());
+    }
+
+    #[test]
+    fn image() {
+	let mut dtcs = DtcStore::new(DEFAULT_AGING);
+	dtcs.start(7);
+	dtcs.record(Fault::ShortedButtons(Gear::D, Gear::R), 1234, frame(Gear::D, 1234));
+	dtcs.record(Fault::WatchdogReset(None), 99, FreezeFrame { gear: None, buttons: 0, uptime: 99 });
+	dtcs.record(Fault::ShortedButtons(Gear::D, Gear::R), 2345, frame(Gear::N, 2345));
+
+	let mut decoded = DtcStore::new(DEFAULT_AGING);
+	assert_eq!(decoded.decode(&dtcs.encode(42)), Some(42));
+	assert_eq!(decoded.iter().collect::<Vec<_>>(), dtcs.iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn corrupt_image() {
+	let mut dtcs = DtcStore::new(DEFAULT_AGING);
+	dtcs.record(Fault::Panic, 0, frame(Gear::P, 0));
+	let mut buf = dtcs.encode(1);
+	buf[20] ^= 0x01;
+
+	let mut decoded = DtcStore::new(DEFAULT_AGING);
+	decoded.record(Fault::OpenLed(Gear::R), 0, frame(Gear::R, 0));
+	assert_eq!(decoded.decode(&buf), None);
+	assert_eq!(decoded.decode(&[0xFF; IMAGE_SIZE]), None);
+
+	// Left alone.
+	assert_eq!(codes(&decoded), [Fault::OpenLed(Gear::R).code()]);
+    }
+
+    #[test]
+    fn load_and_store() {
+	let mut flash = MemFlash::<SIZE>::new();
+	let mut area = DtcArea::new(0);
+	let mut dtcs = DtcStore::new(DEFAULT_AGING);
+	assert!(!area.load(&mut flash, &mut dtcs).unwrap());
+
+	// Every store goes to the other sector, and the newest is loaded.
+	for (i, fault) in faults().take(5).enumerate() {
+	    dtcs.record(fault, i as u64, frame(Gear::P, 0));
+	    area.store(&mut flash, &dtcs).unwrap();
+
+	    let mut loaded = DtcStore::new(DEFAULT_AGING);
+	    assert!(DtcArea::new(0).load(&mut flash, &mut loaded).unwrap());
+	    assert_eq!(codes(&loaded), codes(&dtcs));
+	}
+    }
+
+    #[test]
+    fn torn_store() {
+	let mut flash = MemFlash::<SIZE>::new();
+	let mut area = DtcArea::new(0);
+	let mut dtcs = DtcStore::new(DEFAULT_AGING);
+	dtcs.record(Fault::Panic, 0, frame(Gear::P, 0));
+	area.store(&mut flash, &dtcs).unwrap();
+
+	// The power went after erasing the other sector, before writing.
+	let sector = MemFlash::<SIZE>::SECTOR_SIZE;
+	let from = if area.sector == 0 { sector } else { 0 };
+	flash.erase(from, from + sector).unwrap();
+	flash.write(from, &[0x44, 0x54, 0x43, 0x31, 0x02]).unwrap();
+
+	let mut loaded = DtcStore::new(DEFAULT_AGING);
+	assert!(DtcArea::new(0).load(&mut flash, &mut loaded).unwrap());
+	assert_eq!(codes(&loaded), [Fault::Panic.code()]);
+    }
+
+    #[test]
+    fn sequence_wraps() {
+	let mut flash = MemFlash::<SIZE>::new();
+	let mut area = DtcArea { offset: 0, seq: u32::MAX - 1, sector: 1 };
+	let mut dtcs = DtcStore::new(DEFAULT_AGING);
+	dtcs.record(Fault::Panic, 0, frame(Gear::P, 0));
+	area.store(&mut flash, &dtcs).unwrap(); // u32::MAX
+
+	dtcs.record(Fault::OpenLed(Gear::D), 0, frame(Gear::P, 0));
+	area.store(&mut flash, &dtcs).unwrap(); // 0
+
+	let mut loaded = DtcStore::new(DEFAULT_AGING);
+	let mut area = DtcArea::new(0);
+	assert!(area.load(&mut flash, &mut loaded).unwrap());
+	assert_eq!((area.seq, loaded.len()), (0, 2));
+    }
+}
@@ -0,0 +1,432 @@
+//! What happened - button presses, gear changes, faults - with when it did.
+//!
+//! The newest events are kept in RAM by `EventLog`, and can also be written
+//! to flash (`EventSpill`), so there's a longer history of what the driver
+//! did. All times are in ms, since the boot the event happened in.
+
+use crate::crc::crc32;
+use crate::fault::Fault;
+use crate::gear::{Gear, Mode, Selection};
+use crate::interlock::Rejection;
+use crate::storage::Flash;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LogEvent {
+    Press(Gear),
+
+    /// Released after being held this many ms.
+    Release(Gear, u32),
+
+    /// Held long enough to be a long press, this many ms.
+    LongHold(Gear, u32),
+
+    GearChanged(Selection),
+    Rejected(Rejection),
+    Fault(Fault),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LogEntry {
+    pub at:    u64,
+    pub boot:  u32,
+    pub event: LogEvent,
+}
+
+/// Size of an entry, without the CRC.
+pub const ENTRY_SIZE: usize = 12;
+
+const NO_GEAR: u8 = 0xFF;
+
+impl LogEntry {
+    /// As stored and sent:
+    ///
+    /// | bytes  | field           |
+    /// |--------|-----------------|
+    /// | 0..4   | at (ms)         |
+    /// | 4..6   | boot (low bits) |
+    /// | 6      | kind            |
+    /// | 7..11  | data            |
+    /// | 11     | reserved        |
+    ///
+    /// Kinds are 0 press (`[gear]`), 1 release and 2 long hold (`[gear, ms:
+    /// u24]`), 3 gear changed (`[gear, requested, mode, park lock]`), 4
+    /// rejected (`[rejection]`) and 5 fault (`[code: u16]`). Little endian.
+    pub fn to_bytes(&self) -> [u8; ENTRY_SIZE] {
+	let mut buf = [0; ENTRY_SIZE];
+	buf[0..4].copy_from_slice(&(self.at as u32).to_le_bytes());
+	buf[4..6].copy_from_slice(&(self.boot as u16).to_le_bytes());
+
+	let ms = |gear: Gear, ms: u32| {
+	    let [a, b, c, _] = ms.min(0xFF_FFFF).to_le_bytes();
+	    [gear as u8, a, b, c]
+	};
+	let gear = |gear: Option<Gear>| gear.map_or(NO_GEAR, |g| g as u8);
+	let [a, b] = match self.event {
+	    LogEvent::Fault(fault) => fault.code().to_le_bytes(),
+	    _ => [0, 0],
+	};
+	let (kind, data) = match self.event {
+	    LogEvent::Press(gear)          => (0, [gear as u8, 0, 0, 0]),
+	    LogEvent::Release(gear, held)  => (1, ms(gear, held)),
+	    LogEvent::LongHold(gear, held) => (2, ms(gear, held)),
+	    LogEvent::GearChanged(s)       => (3, [gear(s.gear), gear(s.requested), s.mode as u8, s.park_lock as u8]),
+	    LogEvent::Rejected(rejection)  => (4, [rejection as u8, 0, 0, 0]),
+	    LogEvent::Fault(_)             => (5, [a, b, 0, 0]),
+	};
+	buf[6] = kind;
+	buf[7..11].copy_from_slice(&data);
+	buf
+    }
+
+    /// `None` if it's not a valid entry.
+    pub fn from_bytes(buf: &[u8; ENTRY_SIZE]) -> Option<Self> {
+	let d = &buf[7..11];
+	let gear = |g: u8| match g {
+	    NO_GEAR => Some(None),
+	    g => Gear::from_u8(g).map(Some),
+	};
+	let ms = u32::from_le_bytes([d[1], d[2], d[3], 0]);
+
+	let event = match buf[6] {
+	    0 => LogEvent::Press(Gear::from_u8(d[0])?),
+	    1 => LogEvent::Release(Gear::from_u8(d[0])?, ms),
+	    2 => LogEvent::LongHold(Gear::from_u8(d[0])?, ms),
+	    3 => LogEvent::GearChanged(Selection {
+		gear:      gear(d[0])?,
+		requested: gear(d[1])?,
+		mode:      Mode::from_u8(d[2])?,
+		park_lock: d[3] != 0,
+	    }),
+	    4 => LogEvent::Rejected(Rejection::from_u8(d[0])?),
+	    5 => LogEvent::Fault(Fault::from_code(u16::from_le_bytes([d[0], d[1]]))?),
+	    _ => return None,
+	};
+	Some(LogEntry {
+	    at:   u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as u64,
+	    boot: u16::from_le_bytes([buf[4], buf[5]]) as u32,
+	    event,
+	})
+    }
+}
+
+/// The newest `N` events, in RAM.
+#[derive(Clone, Debug)]
+pub struct EventLog<const N: usize> {
+    entries: [Option<LogEntry>; N],
+    next:    usize,
+
+    // How many of the newest entries haven't been spilled to flash.
+    unspilled: usize,
+}
+
+impl<const N: usize> Default for EventLog<N> {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+impl<const N: usize> EventLog<N> {
+    pub const fn new() -> Self {
+	Self { entries: [None; N], next: 0, unspilled: 0 }
+    }
+
+    pub fn push(&mut self, entry: LogEntry) {
+	self.entries[self.next] = Some(entry);
+	self.next = (self.next + 1) % N;
+	self.unspilled = (self.unspilled + 1).min(N);
+    }
+
+    pub fn len(&self) -> usize {
+	self.entries.iter().flatten().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+	self.len() == 0
+    }
+
+    /// Oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &LogEntry> {
+	let (newer, older) = self.entries.split_at(self.next);
+	older.iter().chain(newer).flatten()
+    }
+
+    /// The entries that haven't been spilled yet, oldest first. If more
+    /// than `N` have been pushed since the last spill, the oldest of them
+    /// are already gone.
+    pub fn unspilled(&self) -> impl Iterator<Item = &LogEntry> {
+	let skip = self.len() - self.unspilled;
+	self.iter().skip(skip)
+    }
+
+    /// Everything up to now have been spilled.
+    pub fn spilled(&mut self) {
+	self.unspilled = 0;
+    }
+}
+
+// ================================================================================
+
+// An entry and its CRC, in flash. Divides any sector size.
+const SLOT_SIZE: u32 = ENTRY_SIZE as u32 + 4;
+
+/// Events in flash, as a ring of erase sectors. Entries are written one
+/// after the other, and the next sector is erased as soon as one is full -
+/// so the one after the sector being written always has the oldest entries,
+/// and after a reset we continue at the first erased slot.
+#[derive(Debug)]
+pub struct EventSpill {
+    offset: u32,
+    size:   u32,
+    next:   u32, // Slot to write next.
+}
+
+impl EventSpill {
+    /// Use `size` bytes from `offset`. Both should be sector aligned, and
+    /// there need to be at least two sectors.
+    pub const fn new(offset: u32, size: u32) -> Self {
+	Self { offset, size, next: 0 }
+    }
+
+    /// How many entries there's room for.
+    pub fn slots(&self) -> usize {
+	(self.size / SLOT_SIZE) as usize
+    }
+
+    /// Find where to continue writing - the first erased slot after one
+    /// that isn't. An all erased flash starts from the beginning.
+    pub fn find<F: Flash>(&mut self, flash: &mut F) -> Result<(), F::Error> {
+	let slots = self.slots() as u32;
+	let mut previous = self.is_erased(flash, slots - 1)?;
+	for slot in 0..slots {
+	    let erased = self.is_erased(flash, slot)?;
+	    if erased && !previous {
+		self.next = slot;
+		return Ok(());
+	    }
+	    previous = erased;
+	}
+	self.next = 0;
+	Ok(())
+    }
+
+    pub fn write<F: Flash>(&mut self, flash: &mut F, entry: &LogEntry) -> Result<(), F::Error> {
+	// Only if something went wrong - a write or erase that didn't finish,
+	// or flash that was never erased at all.
+	if !self.is_erased(flash, self.next)? {
+	    self.erase_sector_of(flash, self.next)?;
+	}
+
+	let mut buf = [0; SLOT_SIZE as usize];
+	buf[..ENTRY_SIZE].copy_from_slice(&entry.to_bytes());
+	let crc = crc32(&buf[..ENTRY_SIZE]);
+	buf[ENTRY_SIZE..].copy_from_slice(&crc.to_le_bytes());
+	flash.write(self.offset + self.next * SLOT_SIZE, &buf)?;
+
+	self.next = (self.next + 1) % self.slots() as u32;
+	if self.next.is_multiple_of(F::SECTOR_SIZE / SLOT_SIZE) {
+	    self.erase_sector_of(flash, self.next)?;
+	}
+	Ok(())
+    }
+
+    /// Entry `n`, counting from the oldest that could be there. `None` for
+    /// an erased or corrupt slot.
+    pub fn read<F: Flash>(&self, flash: &mut F, n: usize) -> Result<Option<LogEntry>, F::Error> {
+	let per_sector = F::SECTOR_SIZE / SLOT_SIZE;
+	let oldest = self.next - self.next % per_sector + per_sector;
+	let slot = (oldest + n as u32) % self.slots() as u32;
+
+	let mut buf = [0; SLOT_SIZE as usize];
+	flash.read(self.offset + slot * SLOT_SIZE, &mut buf)?;
+	let (entry, crc) = buf.split_at(ENTRY_SIZE);
+	if crc32(entry).to_le_bytes() != crc {
+	    return Ok(None);
+	}
+	Ok(entry.try_into().ok().and_then(LogEntry::from_bytes))
+    }
+
+    fn is_erased<F: Flash>(&self, flash: &mut F, slot: u32) -> Result<bool, F::Error> {
+	let mut buf = [0; SLOT_SIZE as usize];
+	flash.read(self.offset + slot * SLOT_SIZE, &mut buf)?;
+	Ok(buf.iter().all(|b| *b == 0xFF))
+    }
+
+    fn erase_sector_of<F: Flash>(&self, flash: &mut F, slot: u32) -> Result<(), F::Error> {
+	let addr = slot * SLOT_SIZE;
+	let from = self.offset + addr - addr % F::SECTOR_SIZE;
+	flash.erase(from, from + F::SECTOR_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::storage::MemFlash;
+
+    const SECTOR: u32 = MemFlash::<0>::SECTOR_SIZE;
+    const SIZE: usize = 2 * SECTOR as usize;
+    const PER_SECTOR: usize = (SECTOR / SLOT_SIZE) as usize;
+
+    fn press(at: u64) -> LogEntry {
+	LogEntry { at, boot: 1, event: LogEvent::Press(Gear::D) }
+    }
+
+    fn ats<'a>(entries: impl Iterator<Item = &'a LogEntry>) -> Vec<u64> {
+	entries.map(|entry| entry.at).collect()
+    }
+
+    #[test]
+    fn entries() {
+	let selection = Selection { gear: Some(Gear::D), requested: None, mode: Mode::Manual, park_lock: true };
+	for event in [
+	    LogEvent::Press(Gear::B),
+	    LogEvent::Release(Gear::P, 1234),
+	    LogEvent::LongHold(Gear::Two, 0xFF_FFFF),
+	    LogEvent::GearChanged(selection),
+	    LogEvent::GearChanged(Selection { gear: None, requested: Some(Gear::R), ..selection }),
+	    LogEvent::Rejected(Rejection::NotAtStandstill),
+	    LogEvent::Fault(Fault::ShortedButtons(Gear::R, Gear::D)),
+	    LogEvent::Fault(Fault::WatchdogReset(None)),
+	] {
+	    let entry = LogEntry { at: 0x1234_5678, boot: 42, event };
+	    assert_eq!(LogEntry::from_bytes(&entry.to_bytes()), Some(entry), "{:?}", event);
+	}
+    }
+
+    #[test]
+    fn entry_bytes() {
+	let entry = LogEntry { at: 0x0102_0304, boot: 0x0506, event: LogEvent::Release(Gear::D, 0x0A_0B0C) };
+	assert_eq!(entry.to_bytes(), [0x04, 0x03, 0x02, 0x01, 0x06, 0x05, 1, 3, 0x0C, 0x0B, 0x0A, 0]);
+
+	let entry = LogEntry { at: 0, boot: 0, event: LogEvent::Fault(Fault::StuckButton(Gear::R)) };
+	assert_eq!(entry.to_bytes(), [0, 0, 0, 0, 0, 0, 5, 0x02, 0x06, 0, 0, 0]);
+    }
+
+    #[test]
+    fn entry_truncated() {
+	// Only the low bits of the time, the boot and how long it was held.
+	let entry = LogEntry { at: 0x1_0000_0005, boot: 0x1_0002, event: LogEvent::LongHold(Gear::D, 0x1FF_FFFF) };
+	let decoded = LogEntry::from_bytes(&entry.to_bytes()).unwrap();
+	assert_eq!((decoded.at, decoded.boot, decoded.event), (5, 2, LogEvent::LongHold(Gear::D, 0xFF_FFFF)));
+    }
+
+    #[test]
+    fn bad_entries() {
+	let mut buf = press(0).to_bytes();
+	buf[6] = 6;
+	assert_eq!(LogEntry::from_bytes(&buf), None);
+
+	buf[6] = 0;
+	buf[7] = Gear::COUNT as u8;
+	assert_eq!(LogEntry::from_bytes(&buf), None);
+
+	let mut buf = LogEntry { at: 0, boot: 0, event: LogEvent::Fault(Fault::Panic) }.to_bytes();
+	buf[8] = 0x7F;
+	assert_eq!(LogEntry::from_bytes(&buf), None);
+    }
+
+    #[test]
+    fn log() {
+	let mut log = EventLog::<4>::new();
+	assert!(log.is_empty());
+	for at in 0..3 {
+	    log.push(press(at));
+	}
+	assert_eq!(ats(log.iter()), [0, 1, 2]);
+
+	// The oldest make room.
+	for at in 3..7 {
+	    log.push(press(at));
+	}
+	assert_eq!(log.len(), 4);
+	assert_eq!(ats(log.iter()), [3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn unspilled() {
+	let mut log = EventLog::<4>::new();
+	log.push(press(0));
+	log.push(press(1));
+	assert_eq!(ats(log.unspilled()), [0, 1]);
+
+	log.spilled();
+	assert_eq!(ats(log.unspilled()), []);
+	log.push(press(2));
+	assert_eq!(ats(log.unspilled()), [2]);
+
+	// More than fit since the last spill - the oldest are gone.
+	log.spilled();
+	for at in 3..9 {
+	    log.push(press(at));
+	}
+	assert_eq!(ats(log.unspilled()), [5, 6, 7, 8]);
+    }
+
+    fn read_all(spill: &EventSpill, flash: &mut MemFlash<SIZE>) -> Vec<u64> {
+	(0..spill.slots()).filter_map(|n| spill.read(flash, n).unwrap()).map(|entry| entry.at).collect()
+    }
+
+    #[test]
+    fn spill() {
+	let mut flash = MemFlash::<SIZE>::new();
+	let mut spill = EventSpill::new(0, SIZE as u32);
+	spill.find(&mut flash).unwrap();
+	assert_eq!(spill.slots(), 2 * PER_SECTOR);
+	assert_eq!(read_all(&spill, &mut flash), []);
+
+	for at in 0..10 {
+	    spill.write(&mut flash, &press(at)).unwrap();
+	}
+	assert_eq!(read_all(&spill, &mut flash), (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn spill_wraps() {
+	let mut flash = MemFlash::<SIZE>::new();
+	let mut spill = EventSpill::new(0, SIZE as u32);
+	spill.find(&mut flash).unwrap();
+
+	// Filling a sector erases the next, so there's always one sector's
+	// worth fewer than there's room for.
+	let total = 3 * PER_SECTOR as u64 + 5;
+	for at in 0..total {
+	    spill.write(&mut flash, &press(at)).unwrap();
+	}
+	let first = total - PER_SECTOR as u64 - 5;
+	assert_eq!(read_all(&spill, &mut flash), (first..total).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn spill_continues() {
+	let mut flash = MemFlash::<SIZE>::new();
+	let mut spill = EventSpill::new(0, SIZE as u32);
+	spill.find(&mut flash).unwrap();
+	let total = PER_SECTOR as u64 + 3;
+	for at in 0..total {
+	    spill.write(&mut flash, &press(at)).unwrap();
+	}
+
+	// After a reset, carry on after the last one written.
+	let mut spill = EventSpill::new(0, SIZE as u32);
+	spill.find(&mut flash).unwrap();
+	spill.write(&mut flash, &press(total)).unwrap();
+	assert_eq!(read_all(&spill, &mut flash), (0..=total).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn spill_corrupt() {
+	let mut flash = MemFlash::<SIZE>::new();
+	let mut spill = EventSpill::new(0, SIZE as u32);
+	spill.find(&mut flash).unwrap();
+	for at in 0..3 {
+	    spill.write(&mut flash, &press(at)).unwrap();
+	}
+
+	// A torn write is skipped.
+	flash.data[SLOT_SIZE as usize] &= 0xFE;
+	assert_eq!(read_all(&spill, &mut flash), [0, 2]);
+    }
+}
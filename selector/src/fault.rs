@@ -0,0 +1,480 @@
+//! Faults, what to do about them, and the most recent ones.
+//!
+//! What to do is up to the `FaultPolicy` - just log it, retry, carry on
+//! without the part that failed, or give up and go to the safe gear. The
+//! log is a small ring buffer in RAM, oldest entries dropped first.
+//! Readable over the UART. All times are in ms.
+
+use crate::crc::crc32;
+use crate::gear::Gear;
+use crate::interlock::Rejection;
+use crate::watchdog::Task;
+
+/// The parts of the firmware that can fail on their own.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Subsystem {
+    Buttons,
+    Leds,
+    Gestures,
+    Inputs,
+    Confirm,
+    Dimmer,
+    Can,
+    Uart,
+    Usb,
+    Watchdog,
+    Status,
+    Storage,
+    Power,
+}
+
+impl Subsystem {
+    const ALL: [Subsystem; 13] = [
+	Subsystem::Buttons, Subsystem::Leds, Subsystem::Gestures, Subsystem::Inputs, Subsystem::Confirm,
+	Subsystem::Dimmer, Subsystem::Can, Subsystem::Uart, Subsystem::Usb, Subsystem::Watchdog,
+	Subsystem::Status, Subsystem::Storage, Subsystem::Power,
+    ];
+
+    /// The reverse of `subsystem as u8`.
+    pub fn from_u8(value: u8) -> Option<Self> {
+	Self::ALL.get(value as usize).copied()
+    }
+
+    pub fn name(self) -> &'static str {
+	match self {
+	    Subsystem::Buttons  => "buttons",
+	    Subsystem::Leds     => "LEDs",
+	    Subsystem::Gestures => "gestures",
+	    Subsystem::Inputs   => "inputs",
+	    Subsystem::Confirm  => "confirmation",
+	    Subsystem::Dimmer   => "dimmer",
+	    Subsystem::Can      => "CAN",
+	    Subsystem::Uart     => "UART",
+	    Subsystem::Usb      => "USB",
+	    Subsystem::Watchdog => "watchdog",
+	    Subsystem::Status   => "status LED",
+	    Subsystem::Storage  => "storage",
+	    Subsystem::Power    => "power",
+	}
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// The interlocks said no to a gear change.
+    Rejected(Rejection),
+
+    /// The transmission didn't confirm this gear in time.
+    NotConfirmed(Gear),
+
+    /// We were reset by the watchdog - this task had stopped (`None` if
+    /// we don't know which one).
+    WatchdogReset(Option<Task>),
+
+    /// Couldn't start the task(s) of a subsystem.
+    SpawnFailed(Subsystem),
+
+    /// A subsystem isn't keeping up - its channel was full, and whatever
+    /// was sent to it is lost.
+    ChannelFull(Subsystem),
+
+    /// The hardware of a subsystem didn't come up.
+    InitFailed(Subsystem),
+
+    /// This gear's button have been pressed for too long.
+    StuckButton(Gear),
+
+    /// We panicked before the last reset.
+    Panic,
+
+    /// These two gears' buttons went down at the same time - they're
+    /// shorted to each other.
+    ShortedButtons(Gear, Gear),
+
+    /// This gear's LED doesn't draw any current when it's on.
+    OpenLed(Gear),
+}
+
+impl Fault {
+    pub fn name(self) -> &'static str {
+	match self {
+	    Fault::Rejected(_)        => "rejected",
+	    Fault::NotConfirmed(_)    => "not confirmed",
+	    Fault::WatchdogReset(_)   => "watchdog reset",
+	    Fault::SpawnFailed(_)     => "spawn failed",
+	    Fault::ChannelFull(_)     => "channel full",
+	    Fault::InitFailed(_)      => "init failed",
+	    Fault::StuckButton(_)     => "stuck button",
+	    Fault::Panic              => "panic",
+	    Fault::ShortedButtons(..) => "shorted buttons",
+	    Fault::OpenLed(_)         => "open LED",
+	}
+    }
+
+    /// Should it be kept as a trouble code? A rejected gear change is the
+    /// driver's doing, not a fault of ours.
+    pub fn is_dtc(self) -> bool {
+	!matches!(self, Fault::Rejected(_))
+    }
+
+    /// Diagnostic code - the kind of fault in the high byte, and what it
+    /// was about in the low. Kinds are
+    ///
+    ///   0 rejected (`rejection as u8`)
+    ///   1 not confirmed (the gear)
+    ///   2 watchdog reset (`Task::to_u8()`, `0xFF` if unknown)
+    ///   3 spawn failed, 4 channel full, 5 init failed (`subsystem as u8`)
+    ///   6 stuck button (the gear)
+    ///   7 panic (0)
+    ///   8 shorted buttons (the gears, one per nibble)
+    ///   9 open LED (the gear)
+    ///
+    /// where a gear is `gear as u8`.
+    pub fn code(self) -> u16 {
+	let (kind, detail) = match self {
+	    Fault::Rejected(rejection)    => (0, rejection as u8),
+	    Fault::NotConfirmed(gear)     => (1, gear as u8),
+	    Fault::WatchdogReset(task)    => (2, task.map_or(UNKNOWN_TASK, Task::to_u8)),
+	    Fault::SpawnFailed(subsystem) => (3, subsystem as u8),
+	    Fault::ChannelFull(subsystem) => (4, subsystem as u8),
+	    Fault::InitFailed(subsystem)  => (5, subsystem as u8),
+	    Fault::StuckButton(gear)      => (6, gear as u8),
+	    Fault::Panic                  => (7, 0),
+	    Fault::ShortedButtons(a, b)   => (8, (a as u8) << 4 | b as u8),
+	    Fault::OpenLed(gear)          => (9, gear as u8),
+	};
+	(kind as u16) << 8 | detail as u16
+    }
+
+    /// The reverse of `code()`.
+    pub fn from_code(code: u16) -> Option<Self> {
+	let detail = code as u8;
+	Some(match code >> 8 {
+	    0 => Fault::Rejected(Rejection::from_u8(detail)?),
+	    1 => Fault::NotConfirmed(Gear::from_u8(detail)?),
+	    2 if detail == UNKNOWN_TASK => Fault::WatchdogReset(None),
+	    2 => Fault::WatchdogReset(Some(Task::from_u8(detail)?)),
+	    3 => Fault::SpawnFailed(Subsystem::from_u8(detail)?),
+	    4 => Fault::ChannelFull(Subsystem::from_u8(detail)?),
+	    5 => Fault::InitFailed(Subsystem::from_u8(detail)?),
+	    6 => Fault::StuckButton(Gear::from_u8(detail)?),
+	    7 if detail == 0 => Fault::Panic,
+	    8 => Fault::ShortedButtons(Gear::from_u8(detail >> 4)?, Gear::from_u8(detail & 0x0F)?),
+	    9 => Fault::OpenLed(Gear::from_u8(detail)?),
+	    _ => return None,
+	})
+    }
+}
+
+// In a watchdog reset code, for when we don't know which task it was.
+const UNKNOWN_TASK: u8 = 0xFF;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FaultRecord {
+    pub fault: Fault,
+    pub at:    u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct FaultLog<const N: usize> {
+    records: [Option<FaultRecord>; N],
+    next:    usize,
+    total:   u32,
+}
+
+impl<const N: usize> Default for FaultLog<N> {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+impl<const N: usize> FaultLog<N> {
+    pub const fn new() -> Self {
+	Self { records: [None; N], next: 0, total: 0 }
+    }
+
+    pub fn push(&mut self, fault: Fault, now: u64) {
+	self.records[self.next] = Some(FaultRecord { fault, at: now });
+	self.next = (self.next + 1) % N;
+	self.total = self.total.saturating_add(1);
+    }
+
+    /// How many faults there have been since boot, including the ones that
+    /// have been dropped.
+    pub fn total(&self) -> u32 {
+	self.total
+    }
+
+    /// The faults we still have, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &FaultRecord> {
+	let (newer, older) = self.records.split_at(self.next);
+	older.iter().chain(newer).flatten()
+    }
+}
+
+/// What to do about a fault.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Reaction {
+    /// Log it, nothing else.
+    Log,
+
+    /// Try again, after `FaultPolicy::retry_ms`.
+    Retry,
+
+    /// Carry on without the part that failed.
+    Degrade,
+
+    /// Go to the safe gear and stay there.
+    SafeState,
+}
+
+impl Reaction {
+    pub fn name(self) -> &'static str {
+	match self {
+	    Reaction::Log       => "log",
+	    Reaction::Retry     => "retry",
+	    Reaction::Degrade   => "degrade",
+	    Reaction::SafeState => "safe state",
+	}
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FaultPolicy {
+    /// We can't select gears safely without these - if one of them fails,
+    /// go to the safe state. Anything else just degrades.
+    pub critical: &'static [Subsystem],
+
+    /// How many times to retry bringing up hardware before giving up on it.
+    pub retries: u8,
+    pub retry_ms: u64,
+
+    /// What to do about wiring faults. The buttons are ignored until
+    /// they're released either way.
+    pub stuck_button:    Reaction,
+    pub shorted_buttons: Reaction,
+    pub open_led:        Reaction,
+}
+
+/// Buttons and gestures are critical, and so is the watchdog. Wiring faults
+/// are ignored.
+pub const DEFAULT_POLICY: FaultPolicy = FaultPolicy {
+    critical:        &[Subsystem::Buttons, Subsystem::Gestures, Subsystem::Watchdog],
+    retries:         3,
+    retry_ms:        1000,
+    stuck_button:    Reaction::Degrade,
+    shorted_buttons: Reaction::Degrade,
+    open_led:        Reaction::Degrade,
+};
+
+impl FaultPolicy {
+    /// What to do about `fault`, which have happened `attempt` times before
+    /// (only matters for the ones that can be retried).
+    pub fn reaction(&self, fault: Fault, attempt: u8) -> Reaction {
+	match fault {
+	    Fault::InitFailed(_) if attempt < self.retries => Reaction::Retry,
+	    Fault::SpawnFailed(s) | Fault::InitFailed(s) if self.critical.contains(&s) => Reaction::SafeState,
+	    Fault::SpawnFailed(_) | Fault::InitFailed(_) => Reaction::Degrade,
+	    Fault::StuckButton(_) => self.stuck_button,
+	    Fault::ShortedButtons(..) => self.shorted_buttons,
+	    Fault::OpenLed(_) => self.open_led,
+
+	    // Already dealt with by the time they're reported.
+	    Fault::Rejected(_) | Fault::NotConfirmed(_) | Fault::WatchdogReset(_) | Fault::ChannelFull(_) | Fault::Panic => {
+		Reaction::Log
+	    }
+	}
+    }
+}
+
+// ================================================================================
+
+/// Longest file name kept in a `PanicRecord` - longer ones keep the end.
+pub const PANIC_FILE_LEN: usize = 48;
+
+const PANIC_MAGIC: u32 = 0x5041_4E43; // "PANC"
+
+/// Where we panicked. Kept in RAM that isn't cleared at reset, so it can be
+/// reported after the reboot - which means it might be garbage, hence the
+/// magic and the CRC.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct PanicRecord {
+    magic:  u32,
+    line:   u32,
+    column: u32,
+    len:    u32,
+    file:   [u8; PANIC_FILE_LEN],
+    crc:    u32,
+}
+
+impl PanicRecord {
+    /// Not a valid record.
+    pub const fn empty() -> Self {
+	Self { magic: 0, line: 0, column: 0, len: 0, file: [0; PANIC_FILE_LEN], crc: 0 }
+    }
+
+    pub fn new(file: &str, line: u32, column: u32) -> Self {
+	// Keep the end of the path, without splitting a character.
+	let mut start = file.len().saturating_sub(PANIC_FILE_LEN);
+	while !file.is_char_boundary(start) {
+	    start += 1;
+	}
+	let name = &file.as_bytes()[start..];
+
+	let mut record = Self { magic: PANIC_MAGIC, line, column, len: name.len() as u32, ..Self::empty() };
+	record.file[..name.len()].copy_from_slice(name);
+	record.crc = record.checksum();
+	record
+    }
+
+    pub fn is_valid(&self) -> bool {
+	self.magic == PANIC_MAGIC && self.len as usize <= PANIC_FILE_LEN && self.crc == self.checksum()
+    }
+
+    pub fn file(&self) -> &str {
+	let len = (self.len as usize).min(PANIC_FILE_LEN);
+	core::str::from_utf8(&self.file[..len]).unwrap_or("?")
+    }
+
+    pub fn line(&self) -> u32 {
+	self.line
+    }
+
+    pub fn column(&self) -> u32 {
+	self.column
+    }
+
+    fn checksum(&self) -> u32 {
+	let mut buf = [0; 16 + PANIC_FILE_LEN];
+	buf[0..4].copy_from_slice(&self.magic.to_le_bytes());
+	buf[4..8].copy_from_slice(&self.line.to_le_bytes());
+	buf[8..12].copy_from_slice(&self.column.to_le_bytes());
+	buf[12..16].copy_from_slice(&self.len.to_le_bytes());
+	buf[16..].copy_from_slice(&self.file);
+	crc32(&buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::watchdog::Task;
+
+    // At least one of every kind, and every value of what it's about.
+    fn faults() -> Vec<Fault> {
+	let rejections = (0..=u8::MAX).map_while(Rejection::from_u8);
+	let tasks = [None, Some(Task::Status), Some(Task::Button(0)), Some(Task::Button(15)), Some(Task::Led(7))];
+	let mut faults: Vec<Fault> = rejections.map(Fault::Rejected).collect();
+	faults.extend(tasks.map(Fault::WatchdogReset));
+	faults.push(Fault::Panic);
+	for subsystem in Subsystem::ALL {
+	    faults.extend([Fault::SpawnFailed(subsystem), Fault::ChannelFull(subsystem), Fault::InitFailed(subsystem)]);
+	}
+	for gear in Gear::ALL {
+	    faults.extend([Fault::NotConfirmed(gear), Fault::StuckButton(gear), Fault::OpenLed(gear)]);
+	    faults.extend(Gear::ALL.map(|other| Fault::ShortedButtons(gear, other)));
+	}
+	faults
+    }
+
+    #[test]
+    fn codes() {
+	for (fault, code) in [
+	    (Fault::Rejected(Rejection::BrakeNotPressed), 0x0000),
+	    (Fault::NotConfirmed(Gear::D), 0x0103),
+	    (Fault::WatchdogReset(Some(Task::Led(2))), 0x0222),
+	    (Fault::WatchdogReset(None), 0x02FF),
+	    (Fault::SpawnFailed(Subsystem::Can), 0x0306),
+	    (Fault::ChannelFull(Subsystem::Leds), 0x0401),
+	    (Fault::InitFailed(Subsystem::Power), 0x050C),
+	    (Fault::StuckButton(Gear::R), 0x0602),
+	    (Fault::Panic, 0x0700),
+	    (Fault::ShortedButtons(Gear::N, Gear::B), 0x0819),
+	    (Fault::OpenLed(Gear::P), 0x0900),
+	] {
+	    assert_eq!(fault.code(), code, "{:?}", fault);
+	}
+    }
+
+    #[test]
+    fn round_trip() {
+	let faults = faults();
+	for fault in &faults {
+	    assert_eq!(Fault::from_code(fault.code()), Some(*fault));
+	}
+
+	// And no two the same.
+	let mut codes: Vec<u16> = faults.iter().map(|f| f.code()).collect();
+	codes.sort();
+	codes.dedup();
+	assert_eq!(codes.len(), faults.len());
+    }
+
+    #[test]
+    fn bad_codes() {
+	for code in [0x0004, 0x010A, 0x0230, 0x030D, 0x0701, 0x08A0, 0x080A, 0x0A00, 0xFFFF] {
+	    assert_eq!(Fault::from_code(code), None, "{:04X}", code);
+	}
+
+	// Every code that does decode encodes the same again.
+	for code in 0..=u16::MAX {
+	    if let Some(fault) = Fault::from_code(code) {
+		assert_eq!(fault.code(), code);
+	    }
+	}
+    }
+
+    #[test]
+    fn log() {
+	let mut log = FaultLog::<3>::new();
+	for at in 0..5 {
+	    log.push(Fault::Panic, at);
+	}
+	assert_eq!(log.total(), 5);
+	assert_eq!(log.iter().map(|r| r.at).collect::<Vec<_>>(), [2, 3, 4]);
+    }
+
+    #[test]
+    fn reactions() {
+	let policy = DEFAULT_POLICY;
+	assert_eq!(policy.reaction(Fault::InitFailed(Subsystem::Can), 0), Reaction::Retry);
+	assert_eq!(policy.reaction(Fault::InitFailed(Subsystem::Can), 3), Reaction::Degrade);
+	assert_eq!(policy.reaction(Fault::InitFailed(Subsystem::Buttons), 2), Reaction::Retry);
+	assert_eq!(policy.reaction(Fault::InitFailed(Subsystem::Buttons), 3), Reaction::SafeState);
+	assert_eq!(policy.reaction(Fault::SpawnFailed(Subsystem::Watchdog), 0), Reaction::SafeState);
+	assert_eq!(policy.reaction(Fault::SpawnFailed(Subsystem::Uart), 0), Reaction::Degrade);
+	assert_eq!(policy.reaction(Fault::StuckButton(Gear::D), 0), Reaction::Degrade);
+	assert_eq!(policy.reaction(Fault::NotConfirmed(Gear::D), 0), Reaction::Log);
+
+	let policy = FaultPolicy { shorted_buttons: Reaction::SafeState, ..DEFAULT_POLICY };
+	assert_eq!(policy.reaction(Fault::ShortedButtons(Gear::D, Gear::R), 0), Reaction::SafeState);
+    }
+
+    #[test]
+    fn panic_record() {
+	let record = PanicRecord::new("src/main.rs", 12, 34);
+	assert!(record.is_valid());
+	assert_eq!((record.file(), record.line(), record.column()), ("src/main.rs", 12, 34));
+	assert!(!PanicRecord::empty().is_valid());
+
+	let mut corrupt = record;
+	corrupt.line = 13;
+	assert!(!corrupt.is_valid());
+    }
+
+    #[test]
+    fn panic_record_long_file() {
+	// Keeps the end, and doesn't split the 'é'.
+	let file = "/home/someone/.cargo/registry/src/crates/célèbre-1.0/src/lib.rs";
+	let record = PanicRecord::new(file, 1, 1);
+	assert!(record.is_valid());
+	assert!(file.ends_with(record.file()));
+	assert!(record.file().len() <= PANIC_FILE_LEN);
+	assert!(record.file().len() >= PANIC_FILE_LEN - 1);
+    }
+}
@@ -0,0 +1,75 @@
+//! Just enough futures for the tasks in `tasks` - waiting for whichever of
+//! a few things happens first, without depending on an executor's crate.
+
+use core::future::{poll_fn, Future};
+use core::pin::pin;
+use core::task::Poll;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Either<A, B> {
+    First(A),
+    Second(B),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Either3<A, B, C> {
+    First(A),
+    Second(B),
+    Third(C),
+}
+
+/// Whichever of `a` and `b` is done first - `a` if they both are. The
+/// other one is dropped.
+pub async fn select<A: Future, B: Future>(a: A, b: B) -> Either<A::Output, B::Output> {
+    let mut a = pin!(a);
+    let mut b = pin!(b);
+    poll_fn(|cx| {
+	if let Poll::Ready(a) = a.as_mut().poll(cx) {
+	    return Poll::Ready(Either::First(a));
+	}
+	if let Poll::Ready(b) = b.as_mut().poll(cx) {
+	    return Poll::Ready(Either::Second(b));
+	}
+	Poll::Pending
+    }).await
+}
+
+/// The same, for three.
+pub async fn select3<A: Future, B: Future, C: Future>(a: A, b: B, c: C) -> Either3<A::Output, B::Output, C::Output> {
+    match select(a, select(b, c)).await {
+	Either::First(a)                  => Either3::First(a),
+	Either::Second(Either::First(b))  => Either3::Second(b),
+	Either::Second(Either::Second(c)) => Either3::Third(c),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::future::{pending, ready};
+    use core::task::{Context, Waker};
+
+    use super::*;
+
+    // Polls once.
+    fn poll<F: Future>(future: F) -> Poll<F::Output> {
+	pin!(future).poll(&mut Context::from_waker(Waker::noop()))
+    }
+
+    #[test]
+    fn first_done() {
+	assert_eq!(poll(select(ready(1), pending::<()>())), Poll::Ready(Either::First(1)));
+	assert_eq!(poll(select(pending::<()>(), ready(2))), Poll::Ready(Either::Second(2)));
+	assert_eq!(poll(select(pending::<()>(), pending::<()>())), Poll::Pending);
+
+	// Both, the first wins.
+	assert_eq!(poll(select(ready(1), ready(2))), Poll::Ready(Either::First(1)));
+    }
+
+    #[test]
+    fn three() {
+	assert_eq!(poll(select3(ready(1), ready(2), ready(3))), Poll::Ready(Either3::First(1)));
+	assert_eq!(poll(select3(pending::<()>(), ready(2), ready(3))), Poll::Ready(Either3::Second(2)));
+	assert_eq!(poll(select3(pending::<()>(), pending::<()>(), ready(3))), Poll::Ready(Either3::Third(3)));
+	assert_eq!(poll(select3(pending::<()>(), pending::<()>(), pending::<()>())), Poll::Pending);
+    }
+}
@@ -0,0 +1,415 @@
+//! The gear selection logic.
+//!
+//! Every button belongs to a gear position. A press selects that gear, and
+//! the selector answers with the new gear, from which what every gear LED
+//! should show follows - our own LED on, all the others off. Unless the
+//! interlocks say no, in which case nothing changes.
+
+use crate::action::Action;
+use crate::interlock::{Inputs, Interlock, Rejection};
+use crate::led::{LedStatus, BLINK_MS};
+
+/// Every gear position there is. A selector only has buttons for some of
+/// them - which ones, is up to the board.
+///
+/// Don't reorder, the numbers are stored in flash.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Gear {
+    P, N, R, D,
+
+    /// Low, 2nd and 1st.
+    L, Two, One,
+
+    /// Sport and manual.
+    S, M,
+
+    /// Regen braking.
+    B,
+}
+
+impl Gear {
+    pub const COUNT: usize = 10;
+
+    pub const ALL: [Gear; Gear::COUNT] = [
+	Gear::P, Gear::N, Gear::R, Gear::D, Gear::L, Gear::Two, Gear::One, Gear::S, Gear::M, Gear::B,
+    ];
+
+    /// The reverse of `gear as u8`.
+    pub fn from_u8(value: u8) -> Option<Self> {
+	Self::ALL.get(value as usize).copied()
+    }
+
+    pub fn name(self) -> &'static str {
+	match self {
+	    Gear::P   => "P",
+	    Gear::N   => "N",
+	    Gear::R   => "R",
+	    Gear::D   => "D",
+	    Gear::L   => "L",
+	    Gear::Two => "2",
+	    Gear::One => "1",
+	    Gear::S   => "S",
+	    Gear::M   => "M",
+	    Gear::B   => "B",
+	}
+    }
+
+    /// The reverse of `name()`.
+    pub fn from_name(name: &str) -> Option<Self> {
+	Self::ALL.into_iter().find(|g| g.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// Sub-mode of D.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Mode { Normal, Sport, Manual }
+
+impl Mode {
+    /// The reverse of `mode as u8`.
+    pub fn from_u8(value: u8) -> Option<Self> {
+	[Mode::Normal, Mode::Sport, Mode::Manual].get(value as usize).copied()
+    }
+
+    pub fn name(self) -> &'static str {
+	match self {
+	    Mode::Normal => "normal",
+	    Mode::Sport  => "sport",
+	    Mode::Manual => "manual",
+	}
+    }
+}
+
+
+/// Where we are after a button press (or a confirmation, or a timeout) -
+/// the gear the transmission is in and the one we've asked for but haven't
+/// got confirmed yet.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Selection {
+    pub gear:      Option<Gear>,
+    pub requested: Option<Gear>,
+    pub mode:      Mode,
+    pub park_lock: bool,
+}
+
+impl Selection {
+    /// The gear we want to be in - the requested one if there is one.
+    pub fn target(&self) -> Option<Gear> {
+	self.requested.or(self.gear)
+    }
+
+    /// What the LED of `gear` should show - the engaged gear on, the
+    /// requested one blinking.
+    pub fn led(&self, gear: Gear) -> LedStatus {
+	if self.requested == Some(gear) {
+	    LedStatus::Blink(BLINK_MS)
+	} else if self.gear == Some(gear) {
+	    LedStatus::On
+	} else {
+	    LedStatus::Off
+	}
+    }
+}
+
+// A gear change waiting for the transmission to confirm it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Request {
+    gear:      Gear,
+    park_lock: bool,
+    since:     u64,
+}
+
+/// Keeps track of the selected gear.
+///
+/// By default a gear is engaged as soon as it's selected. With
+/// `confirm_within()`, it's only requested - the LED blinks until the
+/// transmission confirms it, and if that doesn't happen in time we fall
+/// back to the gear we were in. All times are in ms.
+#[derive(Debug)]
+pub struct GearSelector {
+    gear:      Option<Gear>,
+    requested: Option<Request>,
+    mode:      Mode,
+    park_lock: bool,
+    interlock: Interlock,
+    timeout:   Option<u64>,
+}
+
+impl GearSelector {
+    /// Nothing selected, all LEDs off.
+    pub const fn new(interlock: Interlock) -> Self {
+	Self { gear: None, requested: None, mode: Mode::Normal, park_lock: false, interlock, timeout: None }
+    }
+
+    /// Require a confirmation within `timeout` ms for every gear change.
+    pub const fn confirm_within(mut self, timeout: u64) -> Self {
+	self.timeout = Some(timeout);
+	self
+    }
+
+    /// The currently engaged gear, if any.
+    pub fn gear(&self) -> Option<Gear> {
+	self.gear
+    }
+
+    /// The gear we're waiting for a confirmation on, if any.
+    pub fn requested(&self) -> Option<Gear> {
+	self.requested.map(|r| r.gear)
+    }
+
+    pub fn mode(&self) -> Mode {
+	self.mode
+    }
+
+    /// Have the park lock been confirmed (with a long press on P)?
+    pub fn park_lock(&self) -> bool {
+	self.park_lock
+    }
+
+    /// When `tick()` needs to be called, if a request is pending.
+    pub fn deadline(&self) -> Option<u64> {
+	Some(self.requested?.since + self.timeout?)
+    }
+
+    /// The current gear and everything that goes with it.
+    pub fn selection(&self) -> Selection {
+	Selection {
+	    gear:      self.gear,
+	    requested: self.requested(),
+	    mode:      self.mode,
+	    park_lock: self.park_lock,
+	}
+    }
+
+    /// Go straight to a gear - no interlocks, no confirmation, no questions
+    /// asked. For restoring the gear at boot.
+    pub fn restore(&mut self, gear: Gear) -> Selection {
+	self.engage(gear, false);
+	self.selection()
+    }
+
+    /// A button have been pressed (and released) at `now` - select its gear,
+    /// if the interlocks allow it. If they don't, we stay in the current gear.
+    pub fn press(&mut self, gear: Gear, inputs: &Inputs, now: u64) -> Result<Selection, Rejection> {
+	self.apply(Action::Select(gear), inputs, now)
+    }
+
+    /// Do whatever a gesture was mapped to.
+    pub fn apply(&mut self, action: Action, inputs: &Inputs, now: u64) -> Result<Selection, Rejection> {
+	let gear = match action {
+	    Action::Select(gear) => gear,
+	    Action::ParkLock     => Gear::P,
+	    Action::Mode(_)      => self.gear.ok_or(Rejection::NotInDrive)?,
+	};
+	self.interlock.check(self.gear, gear, inputs)?;
+
+	let park_lock = action == Action::ParkLock;
+	match action {
+	    Action::Mode(_) if gear != Gear::D => return Err(Rejection::NotInDrive),
+	    Action::Mode(mode) if mode == self.mode => self.mode = Mode::Normal,
+	    Action::Mode(mode) => self.mode = mode,
+	    _ if Some(gear) == self.gear => {
+		// Already there - forget about anything else we asked for.
+		self.requested = None;
+		self.park_lock |= park_lock;
+	    }
+	    _ if self.timeout.is_some() => self.requested = Some(Request { gear, park_lock, since: now }),
+	    _ => self.engage(gear, park_lock),
+	}
+
+	Ok(self.selection())
+    }
+
+    /// The transmission says it's in `gear`. If that's what we asked for,
+    /// it's now engaged.
+    pub fn confirm(&mut self, gear: Gear) -> Option<Selection> {
+	let request = self.requested.filter(|r| r.gear == gear)?;
+	self.engage(request.gear, request.park_lock);
+	Some(self.selection())
+    }
+
+    /// Time have passed. If the pending request timed out, it's dropped and
+    /// we're back in the gear we were in - returns what to show then.
+    pub fn tick(&mut self, now: u64) -> Option<Selection> {
+	if now < self.deadline()? {
+	    return None;
+	}
+
+	self.requested = None;
+	Some(self.selection())
+    }
+
+    fn engage(&mut self, gear: Gear, park_lock: bool) {
+	if Some(gear) != self.gear {
+	    // A new gear - back to normal.
+	    self.mode = Mode::Normal;
+	}
+	self.gear = Some(gear);
+	self.park_lock = park_lock;
+	self.requested = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interlock::DEFAULT_INTERLOCK;
+
+    const NOTHING: Inputs = Inputs { brake: false, standstill: false };
+    const BRAKE: Inputs = Inputs { brake: true, standstill: false };
+    const STANDSTILL: Inputs = Inputs { brake: false, standstill: true };
+    const BOTH: Inputs = Inputs { brake: true, standstill: true };
+
+    // Every change between P, N, R and D with the default interlocks, and
+    // what it takes - (from, to, brake, standstill).
+    const CHANGES: [(Gear, Gear, bool, bool); 16] = [
+	(Gear::P, Gear::P, false, false),
+	(Gear::P, Gear::N, true,  false),
+	(Gear::P, Gear::R, true,  true),
+	(Gear::P, Gear::D, true,  false),
+	(Gear::N, Gear::P, false, true),
+	(Gear::N, Gear::N, false, false),
+	(Gear::N, Gear::R, false, true),
+	(Gear::N, Gear::D, false, false),
+	(Gear::R, Gear::P, false, true),
+	(Gear::R, Gear::N, false, false),
+	(Gear::R, Gear::R, false, false),
+	(Gear::R, Gear::D, false, false),
+	(Gear::D, Gear::P, false, true),
+	(Gear::D, Gear::N, false, false),
+	(Gear::D, Gear::R, false, true),
+	(Gear::D, Gear::D, false, false),
+    ];
+
+    fn selector(gear: Gear) -> GearSelector {
+	let mut selector = GearSelector::new(DEFAULT_INTERLOCK);
+	selector.restore(gear);
+	selector
+    }
+
+    #[test]
+    fn every_change() {
+	for (from, to, brake, standstill) in CHANGES {
+	    for inputs in [NOTHING, BRAKE, STANDSTILL, BOTH] {
+		let mut selector = selector(from);
+		let result = selector.press(to, &inputs, 0);
+		let wanted = if brake && !inputs.brake {
+		    Err(Rejection::BrakeNotPressed)
+		} else if standstill && !inputs.standstill {
+		    Err(Rejection::NotAtStandstill)
+		} else {
+		    Ok(Selection { gear: Some(to), requested: None, mode: Mode::Normal, park_lock: false })
+		};
+		assert_eq!(result, wanted, "{} to {} with {:?}", from.name(), to.name(), inputs);
+
+		// Rejected, and we stay where we were.
+		let gear = if result.is_ok() { to } else { from };
+		assert_eq!(selector.gear(), Some(gear));
+	    }
+	}
+    }
+
+    #[test]
+    fn from_nothing() {
+	let mut selector = GearSelector::new(DEFAULT_INTERLOCK);
+	assert_eq!(selector.gear(), None);
+	assert_eq!(selector.press(Gear::R, &NOTHING, 0), Err(Rejection::NotAtStandstill));
+	assert_eq!(selector.press(Gear::D, &NOTHING, 0).map(|s| s.gear), Ok(Some(Gear::D)));
+    }
+
+    #[test]
+    fn leds() {
+	let selection = selector(Gear::D).selection();
+	assert_eq!(selection.led(Gear::D), LedStatus::On);
+	for gear in [Gear::P, Gear::N, Gear::R] {
+	    assert_eq!(selection.led(gear), LedStatus::Off);
+	}
+    }
+
+    #[test]
+    fn confirmed() {
+	let mut selector = GearSelector::new(DEFAULT_INTERLOCK).confirm_within(2000);
+	selector.restore(Gear::P);
+
+	let selection = selector.press(Gear::N, &BRAKE, 100).unwrap();
+	assert_eq!((selection.gear, selection.requested), (Some(Gear::P), Some(Gear::N)));
+	assert_eq!(selection.led(Gear::N), LedStatus::Blink(BLINK_MS));
+	assert_eq!(selection.led(Gear::P), LedStatus::On);
+	assert_eq!(selector.deadline(), Some(2100));
+
+	// Not what we asked for.
+	assert_eq!(selector.confirm(Gear::D), None);
+	assert_eq!(selector.tick(2099), None);
+
+	let selection = selector.confirm(Gear::N).unwrap();
+	assert_eq!((selection.gear, selection.requested), (Some(Gear::N), None));
+	assert_eq!(selector.deadline(), None);
+    }
+
+    #[test]
+    fn not_confirmed() {
+	let mut selector = GearSelector::new(DEFAULT_INTERLOCK).confirm_within(2000);
+	selector.restore(Gear::N);
+
+	selector.press(Gear::D, &NOTHING, 0).unwrap();
+	let selection = selector.tick(2000).unwrap();
+	assert_eq!((selection.gear, selection.requested), (Some(Gear::N), None));
+
+	// Too late now.
+	assert_eq!(selector.confirm(Gear::D), None);
+	assert_eq!(selector.gear(), Some(Gear::N));
+    }
+
+    #[test]
+    fn reselect_cancels_request() {
+	let mut selector = GearSelector::new(DEFAULT_INTERLOCK).confirm_within(2000);
+	selector.restore(Gear::N);
+
+	selector.press(Gear::D, &NOTHING, 0).unwrap();
+	let selection = selector.press(Gear::N, &NOTHING, 10).unwrap();
+	assert_eq!((selection.gear, selection.requested), (Some(Gear::N), None));
+	assert_eq!(selector.deadline(), None);
+    }
+
+    #[test]
+    fn park_lock() {
+	let mut selector = selector(Gear::N);
+	assert_eq!(selector.apply(Action::ParkLock, &NOTHING, 0), Err(Rejection::NotAtStandstill));
+
+	let selection = selector.apply(Action::ParkLock, &STANDSTILL, 0).unwrap();
+	assert_eq!((selection.gear, selection.park_lock), (Some(Gear::P), true));
+
+	// Already in P, still locked. Leaving P unlocks it.
+	assert!(selector.press(Gear::P, &NOTHING, 0).unwrap().park_lock);
+	assert!(!selector.press(Gear::N, &BRAKE, 0).unwrap().park_lock);
+    }
+
+    #[test]
+    fn modes() {
+	let mut selector = selector(Gear::N);
+	assert_eq!(selector.apply(Action::Mode(Mode::Sport), &NOTHING, 0), Err(Rejection::NotInDrive));
+
+	selector.press(Gear::D, &NOTHING, 0).unwrap();
+	assert_eq!(selector.apply(Action::Mode(Mode::Sport), &NOTHING, 0).unwrap().mode, Mode::Sport);
+	assert_eq!(selector.apply(Action::Mode(Mode::Manual), &NOTHING, 0).unwrap().mode, Mode::Manual);
+
+	// Again toggles it off.
+	assert_eq!(selector.apply(Action::Mode(Mode::Manual), &NOTHING, 0).unwrap().mode, Mode::Normal);
+
+	// And a new gear is always normal.
+	selector.apply(Action::Mode(Mode::Sport), &NOTHING, 0).unwrap();
+	assert_eq!(selector.press(Gear::N, &NOTHING, 0).unwrap().mode, Mode::Normal);
+    }
+
+    #[test]
+    fn names() {
+	for gear in Gear::ALL {
+	    assert_eq!(Gear::from_name(gear.name()), Some(gear));
+	    assert_eq!(Gear::from_u8(gear as u8), Some(gear));
+	}
+	assert_eq!(Gear::from_name("d"), Some(Gear::D));
+	assert_eq!(Gear::from_name("X"), None);
+	assert_eq!(Gear::from_u8(Gear::COUNT as u8), None);
+    }
+}
@@ -0,0 +1,267 @@
+//! Short, long, double and held-repeat presses.
+//!
+//! The recognizer doesn't know anything about time by itself, it's fed the
+//! press and release edges with their timestamps (in ms) and polled when
+//! `deadline()` have passed. That way it works just as well with
+//! `embassy_time::Instant` as with made up timestamps on the host.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Gesture {
+    /// Pressed and released, no second press followed.
+    Short,
+
+    /// Held for at least the long threshold, sent on release with the
+    /// total time (in ms) the button was held.
+    Long(u64),
+
+    /// Pressed again within the double-press window.
+    Double,
+
+    /// Still held - sent every repeat interval once the long threshold
+    /// have passed, with a counter starting at 1.
+    Repeat(u16),
+}
+
+/// Gesture, without any of the data.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GestureKind { Short, Long, Double, Repeat }
+
+impl Gesture {
+    pub fn kind(self) -> GestureKind {
+	match self {
+	    Gesture::Short     => GestureKind::Short,
+	    Gesture::Long(_)   => GestureKind::Long,
+	    Gesture::Double    => GestureKind::Double,
+	    Gesture::Repeat(_) => GestureKind::Repeat,
+	}
+    }
+}
+
+impl GestureKind {
+    pub fn name(self) -> &'static str {
+	match self {
+	    GestureKind::Short  => "short",
+	    GestureKind::Long   => "long",
+	    GestureKind::Double => "double",
+	    GestureKind::Repeat => "repeat",
+	}
+    }
+}
+
+/// All times in ms.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Thresholds {
+    /// Held at least this long is a long press.
+    pub long_ms: u64,
+
+    /// A second press within this long after release is a double press.
+    /// Zero disables double presses, which means a short press is reported
+    /// right on release instead of after the window.
+    pub double_ms: u64,
+
+    /// Interval between held-repeats.
+    pub repeat_ms: u64,
+}
+
+pub const DEFAULT_THRESHOLDS: Thresholds = Thresholds {
+    long_ms:   1000,
+    double_ms: 250,
+    repeat_ms: 500,
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum State {
+    Idle,
+    Pressed { at: u64, repeats: u16 },
+    Released { at: u64 },
+    SecondPress,
+}
+
+#[derive(Debug)]
+pub struct GestureRecognizer {
+    thresholds: Thresholds,
+    state:      State,
+}
+
+impl GestureRecognizer {
+    pub const fn new(thresholds: Thresholds) -> Self {
+	Self { thresholds, state: State::Idle }
+    }
+
+    /// When `poll()` should be called next, if at all.
+    pub fn deadline(&self) -> Option<u64> {
+	let t = &self.thresholds;
+	match self.state {
+	    State::Pressed { at, repeats } => Some(at + t.long_ms + repeats as u64 * t.repeat_ms),
+	    State::Released { at }         => Some(at + t.double_ms),
+	    _ => None,
+	}
+    }
+
+    /// The button was pressed at `now`.
+    pub fn press(&mut self, now: u64) -> Option<Gesture> {
+	match self.state {
+	    State::Released { at } if now - at < self.thresholds.double_ms => {
+		self.state = State::SecondPress;
+		Some(Gesture::Double)
+	    }
+	    State::Released { .. } => {
+		// Missed the poll - the previous press was a short one.
+		self.state = State::Pressed { at: now, repeats: 0 };
+		Some(Gesture::Short)
+	    }
+	    _ => {
+		self.state = State::Pressed { at: now, repeats: 0 };
+		None
+	    }
+	}
+    }
+
+    /// The button was released at `now`.
+    pub fn release(&mut self, now: u64) -> Option<Gesture> {
+	match self.state {
+	    State::Pressed { at, .. } => {
+		let held = now - at;
+		if held >= self.thresholds.long_ms {
+		    self.state = State::Idle;
+		    Some(Gesture::Long(held))
+		} else if self.thresholds.double_ms == 0 {
+		    self.state = State::Idle;
+		    Some(Gesture::Short)
+		} else {
+		    self.state = State::Released { at: now };
+		    None
+		}
+	    }
+	    _ => {
+		self.state = State::Idle;
+		None
+	    }
+	}
+    }
+
+    /// Time have passed, see if that means something.
+    pub fn poll(&mut self, now: u64) -> Option<Gesture> {
+	let deadline = self.deadline()?;
+	if now < deadline {
+	    return None;
+	}
+
+	match self.state {
+	    State::Pressed { at, repeats } => {
+		self.state = State::Pressed { at, repeats: repeats + 1 };
+		Some(Gesture::Repeat(repeats + 1))
+	    }
+	    State::Released { .. } => {
+		self.state = State::Idle;
+		Some(Gesture::Short)
+	    }
+	    _ => None,
+	}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::vec::Vec;
+
+    use super::*;
+
+    // Presses (true) and releases at the given times, with polls at every
+    // deadline up to `until`. Returns the gestures, and when.
+    fn run(thresholds: Thresholds, edges: &[(u64, bool)], until: u64) -> Vec<(u64, Gesture)> {
+	let mut recognizer = GestureRecognizer::new(thresholds);
+	let mut gestures = Vec::new();
+	let poll = |recognizer: &mut GestureRecognizer, gestures: &mut Vec<_>, upto: u64| {
+	    while let Some(deadline) = recognizer.deadline().filter(|d| *d <= upto) {
+		gestures.extend(recognizer.poll(deadline).map(|g| (deadline, g)));
+	    }
+	};
+	for (at, pressed) in edges {
+	    poll(&mut recognizer, &mut gestures, *at);
+	    let gesture = if *pressed { recognizer.press(*at) } else { recognizer.release(*at) };
+	    gestures.extend(gesture.map(|g| (*at, g)));
+	}
+	poll(&mut recognizer, &mut gestures, until);
+	gestures
+    }
+
+    #[test]
+    fn short() {
+	// Reported when the double-press window is over.
+	assert_eq!(run(DEFAULT_THRESHOLDS, &[(0, true), (100, false)], 5000), [(350, Gesture::Short)]);
+    }
+
+    #[test]
+    fn short_without_double() {
+	let thresholds = Thresholds { double_ms: 0, ..DEFAULT_THRESHOLDS };
+	assert_eq!(run(thresholds, &[(0, true), (100, false)], 5000), [(100, Gesture::Short)]);
+    }
+
+    #[test]
+    fn long() {
+	let gestures = run(DEFAULT_THRESHOLDS, &[(0, true), (1200, false)], 5000);
+	assert_eq!(gestures, [(1000, Gesture::Repeat(1)), (1200, Gesture::Long(1200))]);
+    }
+
+    #[test]
+    fn long_threshold() {
+	// Just short of it is a short press, right on it a long one.
+	assert_eq!(run(DEFAULT_THRESHOLDS, &[(0, true), (999, false)], 5000), [(1249, Gesture::Short)]);
+	let gestures = run(DEFAULT_THRESHOLDS, &[(0, true), (1000, false)], 5000);
+	assert_eq!(gestures.last(), Some(&(1000, Gesture::Long(1000))));
+    }
+
+    #[test]
+    fn repeat() {
+	let gestures = run(DEFAULT_THRESHOLDS, &[(0, true), (2100, false)], 5000);
+	assert_eq!(gestures, [
+	    (1000, Gesture::Repeat(1)),
+	    (1500, Gesture::Repeat(2)),
+	    (2000, Gesture::Repeat(3)),
+	    (2100, Gesture::Long(2100)),
+	]);
+    }
+
+    #[test]
+    fn double() {
+	// Nothing more for the second release.
+	let gestures = run(DEFAULT_THRESHOLDS, &[(0, true), (80, false), (200, true), (280, false)], 5000);
+	assert_eq!(gestures, [(200, Gesture::Double)]);
+    }
+
+    #[test]
+    fn two_shorts() {
+	// Second press after the window - two short ones.
+	let gestures = run(DEFAULT_THRESHOLDS, &[(0, true), (80, false), (400, true), (480, false)], 5000);
+	assert_eq!(gestures, [(330, Gesture::Short), (730, Gesture::Short)]);
+    }
+
+    #[test]
+    fn missed_poll() {
+	// Nobody polled at the end of the window, so the short press comes
+	// with the next one.
+	let mut recognizer = GestureRecognizer::new(DEFAULT_THRESHOLDS);
+	assert_eq!(recognizer.press(0), None);
+	assert_eq!(recognizer.release(80), None);
+	assert_eq!(recognizer.press(1000), Some(Gesture::Short));
+	assert_eq!(recognizer.release(1080), None);
+	assert_eq!(recognizer.poll(1330), Some(Gesture::Short));
+    }
+
+    #[test]
+    fn early_poll() {
+	let mut recognizer = GestureRecognizer::new(DEFAULT_THRESHOLDS);
+	recognizer.press(0);
+	assert_eq!(recognizer.poll(999), None);
+	assert_eq!(recognizer.deadline(), Some(1000));
+    }
+
+    #[test]
+    fn release_without_press() {
+	let mut recognizer = GestureRecognizer::new(DEFAULT_THRESHOLDS);
+	assert_eq!(recognizer.release(10), None);
+	assert_eq!(recognizer.deadline(), None);
+    }
+}
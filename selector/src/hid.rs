@@ -0,0 +1,194 @@
+//! USB HID, for using the selector as a shifter in driving simulators.
+//!
+//! Either a joystick with one button per gear - the button of the gear
+//! we're in is held, all the others released, so games see it as an
+//! H-pattern/sequential shifter - or a keyboard that taps a key whenever
+//! the gear changes.
+//!
+//! The gear reported is `Selection::target()`, like on the CAN bus.
+
+use crate::gear::Gear;
+
+/// A joystick with 16 buttons - button `gear as u8 + 1` is the gear.
+pub const JOYSTICK_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01,         // Usage Page (Generic Desktop)
+    0x09, 0x04,         // Usage (Joystick)
+    0xA1, 0x01,         // Collection (Application)
+    0x05, 0x09,         //   Usage Page (Button)
+    0x19, 0x01,         //   Usage Minimum (1)
+    0x29, 0x10,         //   Usage Maximum (16)
+    0x15, 0x00,         //   Logical Minimum (0)
+    0x25, 0x01,         //   Logical Maximum (1)
+    0x75, 0x01,         //   Report Size (1)
+    0x95, 0x10,         //   Report Count (16)
+    0x81, 0x02,         //   Input (Data, Variable, Absolute)
+    0xC0,               // End Collection
+];
+
+pub const JOYSTICK_REPORT_SIZE: usize = 2;
+
+/// Which buttons are held - only the one for `gear`, `Selection::target()`.
+pub fn joystick_report(gear: Option<Gear>) -> [u8; JOYSTICK_REPORT_SIZE] {
+    let buttons = match gear {
+	Some(gear) => 1u16 << gear as u8,
+	None => 0,
+    };
+    buttons.to_le_bytes()
+}
+
+/// A boot protocol keyboard - modifiers, a reserved byte and up to six
+/// keys. No LEDs.
+pub const KEYBOARD_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01,         // Usage Page (Generic Desktop)
+    0x09, 0x06,         // Usage (Keyboard)
+    0xA1, 0x01,         // Collection (Application)
+    0x05, 0x07,         //   Usage Page (Keyboard)
+    0x19, 0xE0,         //   Usage Minimum (Left Control)
+    0x29, 0xE7,         //   Usage Maximum (Right GUI)
+    0x15, 0x00,         //   Logical Minimum (0)
+    0x25, 0x01,         //   Logical Maximum (1)
+    0x75, 0x01,         //   Report Size (1)
+    0x95, 0x08,         //   Report Count (8)
+    0x81, 0x02,         //   Input (Data, Variable, Absolute) - modifiers
+    0x95, 0x01,         //   Report Count (1)
+    0x75, 0x08,         //   Report Size (8)
+    0x81, 0x01,         //   Input (Constant) - reserved
+    0x95, 0x06,         //   Report Count (6)
+    0x75, 0x08,         //   Report Size (8)
+    0x15, 0x00,         //   Logical Minimum (0)
+    0x25, 0x65,         //   Logical Maximum (101)
+    0x05, 0x07,         //   Usage Page (Keyboard)
+    0x19, 0x00,         //   Usage Minimum (0)
+    0x29, 0x65,         //   Usage Maximum (101)
+    0x81, 0x00,         //   Input (Data, Array) - keys
+    0xC0,               // End Collection
+];
+
+pub const KEYBOARD_REPORT_SIZE: usize = 8;
+
+/// Which key to send for which gear.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct KeyMap {
+    /// HID usage codes, indexed by `gear as usize`. `0` sends nothing.
+    pub keys: [u8; Gear::COUNT],
+
+    /// Held together with the key (bit 0 left control, 1 left shift, 2
+    /// left alt, 3 left GUI, 4-7 the same on the right).
+    pub modifiers: u8,
+}
+
+/// The gear's letter (or digit), no modifiers.
+pub const DEFAULT_KEYMAP: KeyMap = KeyMap {
+    //    P     N     R     D     L     2     1     S     M     B
+    keys: [0x13, 0x11, 0x15, 0x07, 0x0F, 0x1F, 0x1E, 0x16, 0x10, 0x05],
+    modifiers: 0,
+};
+
+impl KeyMap {
+    /// The key for `gear` pressed, or everything released for `None`.
+    pub fn report(&self, gear: Option<Gear>) -> [u8; KEYBOARD_REPORT_SIZE] {
+	let mut report = [0; KEYBOARD_REPORT_SIZE];
+	if let Some(key) = gear.map(|gear| self.keys[gear as usize]).filter(|key| *key != 0) {
+	    report[0] = self.modifiers;
+	    report[2] = key;
+	}
+	report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Walks the short items of a report descriptor, `(prefix, data)` with
+    // the size bits masked off the prefix.
+    fn items(mut descriptor: &[u8]) -> impl Iterator<Item = (u8, u32)> + '_ {
+	core::iter::from_fn(move || {
+	    let (&prefix, rest) = descriptor.split_first()?;
+	    let size = [0, 1, 2, 4][(prefix & 3) as usize];
+	    let (data, rest) = rest.split_at(size);
+	    descriptor = rest;
+	    let data = data.iter().rev().fold(0, |value, byte| value << 8 | *byte as u32);
+	    Some((prefix & !3, data))
+	})
+    }
+
+    // Bytes per input report, and the collections balanced.
+    fn input_size(descriptor: &[u8]) -> usize {
+	let (mut size, mut count, mut bits, mut depth) = (0, 0, 0, 0);
+	for (item, data) in items(descriptor) {
+	    match item {
+		0x74 => size = data,
+		0x94 => count = data,
+		0x80 => bits += size * count,
+		0xA0 => depth += 1,
+		0xC0 => depth -= 1,
+		_ => (),
+	    }
+	}
+	assert_eq!(depth, 0);
+	assert_eq!(bits % 8, 0);
+	bits as usize / 8
+    }
+
+    // The data of the first `item`.
+    fn field(descriptor: &[u8], item: u8) -> u32 {
+	items(descriptor).find(|(i, _)| *i == item).unwrap().1
+    }
+
+    #[test]
+    fn joystick_descriptor() {
+	assert_eq!(JOYSTICK_DESCRIPTOR.len(), 23);
+	assert_eq!(input_size(JOYSTICK_DESCRIPTOR), JOYSTICK_REPORT_SIZE);
+	assert_eq!(field(JOYSTICK_DESCRIPTOR, 0x08), 0x04);
+	assert_eq!(field(JOYSTICK_DESCRIPTOR, 0x18), 1);
+
+	// A button for every gear.
+	assert!(field(JOYSTICK_DESCRIPTOR, 0x28) as usize >= Gear::COUNT);
+    }
+
+    #[test]
+    fn joystick_reports() {
+	assert_eq!(joystick_report(None), [0, 0]);
+	assert_eq!(joystick_report(Some(Gear::P)), [0x01, 0x00]);
+	assert_eq!(joystick_report(Some(Gear::D)), [0x08, 0x00]);
+	assert_eq!(joystick_report(Some(Gear::S)), [0x80, 0x00]);
+	assert_eq!(joystick_report(Some(Gear::B)), [0x00, 0x02]);
+	for gear in Gear::ALL {
+	    assert_eq!(u16::from_le_bytes(joystick_report(Some(gear))), 1 << gear as u8);
+	}
+    }
+
+    #[test]
+    fn keyboard_descriptor() {
+	assert_eq!(KEYBOARD_DESCRIPTOR.len(), 45);
+	assert_eq!(input_size(KEYBOARD_DESCRIPTOR), KEYBOARD_REPORT_SIZE);
+	assert_eq!(field(KEYBOARD_DESCRIPTOR, 0x08), 0x06);
+
+	// Every default key is within the usages.
+	let max = items(KEYBOARD_DESCRIPTOR).filter(|(i, _)| *i == 0x28).last().unwrap().1;
+	assert!(DEFAULT_KEYMAP.keys.iter().all(|key| (*key as u32) <= max));
+    }
+
+    #[test]
+    fn keyboard_reports() {
+	for (gear, key) in [
+	    (Gear::P, 0x13), (Gear::N, 0x11), (Gear::R, 0x15), (Gear::D, 0x07), (Gear::L, 0x0F),
+	    (Gear::Two, 0x1F), (Gear::One, 0x1E), (Gear::S, 0x16), (Gear::M, 0x10), (Gear::B, 0x05),
+	] {
+	    assert_eq!(DEFAULT_KEYMAP.report(Some(gear)), [0, 0, key, 0, 0, 0, 0, 0]);
+	}
+	assert_eq!(DEFAULT_KEYMAP.report(None), [0; KEYBOARD_REPORT_SIZE]);
+    }
+
+    #[test]
+    fn keyboard_modifiers() {
+	let mut keymap = KeyMap { modifiers: 0x05, ..DEFAULT_KEYMAP };
+	assert_eq!(keymap.report(Some(Gear::R)), [0x05, 0, 0x15, 0, 0, 0, 0, 0]);
+
+	// No key, no modifiers either.
+	keymap.keys[Gear::R as usize] = 0;
+	assert_eq!(keymap.report(Some(Gear::R)), [0; KEYBOARD_REPORT_SIZE]);
+	assert_eq!(keymap.report(None), [0; KEYBOARD_REPORT_SIZE]);
+    }
+}
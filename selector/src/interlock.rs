@@ -0,0 +1,169 @@
+//! Gear change interlocks.
+//!
+//! Not every gear change is allowed - we don't want to go from D straight
+//! into R (or P) at speed. The rules are plain data, so different cars can
+//! have different interlocks.
+
+use crate::gear::Gear;
+
+/// What the rest of the car tells us.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Inputs {
+    /// The brake pedal is pressed.
+    pub brake: bool,
+
+    /// Vehicle speed is below the standstill threshold.
+    pub standstill: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Rejection {
+    BrakeNotPressed,
+    NotAtStandstill,
+    NotInDrive,
+
+    /// We're in the safe state after a fault - no gear changes.
+    SafeState,
+}
+
+impl Rejection {
+    /// The reverse of `rejection as u8`.
+    pub fn from_u8(value: u8) -> Option<Self> {
+	[Rejection::BrakeNotPressed, Rejection::NotAtStandstill, Rejection::NotInDrive, Rejection::SafeState]
+	    .get(value as usize).copied()
+    }
+
+    pub fn reason(self) -> &'static str {
+	match self {
+	    Rejection::BrakeNotPressed => "brake not pressed",
+	    Rejection::NotAtStandstill => "vehicle not at standstill",
+	    Rejection::NotInDrive      => "only available in D",
+	    Rejection::SafeState       => "in the safe state after a fault",
+	}
+    }
+}
+
+/// The interlock rules.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Interlock {
+    /// Leaving any of these gears requires the brake to be pressed.
+    pub brake_to_leave: &'static [Gear],
+
+    /// Selecting any of these gears requires the brake to be pressed.
+    pub brake_to_enter: &'static [Gear],
+
+    /// These gears can only be selected at standstill.
+    pub standstill_to_enter: &'static [Gear],
+}
+
+/// Brake to leave P, and P/R only from standstill.
+pub const DEFAULT_INTERLOCK: Interlock = Interlock {
+    brake_to_leave:      &[Gear::P],
+    brake_to_enter:      &[],
+    standstill_to_enter: &[Gear::P, Gear::R],
+};
+
+/// No interlocks at all - any gear, any time.
+pub const NO_INTERLOCK: Interlock = Interlock {
+    brake_to_leave:      &[],
+    brake_to_enter:      &[],
+    standstill_to_enter: &[],
+};
+
+impl Interlock {
+    /// Is going from `from` (`None` if nothing is selected yet) to `to` allowed?
+    /// Re-selecting the gear we're already in always is.
+    pub fn check(&self, from: Option<Gear>, to: Gear, inputs: &Inputs) -> Result<(), Rejection> {
+	if from == Some(to) {
+	    return Ok(());
+	}
+
+	let leaving = from.is_some_and(|from| self.brake_to_leave.contains(&from));
+	if (leaving || self.brake_to_enter.contains(&to)) && !inputs.brake {
+	    return Err(Rejection::BrakeNotPressed);
+	}
+
+	if self.standstill_to_enter.contains(&to) && !inputs.standstill {
+	    return Err(Rejection::NotAtStandstill);
+	}
+
+	Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOTHING: Inputs = Inputs { brake: false, standstill: false };
+    const BRAKE: Inputs = Inputs { brake: true, standstill: false };
+    const STANDSTILL: Inputs = Inputs { brake: false, standstill: true };
+    const BOTH: Inputs = Inputs { brake: true, standstill: true };
+
+    #[test]
+    fn brake_to_leave_p() {
+	let check = |to, inputs| DEFAULT_INTERLOCK.check(Some(Gear::P), to, &inputs);
+	assert_eq!(check(Gear::N, NOTHING), Err(Rejection::BrakeNotPressed));
+	assert_eq!(check(Gear::D, STANDSTILL), Err(Rejection::BrakeNotPressed));
+	assert_eq!(check(Gear::N, BRAKE), Ok(()));
+	assert_eq!(check(Gear::D, BRAKE), Ok(()));
+    }
+
+    #[test]
+    fn standstill_for_p_and_r() {
+	for to in [Gear::P, Gear::R] {
+	    assert_eq!(DEFAULT_INTERLOCK.check(Some(Gear::D), to, &BRAKE), Err(Rejection::NotAtStandstill));
+	    assert_eq!(DEFAULT_INTERLOCK.check(Some(Gear::D), to, &STANDSTILL), Ok(()));
+	}
+	assert_eq!(DEFAULT_INTERLOCK.check(Some(Gear::D), Gear::N, &NOTHING), Ok(()));
+    }
+
+    #[test]
+    fn brake_before_standstill() {
+	// Both missing - the brake is what's reported.
+	assert_eq!(DEFAULT_INTERLOCK.check(Some(Gear::P), Gear::R, &NOTHING), Err(Rejection::BrakeNotPressed));
+	assert_eq!(DEFAULT_INTERLOCK.check(Some(Gear::P), Gear::R, &BRAKE), Err(Rejection::NotAtStandstill));
+	assert_eq!(DEFAULT_INTERLOCK.check(Some(Gear::P), Gear::R, &BOTH), Ok(()));
+    }
+
+    #[test]
+    fn same_gear() {
+	for gear in Gear::ALL {
+	    assert_eq!(DEFAULT_INTERLOCK.check(Some(gear), gear, &NOTHING), Ok(()));
+	}
+    }
+
+    #[test]
+    fn nothing_selected() {
+	// Nothing to leave, so no brake needed.
+	assert_eq!(DEFAULT_INTERLOCK.check(None, Gear::P, &STANDSTILL), Ok(()));
+	assert_eq!(DEFAULT_INTERLOCK.check(None, Gear::R, &NOTHING), Err(Rejection::NotAtStandstill));
+    }
+
+    #[test]
+    fn brake_to_enter() {
+	let interlock = Interlock { brake_to_enter: &[Gear::D], ..NO_INTERLOCK };
+	assert_eq!(interlock.check(Some(Gear::N), Gear::D, &NOTHING), Err(Rejection::BrakeNotPressed));
+	assert_eq!(interlock.check(Some(Gear::N), Gear::D, &BRAKE), Ok(()));
+	assert_eq!(interlock.check(Some(Gear::N), Gear::R, &NOTHING), Ok(()));
+    }
+
+    #[test]
+    fn no_interlock() {
+	for from in Gear::ALL {
+	    for to in Gear::ALL {
+		assert_eq!(NO_INTERLOCK.check(Some(from), to, &NOTHING), Ok(()));
+	    }
+	}
+    }
+
+    #[test]
+    fn rejection_codes() {
+	let all = [Rejection::BrakeNotPressed, Rejection::NotAtStandstill, Rejection::NotInDrive, Rejection::SafeState];
+	for rejection in all {
+	    assert_eq!(Rejection::from_u8(rejection as u8), Some(rejection));
+	}
+	assert_eq!(Rejection::from_u8(4), None);
+    }
+}
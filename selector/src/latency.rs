@@ -0,0 +1,195 @@
+//! How long it takes from a button to the LEDs - a histogram of times in
+//! µs, small enough to keep one per button.
+//!
+//! The buckets double in width every four of them, so any time is within
+//! 25% of its bucket's start - plenty for a percentile, without keeping
+//! every sample. Min, max and the average are exact.
+
+/// Enough buckets for any `u32`.
+pub const BUCKETS: usize = 124;
+
+/// What a histogram adds up to, in µs. All zero if it's empty.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct LatencyStats {
+    pub count: u32,
+    pub min:   u32,
+    pub avg:   u32,
+    pub max:   u32,
+    pub p99:   u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    buckets: [u32; BUCKETS],
+    count:   u32,
+    sum:     u64,
+    min:     u32,
+    max:     u32,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+impl Histogram {
+    pub const fn new() -> Self {
+	Self { buckets: [0; BUCKETS], count: 0, sum: 0, min: u32::MAX, max: 0 }
+    }
+
+    pub fn record(&mut self, us: u32) {
+	let bucket = &mut self.buckets[bucket(us)];
+	*bucket = bucket.saturating_add(1);
+	self.count = self.count.saturating_add(1);
+	self.sum += us as u64;
+	self.min = self.min.min(us);
+	self.max = self.max.max(us);
+    }
+
+    pub fn clear(&mut self) {
+	*self = Self::new();
+    }
+
+    pub fn count(&self) -> u32 {
+	self.count
+    }
+
+    /// The time `percent` of the samples are at or below - the end of the
+    /// bucket it's in, but never more than the max.
+    pub fn percentile(&self, percent: u32) -> u32 {
+	if self.count == 0 {
+	    return 0;
+	}
+	// Rounded up, so the 99th of 10 samples is the last one.
+	let rank = (self.count as u64 * percent as u64).div_ceil(100).max(1);
+	let mut seen = 0;
+	for (n, count) in self.buckets.iter().enumerate() {
+	    seen += *count as u64;
+	    if seen >= rank {
+		return end(n).min(self.max);
+	    }
+	}
+	self.max
+    }
+
+    pub fn stats(&self) -> LatencyStats {
+	if self.count == 0 {
+	    return LatencyStats::default();
+	}
+	LatencyStats {
+	    count: self.count,
+	    min:   self.min,
+	    avg:   (self.sum / self.count as u64) as u32,
+	    max:   self.max,
+	    p99:   self.percentile(99),
+	}
+    }
+}
+
+// 0-3 get a bucket each, after that it's four per power of two.
+fn bucket(us: u32) -> usize {
+    if us < 4 {
+	return us as usize;
+    }
+    let power = 31 - us.leading_zeros();
+    let quarter = (us >> (power - 2)) & 3;
+    (4 * (power - 1) + quarter) as usize
+}
+
+// The last time in bucket `n`.
+fn end(n: usize) -> u32 {
+    if n < 4 {
+	return n as u32;
+    }
+    let power = n as u32 / 4 + 1;
+    let start = (4 + n as u32 % 4) << (power - 2);
+    start.saturating_add((1 << (power - 2)) - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty() {
+	let histogram = Histogram::new();
+	assert_eq!(histogram.count(), 0);
+	assert_eq!(histogram.percentile(99), 0);
+	assert_eq!(histogram.stats(), LatencyStats::default());
+    }
+
+    #[test]
+    fn single() {
+	let mut histogram = Histogram::new();
+	histogram.record(1234);
+	let stats = LatencyStats { count: 1, min: 1234, avg: 1234, max: 1234, p99: 1234 };
+	assert_eq!(histogram.stats(), stats);
+
+	histogram.clear();
+	assert_eq!(histogram.stats(), LatencyStats::default());
+    }
+
+    #[test]
+    fn max() {
+	assert_eq!(bucket(u32::MAX), BUCKETS - 1);
+	assert_eq!(end(BUCKETS - 1), u32::MAX);
+
+	let mut histogram = Histogram::new();
+	histogram.record(u32::MAX);
+	histogram.record(u32::MAX);
+	let stats = LatencyStats { count: 2, min: u32::MAX, avg: u32::MAX, max: u32::MAX, p99: u32::MAX };
+	assert_eq!(histogram.stats(), stats);
+    }
+
+    #[test]
+    fn buckets() {
+	// One each up to 7, then 8-9 is the first that's two wide.
+	for us in 0..8 {
+	    assert_eq!(bucket(us), us as usize);
+	    assert_eq!(end(us as usize), us);
+	}
+	assert_eq!(bucket(8), 8);
+	assert_eq!(bucket(9), 8);
+	assert_eq!(bucket(10), 9);
+	assert_eq!(end(8), 9);
+
+	// Every bucket starts right after the last one ends, and is within
+	// 25% of any time in it.
+	for n in 1..BUCKETS {
+	    let start = end(n - 1) + 1;
+	    assert_eq!(bucket(start), n);
+	    assert_eq!(bucket(end(n)), n);
+	    assert!(end(n) - start <= start / 4, "bucket {}", n);
+	}
+    }
+
+    #[test]
+    fn boundary() {
+	// On the last time of a bucket, so that's what the percentile says.
+	let mut histogram = Histogram::new();
+	histogram.record(1023);
+	histogram.record(2000);
+	assert_eq!(histogram.percentile(50), 1023);
+	assert_eq!(histogram.percentile(100), 2000);
+    }
+
+    #[test]
+    fn p99() {
+	// 99 of 100 in 896-1023 - the p99 is the end of that bucket, however
+	// slow the last one is.
+	let mut histogram = Histogram::new();
+	(0..99).for_each(|_| histogram.record(1000));
+	histogram.record(50_000);
+	let stats = LatencyStats { count: 100, min: 1000, avg: 1490, max: 50_000, p99: 1023 };
+	assert_eq!(histogram.stats(), stats);
+
+	// Two slow ones out of 100 and it's the slow one - never past the max.
+	histogram.clear();
+	(0..98).for_each(|_| histogram.record(1000));
+	histogram.record(5000);
+	histogram.record(5000);
+	assert_eq!(histogram.percentile(99), 5000);
+	assert_eq!(histogram.percentile(98), 1023);
+    }
+}
@@ -0,0 +1,184 @@
+//! What a single gear LED does.
+//!
+//! The LED task is told what to do with `LedStatus` commands, and the `Led`
+//! works out the level (0-255) that gives at any point in time, and when
+//! it next changes. All times are in ms.
+
+/// Default blink rate - on and off this long each.
+pub const BLINK_MS: u32 = 250;
+
+// How often to update while fading.
+const FADE_STEP_MS: u64 = 10;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LedStatus {
+    On,
+    Off,
+
+    /// Blink, on and off this many ms each.
+    Blink(u32),
+
+    /// How bright "on" is (0-255). Doesn't turn the LED on or off.
+    Brightness(u8),
+
+    /// How bright "off" is (0-255) - a dim backlight for the gears that
+    /// aren't selected.
+    Backlight(u8),
+
+    /// Fade on (or off) over this many ms.
+    FadeOn(u32),
+    FadeOff(u32),
+
+    /// Completely off, backlight too, until told otherwise. For when we're
+    /// asleep.
+    Dark,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Effect {
+    Dark,
+    Steady(bool),
+    Blink { period: u64, start: u64 },
+    Fade { from: u8, on: bool, start: u64, ms: u64 },
+}
+
+#[derive(Debug)]
+pub struct Led {
+    brightness: u8,
+    backlight:  u8,
+    effect:     Effect,
+}
+
+impl Default for Led {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+impl Led {
+    /// Off, full brightness, no backlight.
+    pub const fn new() -> Self {
+	Self { brightness: 255, backlight: 0, effect: Effect::Steady(false) }
+    }
+
+    pub fn command(&mut self, status: LedStatus, now: u64) {
+	self.effect = match status {
+	    LedStatus::On  => Effect::Steady(true),
+	    LedStatus::Off => Effect::Steady(false),
+	    LedStatus::Blink(ms) => Effect::Blink { period: ms.max(1) as u64, start: now },
+	    LedStatus::Brightness(brightness) => {
+		self.brightness = brightness;
+		self.effect
+	    }
+	    LedStatus::Backlight(backlight) => {
+		self.backlight = backlight;
+		self.effect
+	    }
+	    LedStatus::FadeOn(ms)  => Effect::Fade { from: self.level(now), on: true, start: now, ms: ms as u64 },
+	    LedStatus::FadeOff(ms) => Effect::Fade { from: self.level(now), on: false, start: now, ms: ms as u64 },
+	    LedStatus::Dark => Effect::Dark,
+	};
+    }
+
+    /// Level at `now`, 0 (completely off) to 255.
+    pub fn level(&self, now: u64) -> u8 {
+	match self.effect {
+	    Effect::Dark => 0,
+	    Effect::Steady(on) => self.on_level(on),
+	    Effect::Blink { period, start } => self.on_level(((now - start) / period) & 1 == 0),
+	    Effect::Fade { from, on, start, ms } => {
+		let to = self.on_level(on);
+		let t = now - start;
+		if t >= ms {
+		    return to;
+		}
+		(from as i64 + (to as i64 - from as i64) * t as i64 / ms as i64) as u8
+	    }
+	}
+    }
+
+    /// When the level changes next, `None` if not until the next command.
+    pub fn next_update(&self, now: u64) -> Option<u64> {
+	match self.effect {
+	    Effect::Dark | Effect::Steady(_) => None,
+	    Effect::Blink { period, start } => Some(now + period - (now - start) % period),
+	    Effect::Fade { start, ms, .. } if now < start + ms => Some((now + FADE_STEP_MS).min(start + ms)),
+	    Effect::Fade { .. } => None,
+	}
+    }
+
+    fn on_level(&self, on: bool) -> u8 {
+	if on { self.brightness } else { self.backlight }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn on_off() {
+	let mut led = Led::new();
+	assert_eq!(led.level(0), 0);
+	led.command(LedStatus::On, 0);
+	assert_eq!(led.level(0), 255);
+	assert_eq!(led.next_update(0), None);
+	led.command(LedStatus::Off, 10);
+	assert_eq!(led.level(10), 0);
+    }
+
+    #[test]
+    fn brightness() {
+	let mut led = Led::new();
+	led.command(LedStatus::On, 0);
+	led.command(LedStatus::Brightness(100), 0);
+	led.command(LedStatus::Backlight(10), 0);
+	assert_eq!(led.level(0), 100);
+	led.command(LedStatus::Off, 0);
+	assert_eq!(led.level(0), 10);
+    }
+
+    #[test]
+    fn blink() {
+	let mut led = Led::new();
+	led.command(LedStatus::Blink(250), 1000);
+	assert_eq!(led.level(1000), 255);
+	assert_eq!(led.level(1249), 255);
+	assert_eq!(led.level(1250), 0);
+	assert_eq!(led.level(1500), 255);
+	assert_eq!(led.next_update(1000), Some(1250));
+	assert_eq!(led.next_update(1300), Some(1500));
+    }
+
+    #[test]
+    fn fade() {
+	let mut led = Led::new();
+	led.command(LedStatus::FadeOn(100), 0);
+	assert_eq!(led.level(0), 0);
+	assert_eq!(led.level(50), 127);
+	assert_eq!(led.level(100), 255);
+	assert_eq!(led.next_update(0), Some(FADE_STEP_MS));
+	assert_eq!(led.next_update(95), Some(100));
+	assert_eq!(led.next_update(100), None);
+
+	// From wherever it is now.
+	led.command(LedStatus::FadeOff(100), 150);
+	assert_eq!(led.level(200), 128);
+    }
+
+    #[test]
+    fn dark() {
+	let mut led = Led::new();
+	led.command(LedStatus::Backlight(10), 0);
+	led.command(LedStatus::Blink(250), 0);
+	led.command(LedStatus::Dark, 0);
+	assert_eq!(led.level(0), 0);
+	assert_eq!(led.next_update(0), None);
+
+	// Brightness doesn't wake it up.
+	led.command(LedStatus::Brightness(50), 0);
+	assert_eq!(led.level(0), 0);
+	led.command(LedStatus::On, 0);
+	assert_eq!(led.level(0), 50);
+    }
+}
@@ -0,0 +1,54 @@
+//! Hardware independent logic for the gear selector.
+//!
+//! Everything in here is `no_std` and knows nothing about the RP2040, so it
+//! builds both for the Pico (`thumbv6m-none-eabi`) and for the host. The
+//! top level `.cargo/config.toml` defaults to the Pico target, so give the
+//! host target explicitly when building/testing on a Linux box:
+//!
+//!   cargo test --target x86_64-unknown-linux-gnu
+
+#![no_std]
+
+pub mod action;
+pub mod button;
+pub mod can;
+pub mod console;
+pub mod crc;
+pub mod debounce;
+pub mod diag;
+pub mod dimmer;
+pub mod dtc;
+pub mod events;
+pub mod fault;
+pub mod future;
+pub mod gear;
+pub mod gesture;
+pub mod hid;
+pub mod interlock;
+pub mod latency;
+pub mod led;
+pub mod power;
+pub mod protocol;
+pub mod status;
+pub mod storage;
+pub mod tasks;
+pub mod watchdog;
+
+pub use action::{Action, ActionMap, Mapping, DEFAULT_ACTIONS};
+pub use button::{ButtonReader, ButtonUpdate, Edge};
+pub use can::{DiagRequest, Frame, FrameLayout, DEFAULT_LAYOUT};
+pub use debounce::{Algorithm, DebounceConfig, Debouncer, DEFAULT_DEBOUNCE};
+pub use diag::{ButtonCheck, DiagConfig, LedCheck, DEFAULT_DIAG};
+pub use dimmer::{Dimmer, DimmerConfig, DEFAULT_DIMMER};
+pub use dtc::{Dtc, DtcArea, DtcStore, FreezeFrame, DEFAULT_AGING};
+pub use events::{EventLog, EventSpill, LogEntry, LogEvent};
+pub use fault::{Fault, FaultLog, FaultPolicy, FaultRecord, PanicRecord, Reaction, Subsystem, DEFAULT_POLICY};
+pub use gear::{Gear, GearSelector, Mode, Selection};
+pub use gesture::{Gesture, GestureKind, GestureRecognizer, Thresholds, DEFAULT_THRESHOLDS};
+pub use interlock::{Inputs, Interlock, Rejection, DEFAULT_INTERLOCK, NO_INTERLOCK};
+pub use latency::{Histogram, LatencyStats};
+pub use led::{Led, LedStatus, BLINK_MS};
+pub use power::{PowerConfig, PowerManager, DEFAULT_POWER};
+pub use protocol::{Command, Message, Packet};
+pub use status::{Keyframe, Pattern, Patterns, Rgb, StatusEvent, StatusIndicator, DEFAULT_PATTERNS};
+pub use storage::{Flash, MemFlash, State, Storage};
@@ -0,0 +1,129 @@
+//! When to go to sleep.
+//!
+//! After a while parked with nothing going on - no buttons, no gear
+//! changes, no commands - it's time to turn everything off and save the
+//! battery. Anything that happens wakes us up again. Never while something
+//! says to stay awake, like the ignition being on. All times are in ms.
+
+use crate::gear::{Gear, Selection};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PowerConfig {
+    /// Parked and idle this long, and we go to sleep.
+    pub idle_ms: u64,
+}
+
+pub const DEFAULT_POWER: PowerConfig = PowerConfig {
+    idle_ms: 5 * 60 * 1000,
+};
+
+#[derive(Debug)]
+pub struct PowerManager {
+    config: PowerConfig,
+    last:   u64,
+    asleep: bool,
+}
+
+impl PowerManager {
+    /// Awake, with nothing having happened since `now`.
+    pub const fn new(config: PowerConfig, now: u64) -> Self {
+	Self { config, last: now, asleep: false }
+    }
+
+    pub fn asleep(&self) -> bool {
+	self.asleep
+    }
+
+    /// Something happened at `now`. Returns true if that woke us up.
+    pub fn activity(&mut self, now: u64) -> bool {
+	self.last = now;
+	core::mem::replace(&mut self.asleep, false)
+    }
+
+    /// When to go to sleep, if nothing happens before then. `None` if
+    /// we're already asleep, not in P (with nothing requested) or told to
+    /// `stay_awake`.
+    pub fn deadline(&self, selection: &Selection, stay_awake: bool) -> Option<u64> {
+	let parked = selection.gear == Some(Gear::P) && selection.requested.is_none();
+	(!self.asleep && parked && !stay_awake).then_some(self.last + self.config.idle_ms)
+    }
+
+    /// Returns true if it's time to go to sleep at `now`. We're then asleep
+    /// until the next `activity()`.
+    pub fn poll(&mut self, selection: &Selection, stay_awake: bool, now: u64) -> bool {
+	let sleep = self.deadline(selection, stay_awake).is_some_and(|at| now >= at);
+	self.asleep |= sleep;
+	sleep
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gear::Mode;
+
+    const CONFIG: PowerConfig = PowerConfig { idle_ms: 1000 };
+
+    fn selection(gear: Gear, requested: Option<Gear>) -> Selection {
+	Selection { gear: Some(gear), requested, mode: Mode::Normal, park_lock: false }
+    }
+
+    #[test]
+    fn idle() {
+	let parked = selection(Gear::P, None);
+	let mut power = PowerManager::new(CONFIG, 500);
+	assert_eq!(power.deadline(&parked, false), Some(1500));
+	assert!(!power.poll(&parked, false, 1499));
+	assert!(!power.asleep());
+	assert!(power.poll(&parked, false, 1500));
+	assert!(power.asleep());
+
+	// Only once, and no more deadline until something happens.
+	assert!(!power.poll(&parked, false, 2000));
+	assert_eq!(power.deadline(&parked, false), None);
+    }
+
+    #[test]
+    fn activity() {
+	// Starts the wait over.
+	let parked = selection(Gear::P, None);
+	let mut power = PowerManager::new(CONFIG, 0);
+	assert!(!power.activity(800));
+	assert_eq!(power.deadline(&parked, false), Some(1800));
+	assert!(!power.poll(&parked, false, 1000));
+	assert!(power.poll(&parked, false, 1800));
+    }
+
+    #[test]
+    fn wake() {
+	// Activity while asleep wakes us, that's only said the first time.
+	let parked = selection(Gear::P, None);
+	let mut power = PowerManager::new(CONFIG, 0);
+	assert!(power.poll(&parked, false, 1000));
+	assert!(power.activity(5000));
+	assert!(!power.asleep());
+	assert!(!power.activity(5100));
+	assert_eq!(power.deadline(&parked, false), Some(6100));
+    }
+
+    #[test]
+    fn awake() {
+	// Not in P, waiting for P, or told to stay awake - never asleep.
+	let mut power = PowerManager::new(CONFIG, 0);
+	for (selection, stay_awake) in [
+	    (selection(Gear::D, None), false),
+	    (selection(Gear::P, Some(Gear::R)), false),
+	    (selection(Gear::R, Some(Gear::P)), false),
+	    (selection(Gear::P, None), true),
+	] {
+	    assert_eq!(power.deadline(&selection, stay_awake), None);
+	    assert!(!power.poll(&selection, stay_awake, 100_000));
+	}
+	assert!(!power.asleep());
+
+	// Idle is from the last activity, parked or not - so once it's
+	// parked again, that's been long enough.
+	let parked = selection(Gear::P, None);
+	assert!(power.poll(&parked, false, 100_000));
+    }
+}
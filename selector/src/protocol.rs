@@ -0,0 +1,645 @@
+//! The UART protocol, for bench control and monitoring.
+//!
+//! Every frame is a payload followed by its CRC-32 (little endian), COBS
+//! encoded and terminated with a zero byte. A zero byte always ends a
+//! frame, so if we lose sync (or a frame is broken) we're back in sync at
+//! the next one.
+//!
+//! The first byte of the payload is the type, the rest depends on it.
+//!
+//! Commands, host to board:
+//!
+//!   0x01 Select gear     `[gear]`
+//!   0x02 Query state     `[]`
+//!   0x03 Set brightness  `[level]`
+//!   0x04 Read config     `[]`
+//!   0x05 Read fault log  `[]`
+//!   0x06 Read DTCs       `[]`
+//!   0x07 Clear DTCs      `[]`
+//!   0x08 Read events     `[0 = RAM / 1 = flash]`
+//!   0x09 Read latency    `[]`
+//!   0x0A Clear latency   `[]`
+//!
+//! Messages, board to host:
+//!
+//!   0x80 Ack             `[]`
+//!   0x81 Nak             `[reason, detail]`
+//!   0x82 State           `[gear, requested, mode, park lock]`
+//!   0x83 Config          `[positions: u16, confirm ms: u16, long ms: u16, double ms: u16, boot count: u32]`
+//!   0x84 Fault           `[index, kind, detail, at ms: u32]`
+//!   0x85 DTC             `[index, code: u16, count: u16, first ms: u32, last ms: u32, boot: u32,
+//!                          frame gear, frame buttons: u16, frame uptime ms: u32]`
+//!   0x86 Event           `[index: u16, entry]`
+//!   0x87 Latency         `[gear, count: u32, min µs: u32, avg µs: u32, max µs: u32, p99 µs: u32]`
+//!   0x90 Button          `[gear, 1 = pressed / 0 = released]`
+//!   0x91 Gear changed    `[gear, requested, mode, park lock]`
+//!
+//! Every command is answered with an Ack (after any other replies, e.g.
+//! the State a Select gear led to, one Fault per entry in the log, one DTC
+//! per code, one Event per logged event or one Latency per button) or a
+//! Nak. Button and Gear changed are sent whenever they happen.
+//!
+//! Gears are `gear as u8`, `0xFF` for no gear. `positions` have bit
+//! `gear as u8` set for every gear the board have a button for. A fault's
+//! kind and detail are the high and low byte of its diagnostic code, see
+//! `Fault::code()`. An event's entry is as `LogEntry::to_bytes()`. Multi
+//! byte values are little endian.
+
+use crate::crc::crc32;
+use crate::dtc::{Dtc, FreezeFrame};
+use crate::events::{LogEntry, ENTRY_SIZE};
+use crate::fault::{Fault, FaultRecord};
+use crate::gear::{Gear, Mode, Selection};
+use crate::interlock::Rejection;
+use crate::latency::LatencyStats;
+
+pub const NO_GEAR: u8 = 0xFF;
+
+/// Longest payload of any command or message.
+pub const MAX_PAYLOAD: usize = 32;
+
+/// Longest frame on the wire - the payload and CRC, one byte of COBS
+/// overhead (there's one per 254 bytes) and the terminating zero.
+pub const MAX_FRAME: usize = MAX_PAYLOAD + 4 + 1 + 1;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// Frame too long, the rest of it was thrown away.
+    Overflow,
+
+    /// Not valid COBS.
+    Cobs,
+
+    /// The CRC doesn't match.
+    Crc,
+
+    /// The payload is too short, or has a value out of range.
+    Invalid,
+
+    /// Don't know that type.
+    UnknownType(u8),
+}
+
+impl Error {
+    pub fn reason(self) -> &'static str {
+	match self {
+	    Error::Overflow       => "frame too long",
+	    Error::Cobs           => "bad framing",
+	    Error::Crc            => "bad checksum",
+	    Error::Invalid        => "invalid payload",
+	    Error::UnknownType(_) => "unknown type",
+	}
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    SelectGear(Gear),
+    QueryState,
+    SetBrightness(u8),
+    ReadConfig,
+    ReadFaultLog,
+    ReadDtcs,
+    ClearDtcs,
+
+    /// The event log in RAM, or (if true) the one in flash.
+    ReadEvents(bool),
+
+    ReadLatency,
+    ClearLatency,
+}
+
+/// Why a command wasn't done.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Nak {
+    /// Couldn't make sense of the command.
+    BadCommand,
+
+    /// The interlocks said no.
+    Rejected(Rejection),
+}
+
+/// How the board is set up.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// Bit `gear as u8` set for every gear the board have a button for.
+    pub positions:  u16,
+
+    /// 0 if gear changes aren't confirmed.
+    pub confirm_ms: u16,
+    pub long_ms:    u16,
+    pub double_ms:  u16,
+    pub boot_count: u32,
+}
+
+impl Config {
+    pub fn has(&self, gear: Gear) -> bool {
+	self.positions & (1 << gear as u8) != 0
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    Ack,
+    Nak(Nak),
+    State(Selection),
+    Config(Config),
+
+    /// Entry `index` (oldest first) of the fault log.
+    Fault(u8, FaultRecord),
+
+    /// Stored trouble code number `index`.
+    Dtc(u8, Dtc),
+
+    /// Entry `index` (oldest first) of the event log.
+    Event(u16, LogEntry),
+
+    /// Button to LED times for a button.
+    Latency(Gear, LatencyStats),
+
+    Button(Gear, bool),
+    GearChanged(Selection),
+}
+
+/// Something that can go in a frame.
+pub trait Packet: Sized {
+    /// Writes the payload, returns its length.
+    fn write(&self, payload: &mut [u8; MAX_PAYLOAD]) -> usize;
+
+    fn read(payload: &[u8]) -> Result<Self, Error>;
+}
+
+impl Packet for Command {
+    fn write(&self, payload: &mut [u8; MAX_PAYLOAD]) -> usize {
+	let mut w = Writer::new(payload);
+	match *self {
+	    Command::SelectGear(gear)     => w.u8(0x01).u8(gear as u8),
+	    Command::QueryState           => w.u8(0x02),
+	    Command::SetBrightness(level) => w.u8(0x03).u8(level),
+	    Command::ReadConfig           => w.u8(0x04),
+	    Command::ReadFaultLog         => w.u8(0x05),
+	    Command::ReadDtcs             => w.u8(0x06),
+	    Command::ClearDtcs            => w.u8(0x07),
+	    Command::ReadEvents(flash)    => w.u8(0x08).u8(flash as u8),
+	    Command::ReadLatency          => w.u8(0x09),
+	    Command::ClearLatency         => w.u8(0x0A),
+	};
+	w.len
+    }
+
+    fn read(payload: &[u8]) -> Result<Self, Error> {
+	let mut r = Reader(payload);
+	Ok(match r.u8()? {
+	    0x01 => Command::SelectGear(r.gear()?.ok_or(Error::Invalid)?),
+	    0x02 => Command::QueryState,
+	    0x03 => Command::SetBrightness(r.u8()?),
+	    0x04 => Command::ReadConfig,
+	    0x05 => Command::ReadFaultLog,
+	    0x06 => Command::ReadDtcs,
+	    0x07 => Command::ClearDtcs,
+	    0x08 => Command::ReadEvents(r.u8()? != 0),
+	    0x09 => Command::ReadLatency,
+	    0x0A => Command::ClearLatency,
+	    t => return Err(Error::UnknownType(t)),
+	})
+    }
+}
+
+impl Packet for Message {
+    fn write(&self, payload: &mut [u8; MAX_PAYLOAD]) -> usize {
+	let mut w = Writer::new(payload);
+	match *self {
+	    Message::Ack => w.u8(0x80),
+	    Message::Nak(Nak::BadCommand) => w.u8(0x81).u8(0).u8(0),
+	    Message::Nak(Nak::Rejected(rejection)) => w.u8(0x81).u8(1).u8(rejection as u8),
+	    Message::State(selection) => w.u8(0x82).selection(&selection),
+	    Message::Config(config) => {
+		w.u8(0x83).u16(config.positions).u16(config.confirm_ms).u16(config.long_ms)
+		    .u16(config.double_ms).u32(config.boot_count)
+	    }
+	    Message::Fault(index, record) => {
+		let code = record.fault.code();
+		w.u8(0x84).u8(index).u8((code >> 8) as u8).u8(code as u8).u32(record.at as u32)
+	    }
+	    Message::Dtc(index, dtc) => {
+		w.u8(0x85).u8(index).u16(dtc.code).u16(dtc.count).u32(dtc.first as u32).u32(dtc.last as u32)
+		    .u32(dtc.boot).gear(dtc.frame.gear).u16(dtc.frame.buttons).u32(dtc.frame.uptime as u32)
+	    }
+	    Message::Event(index, entry) => w.u8(0x86).u16(index).bytes(&entry.to_bytes()),
+	    Message::Latency(gear, stats) => {
+		w.u8(0x87).u8(gear as u8).u32(stats.count).u32(stats.min).u32(stats.avg).u32(stats.max).u32(stats.p99)
+	    }
+	    Message::Button(gear, pressed) => w.u8(0x90).u8(gear as u8).u8(pressed as u8),
+	    Message::GearChanged(selection) => w.u8(0x91).selection(&selection),
+	};
+	w.len
+    }
+
+    fn read(payload: &[u8]) -> Result<Self, Error> {
+	let mut r = Reader(payload);
+	Ok(match r.u8()? {
+	    0x80 => Message::Ack,
+	    0x81 => match (r.u8()?, r.u8()?) {
+		(0, _) => Message::Nak(Nak::BadCommand),
+		(1, detail) => Message::Nak(Nak::Rejected(Rejection::from_u8(detail).ok_or(Error::Invalid)?)),
+		_ => return Err(Error::Invalid),
+	    },
+	    0x82 => Message::State(r.selection()?),
+	    0x83 => Message::Config(Config {
+		positions:  r.u16()?,
+		confirm_ms: r.u16()?,
+		long_ms:    r.u16()?,
+		double_ms:  r.u16()?,
+		boot_count: r.u32()?,
+	    }),
+	    0x84 => {
+		let index = r.u8()?;
+		let kind = r.u8()?;
+		let detail = r.u8()?;
+		let fault = Fault::from_code((kind as u16) << 8 | detail as u16).ok_or(Error::Invalid)?;
+		Message::Fault(index, FaultRecord { fault, at: r.u32()? as u64 })
+	    }
+	    0x85 => Message::Dtc(r.u8()?, Dtc {
+		code:  r.u16()?,
+		count: r.u16()?,
+		first: r.u32()? as u64,
+		last:  r.u32()? as u64,
+		boot:  r.u32()?,
+		frame: FreezeFrame {
+		    gear:    r.gear()?,
+		    buttons: r.u16()?,
+		    uptime:  r.u32()? as u64,
+		},
+	    }),
+	    0x86 => {
+		let index = r.u16()?;
+		Message::Event(index, LogEntry::from_bytes(&r.take::<ENTRY_SIZE>()?).ok_or(Error::Invalid)?)
+	    }
+	    0x87 => Message::Latency(r.gear()?.ok_or(Error::Invalid)?, LatencyStats {
+		count: r.u32()?,
+		min:   r.u32()?,
+		avg:   r.u32()?,
+		max:   r.u32()?,
+		p99:   r.u32()?,
+	    }),
+	    0x90 => Message::Button(r.gear()?.ok_or(Error::Invalid)?, r.u8()? != 0),
+	    0x91 => Message::GearChanged(r.selection()?),
+	    t => return Err(Error::UnknownType(t)),
+	})
+    }
+}
+
+/// Puts `packet` in a frame, ready to send.
+pub fn encode<'a>(packet: &impl Packet, frame: &'a mut [u8; MAX_FRAME]) -> &'a [u8] {
+    let mut payload = [0; MAX_PAYLOAD + 4];
+    let len = packet.write((&mut payload[..MAX_PAYLOAD]).try_into().unwrap());
+    let crc = crc32(&payload[..len]);
+    payload[len..len + 4].copy_from_slice(&crc.to_le_bytes());
+
+    let len = cobs_encode(&payload[..len + 4], frame);
+    frame[len] = 0;
+    &frame[..=len]
+}
+
+/// Collects bytes into frames.
+#[derive(Debug)]
+pub struct Decoder {
+    buf:      [u8; MAX_FRAME],
+    len:      usize,
+    overflow: bool,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+impl Decoder {
+    pub const fn new() -> Self {
+	Self { buf: [0; MAX_FRAME], len: 0, overflow: false }
+    }
+
+    /// Add a received byte. At the end of a frame, returns its payload (to
+    /// hand to `Packet::read`), or why there isn't one.
+    pub fn push(&mut self, byte: u8) -> Option<Result<&[u8], Error>> {
+	if byte != 0 {
+	    match self.buf.get_mut(self.len) {
+		Some(b) => {
+		    *b = byte;
+		    self.len += 1;
+		}
+		None => self.overflow = true,
+	    }
+	    return None;
+	}
+
+	let len = core::mem::take(&mut self.len);
+	if core::mem::take(&mut self.overflow) {
+	    return Some(Err(Error::Overflow));
+	}
+	if len == 0 {
+	    // Just a delimiter - nothing lost.
+	    return None;
+	}
+	Some(self.check(len))
+    }
+
+    fn check(&mut self, len: usize) -> Result<&[u8], Error> {
+	let len = cobs_decode(&mut self.buf[..len]).ok_or(Error::Cobs)?;
+	if len < 5 {
+	    return Err(Error::Invalid);
+	}
+
+	let (payload, crc) = self.buf[..len].split_at(len - 4);
+	match crc32(payload).to_le_bytes() == crc {
+	    true => Ok(payload),
+	    false => Err(Error::Crc),
+	}
+    }
+}
+
+// ================================================================================
+
+// `out` needs room for one more byte per 254 of `data`, plus one.
+fn cobs_encode(data: &[u8], out: &mut [u8]) -> usize {
+    let mut code_at = 0;
+    let mut code = 1u8;
+    let mut len = 1;
+
+    for byte in data {
+	if *byte != 0 {
+	    out[len] = *byte;
+	    len += 1;
+	    code += 1;
+	}
+	if *byte == 0 || code == 0xFF {
+	    out[code_at] = code;
+	    code_at = len;
+	    code = 1;
+	    len += 1;
+	}
+    }
+    out[code_at] = code;
+    len
+}
+
+// Decodes in place, returns the decoded length.
+fn cobs_decode(buf: &mut [u8]) -> Option<usize> {
+    let mut read = 0;
+    let mut len = 0;
+
+    while read < buf.len() {
+	let code = buf[read] as usize;
+	if code == 0 || read + code > buf.len() {
+	    return None;
+	}
+
+	buf.copy_within(read + 1..read + code, len);
+	len += code - 1;
+	read += code;
+
+	// A zero after every block, except the last one and the full ones.
+	if code < 0xFF && read < buf.len() {
+	    buf[len] = 0;
+	    len += 1;
+	}
+    }
+    Some(len)
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Writer<'a> {
+    fn new(buf: &'a mut [u8]) -> Self {
+	Self { buf, len: 0 }
+    }
+
+    fn u8(&mut self, value: u8) -> &mut Self {
+	self.buf[self.len] = value;
+	self.len += 1;
+	self
+    }
+
+    fn u16(&mut self, value: u16) -> &mut Self {
+	value.to_le_bytes().into_iter().fold(self, |w, b| w.u8(b))
+    }
+
+    fn u32(&mut self, value: u32) -> &mut Self {
+	value.to_le_bytes().into_iter().fold(self, |w, b| w.u8(b))
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+	bytes.iter().fold(self, |w, b| w.u8(*b))
+    }
+
+    fn gear(&mut self, gear: Option<Gear>) -> &mut Self {
+	self.u8(gear.map_or(NO_GEAR, |g| g as u8))
+    }
+
+    fn selection(&mut self, selection: &Selection) -> &mut Self {
+	self.gear(selection.gear).gear(selection.requested).u8(selection.mode as u8).u8(selection.park_lock as u8)
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+	if self.0.len() < N {
+	    return Err(Error::Invalid);
+	}
+	let (bytes, rest) = self.0.split_at(N);
+	self.0 = rest;
+	Ok(bytes.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+	Ok(self.take::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+	Ok(u16::from_le_bytes(self.take()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+	Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    fn gear(&mut self) -> Result<Option<Gear>, Error> {
+	match self.u8()? {
+	    NO_GEAR => Ok(None),
+	    value => Gear::from_u8(value).map(Some).ok_or(Error::Invalid),
+	}
+    }
+
+    fn selection(&mut self) -> Result<Selection, Error> {
+	Ok(Selection {
+	    gear:      self.gear()?,
+	    requested: self.gear()?,
+	    mode:      Mode::from_u8(self.u8()?).ok_or(Error::Invalid)?,
+	    park_lock: self.u8()? != 0,
+	})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::events::LogEvent;
+
+    const SELECTION: Selection =
+	Selection { gear: Some(Gear::P), requested: Some(Gear::D), mode: Mode::Sport, park_lock: true };
+
+    // Every frame in `bytes`, decoded.
+    fn decode<T: Packet>(bytes: &[u8]) -> Vec<Result<T, Error>> {
+	let mut decoder = Decoder::new();
+	bytes.iter().filter_map(|b| decoder.push(*b).map(|p| p.and_then(T::read))).collect()
+    }
+
+    fn round_trip<T: Packet + PartialEq + core::fmt::Debug>(packet: T) {
+	let mut frame = [0; MAX_FRAME];
+	let bytes = encode(&packet, &mut frame);
+	assert_eq!(bytes.iter().position(|b| *b == 0), Some(bytes.len() - 1), "{:?}", packet);
+	assert_eq!(decode::<T>(bytes), [Ok(packet)]);
+    }
+
+    #[test]
+    fn commands() {
+	for command in [
+	    Command::SelectGear(Gear::R), Command::QueryState, Command::SetBrightness(0), Command::SetBrightness(255),
+	    Command::ReadConfig, Command::ReadFaultLog, Command::ReadDtcs, Command::ClearDtcs, Command::ReadEvents(false),
+	    Command::ReadEvents(true), Command::ReadLatency, Command::ClearLatency,
+	] {
+	    round_trip(command);
+	}
+    }
+
+    #[test]
+    fn messages() {
+	let dtc = Dtc {
+	    code:  Fault::StuckButton(Gear::N).code(),
+	    count: 3,
+	    first: 1000,
+	    last:  0x1234_5678,
+	    boot:  42,
+	    frame: FreezeFrame { gear: Some(Gear::D), buttons: 0b1010, uptime: 999 },
+	};
+	let entry = LogEntry { at: 123_456, boot: 7, event: LogEvent::GearChanged(SELECTION) };
+	let stats = LatencyStats { count: 10, min: 100, avg: 200, max: 300, p99: 299 };
+	let config = Config { positions: 0x0F, confirm_ms: 2000, long_ms: 1000, double_ms: 250, boot_count: 0xDEAD_BEEF };
+
+	for message in [
+	    Message::Ack, Message::Nak(Nak::BadCommand), Message::Nak(Nak::Rejected(Rejection::NotAtStandstill)),
+	    Message::State(SELECTION), Message::Config(config),
+	    Message::Fault(2, FaultRecord { fault: Fault::Panic, at: 77 }),
+	    Message::Dtc(1, dtc), Message::Event(300, entry), Message::Latency(Gear::D, stats),
+	    Message::Button(Gear::N, true), Message::Button(Gear::N, false), Message::GearChanged(SELECTION),
+	] {
+	    round_trip(message);
+	}
+    }
+
+    #[test]
+    fn no_gear() {
+	round_trip(Message::State(Selection { gear: None, requested: None, mode: Mode::Normal, park_lock: false }));
+    }
+
+    #[test]
+    fn fits() {
+	// The biggest ones, with lots of zeros to stuff.
+	let dtc = Dtc {
+	    code:  0,
+	    count: 0,
+	    first: 0,
+	    last:  0,
+	    boot:  0,
+	    frame: FreezeFrame { gear: Some(Gear::P), buttons: 0, uptime: 0 },
+	};
+	round_trip(Message::Dtc(0, dtc));
+	round_trip(Message::Latency(Gear::P, LatencyStats::default()));
+    }
+
+    #[test]
+    fn cobs() {
+	let mut out = [0; 8];
+	let len = cobs_encode(&[0x11, 0x00, 0x00, 0x22], &mut out);
+	assert_eq!(&out[..len], [0x02, 0x11, 0x01, 0x02, 0x22]);
+	assert_eq!(cobs_decode(&mut out[..len]), Some(4));
+	assert_eq!(&out[..4], [0x11, 0x00, 0x00, 0x22]);
+
+	// A full block of 254 non-zero bytes.
+	let data = [0x55; 254];
+	let mut out = [0; 257];
+	let len = cobs_encode(&data, &mut out);
+	assert_eq!((len, out[0]), (256, 0xFF));
+	assert_eq!(cobs_decode(&mut out[..len]), Some(254));
+	assert_eq!(out[..254], data);
+    }
+
+    #[test]
+    fn corrupt() {
+	let mut frame = [0; MAX_FRAME];
+	let good = encode(&Message::GearChanged(SELECTION), &mut frame).to_vec();
+
+	// Any one byte changed, and it's not a frame any more.
+	for n in 0..good.len() - 1 {
+	    let mut bytes = good.clone();
+	    bytes[n] ^= 0x40;
+	    let decoded = decode::<Message>(&bytes);
+	    assert!(matches!(decoded[..], [Err(Error::Crc | Error::Cobs)]), "byte {}: {:?}", n, decoded);
+	}
+    }
+
+    #[test]
+    fn truncated() {
+	// Lost the middle of a frame - it's broken, and so is the one
+	// we've run into.
+	let mut frame = [0; MAX_FRAME];
+	let bytes = encode(&Command::QueryState, &mut frame).to_vec();
+	assert!(decode::<Command>(&bytes[3..])[0].is_err());
+
+	// Too short to even have a CRC.
+	assert_eq!(decode::<Command>(&[0x03, 0x01, 0x02, 0x00]), [Err(Error::Invalid)]);
+    }
+
+    #[test]
+    fn resync() {
+	// Garbage, a stray delimiter, and then a good frame.
+	let mut frame = [0; MAX_FRAME];
+	let mut bytes = [0x12, 0x34, 0x00, 0x00].to_vec();
+	bytes.extend_from_slice(encode(&Command::ReadConfig, &mut frame));
+	assert_eq!(decode::<Command>(&bytes), [Err(Error::Cobs), Ok(Command::ReadConfig)]);
+    }
+
+    #[test]
+    fn overflow() {
+	let mut bytes = [0x01; MAX_FRAME + 10].to_vec();
+	bytes.push(0);
+	let mut frame = [0; MAX_FRAME];
+	bytes.extend_from_slice(encode(&Command::QueryState, &mut frame));
+	assert_eq!(decode::<Command>(&bytes), [Err(Error::Overflow), Ok(Command::QueryState)]);
+    }
+
+    #[test]
+    fn bad_payload() {
+	assert_eq!(Command::read(&[0x42]), Err(Error::UnknownType(0x42)));
+	assert_eq!(Message::read(&[0x70]), Err(Error::UnknownType(0x70)));
+	assert_eq!(Command::read(&[]), Err(Error::Invalid));
+
+	// Missing the gear, a gear that isn't, and no gear to select.
+	assert_eq!(Command::read(&[0x01]), Err(Error::Invalid));
+	assert_eq!(Command::read(&[0x01, 0x20]), Err(Error::Invalid));
+	assert_eq!(Command::read(&[0x01, NO_GEAR]), Err(Error::Invalid));
+
+	// A mode and a Nak reason we don't know.
+	assert_eq!(Message::read(&[0x82, 0, 0, 9, 0]), Err(Error::Invalid));
+	assert_eq!(Message::read(&[0x81, 2, 0]), Err(Error::Invalid));
+    }
+}
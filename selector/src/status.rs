@@ -0,0 +1,383 @@
+//! What the NeoPixel shows.
+//!
+//! Every pattern is a list of keyframes. A keyframe either holds its color
+//! for its duration, or fades from it to the color of the next keyframe.
+//! The gear logic sends `StatusEvent`s, and the `StatusIndicator` works out
+//! which pattern to play and what color that gives at any point in time.
+//! All times are in ms.
+
+use crate::gear::{Gear, Selection};
+
+pub type Rgb = (u8, u8, u8);
+
+pub const OFF: Rgb = (0, 0, 0);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Keyframe {
+    pub color: Rgb,
+    pub ms:    u32,
+    pub fade:  bool,
+}
+
+impl Keyframe {
+    pub const fn hold(color: Rgb, ms: u32) -> Self {
+	Self { color, ms, fade: false }
+    }
+
+    pub const fn fade(color: Rgb, ms: u32) -> Self {
+	Self { color, ms, fade: true }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Pattern {
+    pub frames: &'static [Keyframe],
+
+    /// Start over when done. If not, the pattern is played once.
+    pub repeat: bool,
+}
+
+// How often to update while fading.
+const FADE_STEP_MS: u64 = 20;
+
+impl Pattern {
+    pub const fn solid(frames: &'static [Keyframe]) -> Self {
+	Self { frames, repeat: true }
+    }
+
+    pub fn duration(&self) -> u64 {
+	self.frames.iter().map(|f| f.ms as u64).sum()
+    }
+
+    /// Color `t` ms into the pattern, `None` if it's a one-shot that's done.
+    pub fn render(&self, t: u64) -> Option<Rgb> {
+	Some(self.frame_at(t)?.0)
+    }
+
+    /// When, relative to the start, the color changes next after `t`.
+    /// `None` if it never will.
+    pub fn next_change(&self, t: u64) -> Option<u64> {
+	let (_, frame, end) = self.frame_at(t)?;
+	match frame.fade {
+	    true  => Some((t + FADE_STEP_MS).min(end)),
+	    false if self.frames.len() == 1 && self.repeat => None,
+	    false => Some(end),
+	}
+    }
+
+    // Color, keyframe and when (relative to the start) it ends.
+    fn frame_at(&self, t: u64) -> Option<(Rgb, Keyframe, u64)> {
+	let total = self.duration();
+	if total == 0 || (!self.repeat && t >= total) {
+	    return None;
+	}
+
+	let round = t - t % total; // Start of this round.
+	let mut frame_start = 0;
+	for (i, frame) in self.frames.iter().enumerate() {
+	    let ms = frame.ms as u64;
+	    let pos = t % total - frame_start;
+	    if pos < ms {
+		let end = round + frame_start + ms;
+		if !frame.fade {
+		    return Some((frame.color, *frame, end));
+		}
+
+		let next = match self.frames.get(i + 1) {
+		    Some(next) => next.color,
+		    None if self.repeat => self.frames[0].color,
+		    None => frame.color,
+		};
+		return Some((lerp(frame.color, next, pos, ms), *frame, end));
+	    }
+	    frame_start += ms;
+	}
+	None
+    }
+}
+
+fn lerp(from: Rgb, to: Rgb, pos: u64, len: u64) -> Rgb {
+    let mix = |a: u8, b: u8| (a as i64 + (b as i64 - a as i64) * pos as i64 / len as i64) as u8;
+    (mix(from.0, to.0), mix(from.1, to.1), mix(from.2, to.2))
+}
+
+/// Which pattern for what.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Patterns {
+    /// The engaged gear, indexed by `gear as usize`.
+    pub gears: [Pattern; Gear::COUNT],
+
+    /// No gear at all.
+    pub none: Pattern,
+
+    /// Waiting for the transmission to confirm a gear change.
+    pub pending: Pattern,
+
+    /// Gear change refused by the interlocks (played once).
+    pub rejected: Pattern,
+
+    /// Something went wrong (played once).
+    pub fault: Pattern,
+
+    /// Nothing have happened for `idle_ms`.
+    pub idle: Pattern,
+    pub idle_ms: u64,
+}
+
+const AMBER: Rgb = (255, 100, 0);
+const RED: Rgb   = (255, 0, 0);
+
+pub const DEFAULT_PATTERNS: Patterns = Patterns {
+    gears: [
+	Pattern::solid(&[Keyframe::hold((0, 0, 255), 1000)]),     // P - blue
+	Pattern::solid(&[Keyframe::hold((0, 255, 0), 1000)]),     // N - green
+	Pattern::solid(&[Keyframe::hold((255, 255, 255), 1000)]), // R - white
+	Pattern::solid(&[Keyframe::hold((0, 255, 255), 1000)]),   // D - cyan
+	Pattern::solid(&[Keyframe::hold((0, 128, 128), 1000)]),   // L - dim cyan
+	Pattern::solid(&[Keyframe::hold((0, 128, 128), 1000)]),   // 2 - dim cyan
+	Pattern::solid(&[Keyframe::hold((0, 128, 128), 1000)]),   // 1 - dim cyan
+	Pattern::solid(&[Keyframe::hold((255, 0, 255), 1000)]),   // S - magenta
+	Pattern::solid(&[Keyframe::hold((255, 255, 0), 1000)]),   // M - yellow
+	Pattern::solid(&[Keyframe::hold((0, 255, 64), 1000)]),    // B - green/blue
+    ],
+    none:    Pattern::solid(&[Keyframe::hold(OFF, 1000)]),
+    pending: Pattern::solid(&[Keyframe::hold(AMBER, 150), Keyframe::hold(OFF, 150)]),
+    rejected: Pattern {
+	frames: &[
+	    Keyframe::hold(RED, 150), Keyframe::hold(OFF, 150),
+	    Keyframe::hold(RED, 150), Keyframe::hold(OFF, 150),
+	    Keyframe::hold(RED, 150), Keyframe::hold(OFF, 150),
+	],
+	repeat: false,
+    },
+    fault: Pattern {
+	frames: &[
+	    Keyframe::hold(RED, 200), Keyframe::hold(AMBER, 200),
+	    Keyframe::hold(RED, 200), Keyframe::hold(AMBER, 200),
+	    Keyframe::hold(RED, 200), Keyframe::hold(AMBER, 200),
+	    Keyframe::hold(RED, 200), Keyframe::hold(AMBER, 200),
+	    Keyframe::hold(RED, 200), Keyframe::hold(AMBER, 200),
+	],
+	repeat: false,
+    },
+    idle:    Pattern::solid(&[Keyframe::fade(OFF, 1500), Keyframe::fade((0, 0, 64), 1500)]),
+    idle_ms: 30_000,
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StatusEvent {
+    /// What the selector says now.
+    Selected(Selection),
+
+    Rejected,
+    Fault,
+
+    /// Dark until the next `Selected`.
+    Sleep,
+}
+
+/// Plays the right pattern for what's going on. One-shot patterns
+/// (rejected, fault) are played on top of the base pattern for the
+/// current gear, which then continues.
+#[derive(Debug)]
+pub struct StatusIndicator {
+    patterns:   Patterns,
+    base:       Pattern,
+    base_start: u64,
+    overlay:    Option<(Pattern, u64)>,
+    last_event: u64,
+    asleep:     bool,
+}
+
+impl StatusIndicator {
+    pub fn new(patterns: Patterns, now: u64) -> Self {
+	Self { patterns, base: patterns.none, base_start: now, overlay: None, last_event: now, asleep: false }
+    }
+
+    pub fn event(&mut self, event: StatusEvent, now: u64) {
+	self.last_event = now;
+	match event {
+	    StatusEvent::Selected(selection) => {
+		self.base = match (selection.requested, selection.gear) {
+		    (Some(_), _)    => self.patterns.pending,
+		    (None, Some(g)) => self.patterns.gears[g as usize],
+		    (None, None)    => self.patterns.none,
+		};
+		self.base_start = now;
+		self.asleep = false;
+	    }
+	    StatusEvent::Rejected => self.overlay = Some((self.patterns.rejected, now)),
+	    StatusEvent::Fault    => self.overlay = Some((self.patterns.fault, now)),
+	    StatusEvent::Sleep => {
+		self.overlay = None;
+		self.asleep = true;
+	    }
+	}
+    }
+
+    /// What to show at `now`.
+    pub fn color(&mut self, now: u64) -> Rgb {
+	if self.asleep {
+	    return OFF;
+	}
+	let (pattern, start) = self.current(now);
+	pattern.render(now - start).unwrap_or(OFF)
+    }
+
+    /// When the color changes next, `None` if not until the next event.
+    pub fn next_update(&mut self, now: u64) -> Option<u64> {
+	if self.asleep {
+	    return None;
+	}
+	let (pattern, start) = self.current(now);
+	let next = pattern.next_change(now - start).map(|t| start + t);
+
+	// Make sure we notice going idle.
+	let idle_at = self.last_event + self.patterns.idle_ms;
+	match next {
+	    Some(next) if now < idle_at => Some(next.min(idle_at)),
+	    None if now < idle_at => Some(idle_at),
+	    next => next,
+	}
+    }
+
+    fn current(&mut self, now: u64) -> (Pattern, u64) {
+	if let Some((pattern, start)) = self.overlay {
+	    if pattern.render(now - start).is_some() {
+		return (pattern, start);
+	    }
+	    self.overlay = None;
+	}
+
+	let idle_at = self.last_event + self.patterns.idle_ms;
+	match now >= idle_at {
+	    true  => (self.patterns.idle, idle_at),
+	    false => (self.base, self.base_start),
+	}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gear::Mode;
+
+    const BLUE: Rgb = (0, 0, 255);
+
+    fn selected(gear: Option<Gear>, requested: Option<Gear>) -> StatusEvent {
+	StatusEvent::Selected(Selection { gear, requested, mode: Mode::Normal, park_lock: false })
+    }
+
+    #[test]
+    fn hold() {
+	const PATTERN: Pattern = Pattern::solid(&[Keyframe::hold(RED, 100), Keyframe::hold(OFF, 50)]);
+	let pattern = PATTERN;
+	assert_eq!(pattern.duration(), 150);
+	assert_eq!(pattern.render(0), Some(RED));
+	assert_eq!(pattern.render(99), Some(RED));
+	assert_eq!(pattern.render(100), Some(OFF));
+	assert_eq!(pattern.render(150), Some(RED));
+	assert_eq!(pattern.next_change(20), Some(100));
+	assert_eq!(pattern.next_change(120), Some(150));
+	assert_eq!(pattern.next_change(170), Some(250));
+    }
+
+    #[test]
+    fn fade() {
+	const PATTERN: Pattern = Pattern::solid(&[Keyframe::fade(OFF, 100), Keyframe::fade(BLUE, 100)]);
+	let pattern = PATTERN;
+	assert_eq!(pattern.render(0), Some(OFF));
+	assert_eq!(pattern.render(50), Some((0, 0, 127)));
+	assert_eq!(pattern.render(100), Some(BLUE));
+
+	// Back to the first frame at the end.
+	assert_eq!(pattern.render(150), Some((0, 0, 128)));
+	assert_eq!(pattern.next_change(50), Some(50 + FADE_STEP_MS));
+	assert_eq!(pattern.next_change(95), Some(100));
+    }
+
+    #[test]
+    fn steady() {
+	const PATTERN: Pattern = Pattern::solid(&[Keyframe::hold(BLUE, 1000)]);
+	let pattern = PATTERN;
+	assert_eq!(pattern.render(123_456), Some(BLUE));
+	assert_eq!(pattern.next_change(0), None);
+    }
+
+    #[test]
+    fn one_shot() {
+	let pattern = DEFAULT_PATTERNS.rejected;
+	assert_eq!(pattern.render(0), Some(RED));
+	assert_eq!(pattern.render(pattern.duration() - 1), Some(OFF));
+	assert_eq!(pattern.render(pattern.duration()), None);
+	assert_eq!(pattern.next_change(pattern.duration()), None);
+    }
+
+    #[test]
+    fn empty() {
+	let pattern = Pattern::solid(&[]);
+	assert_eq!(pattern.render(0), None);
+	assert_eq!(pattern.next_change(0), None);
+    }
+
+    #[test]
+    fn gears() {
+	let mut indicator = StatusIndicator::new(DEFAULT_PATTERNS, 0);
+	assert_eq!(indicator.color(0), OFF);
+
+	indicator.event(selected(Some(Gear::P), None), 10);
+	assert_eq!(indicator.color(10), BLUE);
+	indicator.event(selected(Some(Gear::N), None), 20);
+	assert_eq!(indicator.color(20), (0, 255, 0));
+    }
+
+    #[test]
+    fn pending() {
+	let mut indicator = StatusIndicator::new(DEFAULT_PATTERNS, 0);
+	indicator.event(selected(Some(Gear::P), Some(Gear::R)), 0);
+	assert_eq!(indicator.color(0), AMBER);
+	assert_eq!(indicator.color(150), OFF);
+	assert_eq!(indicator.next_update(0), Some(150));
+    }
+
+    #[test]
+    fn overlay() {
+	let mut indicator = StatusIndicator::new(DEFAULT_PATTERNS, 0);
+	indicator.event(selected(Some(Gear::P), None), 0);
+	indicator.event(StatusEvent::Rejected, 100);
+	assert_eq!(indicator.color(100), RED);
+	assert_eq!(indicator.color(250), OFF);
+
+	// Then back to P.
+	let end = 100 + DEFAULT_PATTERNS.rejected.duration();
+	assert_eq!(indicator.next_update(end - 1), Some(end));
+	assert_eq!(indicator.color(end), BLUE);
+    }
+
+    #[test]
+    fn idle() {
+	let mut indicator = StatusIndicator::new(DEFAULT_PATTERNS, 0);
+	indicator.event(selected(Some(Gear::P), None), 0);
+	let idle_ms = DEFAULT_PATTERNS.idle_ms;
+	assert_eq!(indicator.next_update(1000), Some(idle_ms));
+	assert_eq!(indicator.color(idle_ms), OFF);
+	assert_eq!(indicator.color(idle_ms + 1500), (0, 0, 64));
+
+	// Anything happening ends it.
+	indicator.event(StatusEvent::Rejected, idle_ms + 2000);
+	assert_eq!(indicator.color(idle_ms + 2000), RED);
+    }
+
+    #[test]
+    fn sleep() {
+	let mut indicator = StatusIndicator::new(DEFAULT_PATTERNS, 0);
+	indicator.event(selected(Some(Gear::P), None), 0);
+	indicator.event(StatusEvent::Fault, 10);
+	indicator.event(StatusEvent::Sleep, 20);
+	assert_eq!(indicator.color(20), OFF);
+	assert_eq!(indicator.next_update(20), None);
+
+	indicator.event(selected(Some(Gear::P), None), 5000);
+	assert_eq!(indicator.color(5000), BLUE);
+    }
+}
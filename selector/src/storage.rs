@@ -0,0 +1,389 @@
+//! Selected gear and boot counter, kept in flash.
+//!
+//! The storage area is (at least) two erase sectors. Every update is
+//! written as a new, CRC protected, record in the next free slot - when a
+//! sector is full, the other one is erased and we continue there. At boot,
+//! the valid record with the newest sequence number wins - compared so it
+//! keeps working when the number wraps. A half written record (power lost
+//! while writing) simply fails its CRC and is skipped.
+
+use crate::crc::crc32;
+use crate::gear::Gear;
+
+/// The bits we need from a NOR flash. Offsets are relative to the start of
+/// the flash, erased flash reads as `0xFF`.
+pub trait Flash {
+    type Error;
+
+    const SECTOR_SIZE: u32;
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), Self::Error>;
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Self::Error>;
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error>;
+}
+
+/// What we remember between power cycles.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct State {
+    pub gear:       Option<Gear>,
+    pub boot_count: u32,
+}
+
+pub const RECORD_SIZE: usize = 16;
+
+const MAGIC: u16 = 0x4753; // "GS"
+const NO_GEAR: u8 = 0xFF;
+
+/// One record, as stored:
+///
+/// | bytes  | field                 |
+/// |--------|-----------------------|
+/// | 0..2   | magic                 |
+/// | 2      | gear (0xFF = none)    |
+/// | 3      | reserved              |
+/// | 4..8   | sequence number       |
+/// | 8..12  | boot counter          |
+/// | 12..16 | CRC-32 of bytes 0..12 |
+///
+/// All little endian.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    pub seq:   u32,
+    pub state: State,
+}
+
+impl Record {
+    pub fn encode(&self) -> [u8; RECORD_SIZE] {
+	let mut buf = [0u8; RECORD_SIZE];
+	buf[0..2].copy_from_slice(&MAGIC.to_le_bytes());
+	buf[2] = self.state.gear.map_or(NO_GEAR, |g| g as u8);
+	buf[3] = 0;
+	buf[4..8].copy_from_slice(&self.seq.to_le_bytes());
+	buf[8..12].copy_from_slice(&self.state.boot_count.to_le_bytes());
+	let crc = crc32(&buf[0..12]);
+	buf[12..16].copy_from_slice(&crc.to_le_bytes());
+	buf
+    }
+
+    /// `None` if it's not a record, or a corrupt one.
+    pub fn decode(buf: &[u8; RECORD_SIZE]) -> Option<Self> {
+	if u16::from_le_bytes([buf[0], buf[1]]) != MAGIC {
+	    return None;
+	}
+	if u32::from_le_bytes([buf[12], buf[13], buf[14], buf[15]]) != crc32(&buf[0..12]) {
+	    return None;
+	}
+
+	let gear = match buf[2] {
+	    NO_GEAR => None,
+	    g => Some(Gear::from_u8(g)?),
+	};
+	Some(Record {
+	    seq:   u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]),
+	    state: State {
+		gear,
+		boot_count: u32::from_le_bytes([buf[8], buf[9], buf[10], buf[11]]),
+	    },
+	})
+    }
+}
+
+pub struct Storage<F: Flash> {
+    flash:  F,
+    offset: u32,
+    size:   u32,
+    seq:    u32,
+    state:  State,
+    sector: u32,         // Sector the newest record is in.
+    next:   Option<u32>, // Where to write the next record, `None` = erase first.
+}
+
+impl<F: Flash> Storage<F> {
+    /// Use `size` bytes from `offset`. Both should be sector aligned, and
+    /// there need to be at least two sectors.
+    pub fn new(flash: F, offset: u32, size: u32) -> Self {
+	Self { flash, offset, size, seq: 0, state: State::default(), sector: offset, next: None }
+    }
+
+    /// Give the flash back.
+    pub fn into_flash(self) -> F {
+	self.flash
+    }
+
+    /// Borrow the flash, for keeping something else in another part of it.
+    pub fn flash(&mut self) -> &mut F {
+	&mut self.flash
+    }
+
+    /// The last loaded or stored state.
+    pub fn state(&self) -> State {
+	self.state
+    }
+
+    /// Find the newest valid record. `None` if there isn't one - either an
+    /// empty (new) flash or everything is corrupt.
+    pub fn load(&mut self) -> Result<Option<State>, F::Error> {
+	let mut newest: Option<(u32, Record)> = None;
+
+	let mut addr = self.offset;
+	while addr < self.offset + self.size {
+	    let mut buf = [0u8; RECORD_SIZE];
+	    self.flash.read(addr, &mut buf)?;
+	    if let Some(record) = Record::decode(&buf) {
+		if newest.is_none_or(|(_, n)| record.seq.wrapping_sub(n.seq) as i32 > 0) {
+		    newest = Some((addr, record));
+		}
+	    }
+	    addr += RECORD_SIZE as u32;
+	}
+
+	match newest {
+	    Some((addr, record)) => {
+		self.seq = record.seq;
+		self.state = record.state;
+		self.sector = self.sector_of(addr);
+		self.next = self.free_slot_after(addr)?;
+		Ok(Some(record.state))
+	    }
+	    None => {
+		self.seq = 0;
+		self.next = None;
+		Ok(None)
+	    }
+	}
+    }
+
+    /// Write a new record, unless nothing changed.
+    pub fn store(&mut self, state: State) -> Result<(), F::Error> {
+	if state == self.state && self.seq != 0 {
+	    return Ok(());
+	}
+
+	let addr = match self.next {
+	    Some(addr) => addr,
+	    None => {
+		// Current sector full (or nothing written yet) - start over
+		// in the next one.
+		let sector = match self.seq {
+		    0 => self.offset,
+		    _ if self.sector + F::SECTOR_SIZE >= self.offset + self.size => self.offset,
+		    _ => self.sector + F::SECTOR_SIZE,
+		};
+		self.flash.erase(sector, sector + F::SECTOR_SIZE)?;
+		sector
+	    }
+	};
+
+	// Zero means nothing written yet, so it's skipped when wrapping.
+	let seq = match self.seq.wrapping_add(1) {
+	    0 => 1,
+	    seq => seq,
+	};
+	let record = Record { seq, state };
+	self.flash.write(addr, &record.encode())?;
+
+	self.seq = record.seq;
+	self.state = state;
+	self.sector = self.sector_of(addr);
+	self.next = self.free_slot_after(addr)?;
+
+	Ok(())
+    }
+
+    /// Convenience for the common case of only the gear changing.
+    pub fn store_gear(&mut self, gear: Gear) -> Result<(), F::Error> {
+	self.store(State { gear: Some(gear), ..self.state })
+    }
+
+    // First erased slot after `addr`, in the same sector.
+    fn free_slot_after(&mut self, addr: u32) -> Result<Option<u32>, F::Error> {
+	let sector_end = self.sector_of(addr) + F::SECTOR_SIZE;
+
+	let mut slot = addr + RECORD_SIZE as u32;
+	while slot < sector_end {
+	    let mut buf = [0u8; RECORD_SIZE];
+	    self.flash.read(slot, &mut buf)?;
+	    if buf.iter().all(|b| *b == 0xFF) {
+		return Ok(Some(slot));
+	    }
+	    slot += RECORD_SIZE as u32;
+	}
+	Ok(None)
+    }
+
+    fn sector_of(&self, addr: u32) -> u32 {
+	addr - (addr - self.offset) % F::SECTOR_SIZE
+    }
+}
+
+/// A flash in RAM, for running on the host.
+pub struct MemFlash<const SIZE: usize> {
+    pub data: [u8; SIZE],
+}
+
+impl<const SIZE: usize> MemFlash<SIZE> {
+    /// Erased.
+    pub const fn new() -> Self {
+	Self { data: [0xFF; SIZE] }
+    }
+}
+
+impl<const SIZE: usize> Default for MemFlash<SIZE> {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+impl<const SIZE: usize> Flash for MemFlash<SIZE> {
+    type Error = core::convert::Infallible;
+
+    const SECTOR_SIZE: u32 = 4096;
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), Self::Error> {
+	let offset = offset as usize;
+	buf.copy_from_slice(&self.data[offset..offset + buf.len()]);
+	Ok(())
+    }
+
+    // Like the real thing, writing can only clear bits.
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Self::Error> {
+	let offset = offset as usize;
+	for (d, s) in self.data[offset..offset + data.len()].iter_mut().zip(data) {
+	    *d &= *s;
+	}
+	Ok(())
+    }
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+	self.data[from as usize..to as usize].fill(0xFF);
+	Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECTOR: u32 = MemFlash::<0>::SECTOR_SIZE;
+    const SIZE: usize = 2 * SECTOR as usize;
+    const PER_SECTOR: u32 = SECTOR / RECORD_SIZE as u32;
+
+    fn state(gear: Gear, boot_count: u32) -> State {
+	State { gear: Some(gear), boot_count }
+    }
+
+    // What a fresh boot would find.
+    fn reload(storage: Storage<MemFlash<SIZE>>) -> (Storage<MemFlash<SIZE>>, Option<State>) {
+	let mut storage = Storage::new(storage.into_flash(), 0, SIZE as u32);
+	let state = storage.load().unwrap();
+	(storage, state)
+    }
+
+    #[test]
+    fn record() {
+	let record = Record { seq: 7, state: state(Gear::D, 42) };
+	assert_eq!(Record::decode(&record.encode()), Some(record));
+
+	let record = Record { seq: 1, state: State { gear: None, boot_count: 0 } };
+	assert_eq!(Record::decode(&record.encode()), Some(record));
+    }
+
+    #[test]
+    fn corrupt_record() {
+	let mut buf = Record { seq: 7, state: state(Gear::D, 42) }.encode();
+	buf[8] ^= 0x01;
+	assert_eq!(Record::decode(&buf), None);
+
+	// Erased flash isn't a record.
+	assert_eq!(Record::decode(&[0xFF; RECORD_SIZE]), None);
+    }
+
+    #[test]
+    fn empty() {
+	let mut storage = Storage::new(MemFlash::<SIZE>::new(), 0, SIZE as u32);
+	assert_eq!(storage.load().unwrap(), None);
+    }
+
+    #[test]
+    fn store_and_load() {
+	let mut storage = Storage::new(MemFlash::<SIZE>::new(), 0, SIZE as u32);
+	storage.load().unwrap();
+	storage.store(state(Gear::P, 1)).unwrap();
+	storage.store_gear(Gear::D).unwrap();
+
+	let (mut storage, loaded) = reload(storage);
+	assert_eq!(loaded, Some(state(Gear::D, 1)));
+
+	// And carries on where it left off.
+	storage.store_gear(Gear::N).unwrap();
+	assert_eq!(reload(storage).1, Some(state(Gear::N, 1)));
+    }
+
+    #[test]
+    fn unchanged() {
+	let mut storage = Storage::new(MemFlash::<SIZE>::new(), 0, SIZE as u32);
+	storage.load().unwrap();
+	storage.store(state(Gear::P, 1)).unwrap();
+	let before = storage.flash().data;
+	storage.store(state(Gear::P, 1)).unwrap();
+	assert!(storage.flash().data == before);
+    }
+
+    #[test]
+    fn wear_levelling() {
+	let mut storage = Storage::new(MemFlash::<SIZE>::new(), 0, SIZE as u32);
+	storage.load().unwrap();
+
+	// Fill the first sector, and one more goes in the second.
+	for n in 0..=PER_SECTOR {
+	    storage.store(state(Gear::D, n)).unwrap();
+	}
+	let data = &storage.flash().data;
+	assert!(Record::decode(data[SECTOR as usize..][..RECORD_SIZE].try_into().unwrap()).is_some());
+
+	// Then the second, and it's back to the (erased) first.
+	for n in 0..PER_SECTOR {
+	    storage.store(state(Gear::N, n)).unwrap();
+	}
+	let data = &storage.flash().data;
+	assert!(Record::decode(data[..RECORD_SIZE].try_into().unwrap()).is_some());
+	assert!(data[RECORD_SIZE..SECTOR as usize].iter().all(|b| *b == 0xFF));
+	assert_eq!(reload(storage).1, Some(state(Gear::N, PER_SECTOR - 1)));
+    }
+
+    #[test]
+    fn half_written() {
+	let mut storage = Storage::new(MemFlash::<SIZE>::new(), 0, SIZE as u32);
+	storage.load().unwrap();
+	storage.store(state(Gear::P, 1)).unwrap();
+	storage.store(state(Gear::R, 1)).unwrap();
+
+	// Power went while writing the R one - back to P.
+	storage.flash().data[RECORD_SIZE + 9] = 0xFF;
+	assert_eq!(reload(storage).1, Some(state(Gear::P, 1)));
+    }
+
+    #[test]
+    fn wraparound() {
+	// Left off just before the sequence number wraps.
+	let mut flash = MemFlash::<SIZE>::new();
+	let record = Record { seq: u32::MAX - 1, state: state(Gear::P, 1) };
+	flash.write(0, &record.encode()).unwrap();
+
+	let mut storage = Storage::new(flash, 0, SIZE as u32);
+	assert_eq!(storage.load().unwrap(), Some(state(Gear::P, 1)));
+	storage.store_gear(Gear::N).unwrap(); // u32::MAX
+	storage.store_gear(Gear::D).unwrap(); // 0 is skipped, so 1
+	let (mut storage, loaded) = reload(storage);
+	assert_eq!(loaded, Some(state(Gear::D, 1)));
+
+	let data = &storage.flash().data;
+	let seqs: [u32; 3] = core::array::from_fn(|n| {
+	    Record::decode(data[n * RECORD_SIZE..][..RECORD_SIZE].try_into().unwrap()).unwrap().seq
+	});
+	assert_eq!(seqs, [u32::MAX - 1, u32::MAX, 1]);
+
+	storage.store_gear(Gear::R).unwrap();
+	assert_eq!(reload(storage).1, Some(state(Gear::R, 1)));
+    }
+}
@@ -0,0 +1,474 @@
+//! The tasks that run the selector - a button reader and a LED driver per
+//! gear position, the gesture handler, the status light and the power
+//! manager - and the glue between them.
+//!
+//! They're written against `Board`, and a few small traits for the pins,
+//! so the same code runs on the Pico under embassy (`main` in the firmware)
+//! and on made up time on the host (`selector_host::sim`). Every task is an
+//! `async fn` that never returns. They share `Shared`, behind
+//! `Board::lock()`, and talk to each other through the board - a channel
+//! per LED driver, one for the status light and one for gestures. All times
+//! are in ms, and `u64::MAX` is never.
+
+use core::future::{pending, Future};
+
+use crate::action::{Action, DEFAULT_ACTIONS};
+use crate::button::{ButtonReader, Edge};
+use crate::debounce::DEFAULT_DEBOUNCE;
+use crate::diag::{ButtonCheck, LedCheck, DEFAULT_DIAG};
+use crate::fault::{Fault, Reaction, Subsystem, DEFAULT_POLICY};
+use crate::future::{select, select3, Either, Either3};
+use crate::gear::{Gear, GearSelector, Selection};
+use crate::gesture::{Gesture, DEFAULT_THRESHOLDS};
+use crate::interlock::{Inputs, Rejection};
+use crate::led::{Led, LedStatus};
+use crate::power::{PowerManager, DEFAULT_POWER};
+use crate::status::{Rgb, StatusEvent, StatusIndicator, DEFAULT_PATTERNS};
+use crate::watchdog::Task;
+
+/// Where we go, and stay, when something critical fails.
+pub const SAFE_GEAR: Gear = Gear::N;
+
+/// An input with a level that means active - a button pressed, the
+/// ignition on, current through a LED.
+pub trait ActiveInput {
+    /// An input that can't be read isn't active.
+    fn is_active(&mut self) -> bool;
+}
+
+/// An input that can be waited on.
+pub trait WaitInput: ActiveInput {
+    /// Returns when the level changes - or might have, it's read again
+    /// afterwards anyway.
+    fn changed(&mut self) -> impl Future<Output = ()>;
+}
+
+/// A gear LED.
+pub trait LedOutput {
+    /// `Led::level()`, 0-255 - how that's made to look even is up to the
+    /// output.
+    fn set(&mut self, level: u8);
+}
+
+/// The status light - a single RGB LED.
+pub trait StatusPixel {
+    fn show(&mut self, color: Rgb) -> impl Future<Output = ()>;
+}
+
+/// What the transmission says.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Confirmation {
+    /// It's in this gear (CAN).
+    Gear(Gear),
+
+    /// It's in whatever we requested (GPIO).
+    Requested,
+}
+
+/// What the tasks share.
+#[derive(Debug)]
+pub struct Shared {
+    pub selector: GearSelector,
+
+    /// Brake and standstill, as last read.
+    pub inputs: Inputs,
+
+    /// Which buttons are pressed, for spotting stuck and shorted ones.
+    pub buttons: ButtonCheck,
+
+    /// Something critical failed, and we're sitting in `SAFE_GEAR`. No
+    /// more gear changes until the next reset.
+    pub safe_state: bool,
+
+    /// Gone to sleep, see `power_loop()`.
+    pub asleep: bool,
+}
+
+impl Shared {
+    /// Brake released, not standing still.
+    pub const fn new(selector: GearSelector) -> Self {
+	Self {
+	    selector,
+	    inputs:     Inputs { brake: false, standstill: false },
+	    buttons:    ButtonCheck::new(DEFAULT_DIAG),
+	    safe_state: false,
+	    asleep:     false,
+	}
+    }
+}
+
+/// What the tasks need from whatever they run on - the time, the state
+/// they share, the channels between them, and somewhere to report what
+/// happened. Position `index` is a button and a LED, `0..positions()`.
+pub trait Board {
+    fn now(&self) -> u64;
+
+    /// Returns at `at`, straight away if that's passed.
+    fn at(&self, at: u64) -> impl Future<Output = ()>;
+
+    fn positions(&self) -> usize;
+    fn gear(&self, index: usize) -> Gear;
+
+    fn lock<R>(&self, f: impl FnOnce(&mut Shared) -> R) -> R;
+
+    /// Tell the LED driver of `index`, without waiting. False if it's too
+    /// far behind to take it.
+    fn send_led(&self, index: usize, status: LedStatus) -> bool;
+    fn receive_led(&self, index: usize) -> impl Future<Output = LedStatus>;
+
+    /// Tell the status light, without waiting. False if it's too far
+    /// behind.
+    fn send_status(&self, event: StatusEvent) -> bool;
+    fn receive_status(&self) -> impl Future<Output = StatusEvent>;
+
+    fn send_gesture(&self, button: Gear, gesture: Gesture) -> impl Future<Output = ()>;
+    fn receive_gesture(&self) -> impl Future<Output = (Gear, Gesture)>;
+    fn receive_confirmation(&self) -> impl Future<Output = Confirmation>;
+
+    /// Something happened, so we're not idle - and if we were asleep, the
+    /// power manager wakes us up.
+    fn activity(&self);
+    fn wait_activity(&self) -> impl Future<Output = ()>;
+
+    /// Something besides the ignition says to stay awake.
+    fn stay_awake(&self) -> bool {
+	false
+    }
+
+    /// All dark, now save power until `wake()`.
+    fn sleep(&self) -> impl Future<Output = ()> {
+	async {}
+    }
+
+    /// Back to full power.
+    fn wake(&self) {}
+
+    /// Returns when we've woken up, for the button reader of `index` - in
+    /// case it's the ignition that wakes us.
+    fn woken(&self, _index: usize) -> impl Future<Output = ()> {
+	pending()
+    }
+
+    /// `task` is alive. Returns when to check in next at the latest.
+    fn check_in(&self, _task: Task) -> u64 {
+	u64::MAX
+    }
+
+    /// The button of `index` was pressed or released.
+    fn button(&self, index: usize, edge: Edge);
+
+    /// The button of `index` made a gesture.
+    fn gesture(&self, _index: usize, _gesture: Gesture) {}
+
+    /// The gear changed (or the request did).
+    fn changed(&self, selection: &Selection);
+
+    /// `fault` happened, and this is what's being done about it.
+    fn fault(&self, fault: Fault, reaction: Reaction);
+
+    /// The debouncer of `index` have seen `glitches` glitches.
+    fn glitches(&self, _index: usize, _glitches: u32) {}
+
+    /// A gesture of `button` changed the gear, it's on the LED drivers now.
+    fn applied(&self, _button: Gear) {}
+
+    /// The LED driver of `index` set its output to `level`, after a
+    /// command.
+    fn led_set(&self, _index: usize, _level: u8) {}
+
+    /// The transmission is in `gear` now, keep it for the next boot.
+    fn save_gear(&self, _gear: Gear) {}
+}
+
+// ================================================================================
+
+/// Show `selection` on the LEDs. Doesn't wait for the LED drivers - if one
+/// of them is that far behind, report it rather than stop everything else.
+pub fn show_leds<B: Board>(board: &B, selection: &Selection) {
+    let mut full = false;
+    for index in 0..board.positions() {
+	full |= !board.send_led(index, selection.led(board.gear(index)));
+    }
+    if full {
+	handle_fault(board, Fault::ChannelFull(Subsystem::Leds), 0);
+    }
+}
+
+/// The gear changed - show it, and tell everyone.
+pub fn show<B: Board>(board: &B, selection: &Selection) {
+    board.activity();
+    show_leds(board, selection);
+    if !board.send_status(StatusEvent::Selected(*selection)) {
+	handle_fault(board, Fault::ChannelFull(Subsystem::Status), 0);
+    }
+    board.changed(selection);
+}
+
+/// Report `fault` (the `attempt`th time it happened) and do whatever the
+/// policy says. Retrying is up to the caller.
+pub fn handle_fault<B: Board>(board: &B, fault: Fault, attempt: u8) -> Reaction {
+    let reaction = DEFAULT_POLICY.reaction(fault, attempt);
+    board.fault(fault, reaction);
+    if reaction == Reaction::SafeState {
+	enter_safe_state(board);
+    }
+    reaction
+}
+
+/// Go to the safe gear and ignore the buttons from now on. Not saved - a
+/// reset gets us out of it.
+pub fn enter_safe_state<B: Board>(board: &B) {
+    let selection = board.lock(|s| match core::mem::replace(&mut s.safe_state, true) {
+	true  => None,
+	false => Some(s.selector.restore(SAFE_GEAR)),
+    });
+    if let Some(selection) = selection {
+	show(board, &selection);
+	board.send_status(StatusEvent::Fault);
+    }
+}
+
+/// Do whatever a gesture (or a command) asked for, if the interlocks allow
+/// it.
+pub fn apply<B: Board>(board: &B, action: Action) -> Result<Selection, Rejection> {
+    let now = board.now();
+    let result = board.lock(|s| match s.safe_state {
+	true  => Err(Rejection::SafeState),
+	false => s.selector.apply(action, &s.inputs, now),
+    });
+    match result {
+	Ok(selection) => show(board, &selection),
+	Err(rejection) => {
+	    // Current gear LED stays on, nothing to send.
+	    handle_fault(board, Fault::Rejected(rejection), 0);
+	    board.send_status(StatusEvent::Rejected);
+	}
+    }
+    result
+}
+
+// ================================================================================
+
+/// Drives the LED of `index`. Sleeps until there's either a new command, or
+/// the current effect needs the LED changed (or it's time to check in). If
+/// it has a `sense` input, that's read every `sample_ms` while the LED is on,
+/// whatever the effect is doing.
+pub async fn led_loop<B, O, S>(board: &B, index: usize, mut output: O, mut sense: Option<S>) -> !
+where
+    B: Board,
+    O: LedOutput,
+    S: ActiveInput,
+{
+    let gear = board.gear(index);
+    let mut check = LedCheck::new(DEFAULT_DIAG, board.now());
+    let mut led = Led::new();
+    let mut sampled = board.now();
+    let mut commanded = false;
+
+    loop {
+	let check_in = board.check_in(Task::Led(index as u8));
+	let now = board.now();
+
+	// Before the level changes, it's what the sense input has seen. While
+	// it's off, the first sample is due `sample_ms` after it's on.
+	if let Some(input) = &mut sense {
+	    match check.next_sample(sampled) {
+		Some(at) if now >= at => {
+		    sampled = now;
+		    if check.sample(input.is_active(), now) {
+			handle_fault(board, Fault::OpenLed(gear), 0);
+		    }
+		}
+		Some(_) => (),
+		None => sampled = now,
+	    }
+	}
+	let level = led.level(now);
+	output.set(level);
+	check.set(level, now);
+	if commanded {
+	    board.led_set(index, level);
+	}
+
+	let sample = match sense {
+	    Some(_) => check.next_sample(sampled),
+	    None => None,
+	};
+	let next = led.next_update(now).into_iter().chain(sample).fold(check_in, u64::min);
+	commanded = match select(board.receive_led(index), board.at(next)).await {
+	    Either::First(status) => {
+		led.command(status, board.now());
+		true
+	    }
+	    Either::Second(_) => false,
+	};
+    }
+}
+
+/// Reads the button of `index`, and hands its gestures to `gesture_loop()`.
+/// A press while we're asleep only wakes us up - whatever gestures it makes
+/// (up to the next press, so a double press it starts too) are dropped.
+pub async fn button_loop<B: Board, I: WaitInput>(board: &B, index: usize, mut input: I) -> ! {
+    let button = board.gear(index);
+    let mut reader = ButtonReader::new(button, DEFAULT_DEBOUNCE, DEFAULT_THRESHOLDS, &DEFAULT_ACTIONS);
+    let mut waking = false;
+
+    loop {
+	// Wait for the input to change, or for the reader to need a poll.
+	// Polling early is harmless, so it's also done when it's time to
+	// check in, or when we wake up. The input is read every time, so an
+	// edge missed while we were busy is seen then at the latest.
+	let check_in = board.check_in(Task::Button(index as u8));
+	let deadline = board.lock(|s| reader.deadline(&s.buttons)).map_or(check_in, |d| d.min(check_in));
+	select(select(input.changed(), board.woken(index)), board.at(deadline)).await;
+
+	let now = board.now();
+	let raw = input.is_active();
+	let glitches = reader.glitches();
+	let update = board.lock(|s| reader.update(raw, now, &mut s.buttons));
+	if reader.glitches() != glitches {
+	    board.glitches(index, reader.glitches());
+	}
+
+	// Before `activity()` wakes us.
+	let pressed = update.edge == Some(Edge::Press);
+	let woke = pressed && board.lock(|s| s.asleep);
+
+	if let Some(edge) = update.edge {
+	    board.activity();
+	    board.button(index, edge);
+	}
+	if let Some(fault) = update.fault {
+	    handle_fault(board, fault, 0);
+	}
+	if let Some(gesture) = update.gesture.filter(|_| !waking) {
+	    board.gesture(index, gesture);
+	    board.send_gesture(button, gesture).await;
+	}
+	if pressed {
+	    waking = woke;
+	}
+    }
+}
+
+/// Turns gestures into actions, and actions into LED changes. Also deals
+/// with confirmations from the transmission, and it not confirming in time.
+pub async fn gesture_loop<B: Board>(board: &B) -> ! {
+    loop {
+	let deadline = board.lock(|s| s.selector.deadline()).unwrap_or(u64::MAX);
+
+	match select3(board.receive_gesture(), board.receive_confirmation(), board.at(deadline)).await {
+	    Either3::First((button, gesture)) => {
+		// The LED drivers don't run until we yield, so it's not too
+		// late to start timing them.
+		if let Some(action) = DEFAULT_ACTIONS.action(button, gesture) {
+		    if apply(board, action).is_ok() {
+			board.applied(button);
+		    }
+		}
+	    }
+	    Either3::Second(confirmation) => {
+		let confirmed = board.lock(|s| {
+		    let gear = match confirmation {
+			Confirmation::Gear(gear) => Some(gear),
+			Confirmation::Requested  => s.selector.requested(),
+		    };
+		    gear.and_then(|gear| s.selector.confirm(gear))
+		});
+
+		if let Some(selection) = confirmed {
+		    show(board, &selection);
+		    if let Some(gear) = selection.gear {
+			board.save_gear(gear);
+		    }
+		}
+	    }
+	    Either3::Third(_) => {
+		let now = board.now();
+		let timed_out = board.lock(|s| {
+		    let requested = s.selector.requested();
+		    s.selector.tick(now).zip(requested)
+		});
+
+		if let Some((selection, requested)) = timed_out {
+		    handle_fault(board, Fault::NotConfirmed(requested), 0);
+		    show(board, &selection);
+		    board.send_status(StatusEvent::Fault);
+		}
+	    }
+	}
+    }
+}
+
+/// The status light shows what's going on - whatever pattern the indicator
+/// says, updated whenever it changes or something happens.
+pub async fn status_loop<B: Board, P: StatusPixel>(board: &B, pixel: &mut P, restored: Selection) -> ! {
+    let mut indicator = StatusIndicator::new(DEFAULT_PATTERNS, board.now());
+    indicator.event(StatusEvent::Selected(restored), board.now());
+    loop {
+	let check_in = board.check_in(Task::Status);
+	let now = board.now();
+	pixel.show(indicator.color(now)).await;
+
+	let next = indicator.next_update(now).unwrap_or(u64::MAX).min(check_in);
+	if let Either::First(event) = select(board.receive_status(), board.at(next)).await {
+	    indicator.event(event, board.now());
+	}
+    }
+}
+
+/// Goes to sleep after a while parked and idle, and wakes up again on any
+/// `activity()` or the `ignition` changing. The ignition being on also
+/// keeps us awake. See `PowerManager`.
+pub async fn power_loop<B: Board, I: WaitInput>(board: &B, mut ignition: Option<I>) -> ! {
+    let mut manager = PowerManager::new(DEFAULT_POWER, board.now());
+
+    loop {
+	let selection = board.lock(|s| s.selector.selection());
+	let on = ignition.as_mut().is_some_and(|input| input.is_active());
+	let stay_awake = on || board.stay_awake();
+	let deadline = manager.deadline(&selection, stay_awake).unwrap_or(u64::MAX);
+
+	// Either way the ignition goes, it's something happening.
+	let ignition_edge = async {
+	    match &mut ignition {
+		Some(input) => input.changed().await,
+		None => pending().await,
+	    }
+	};
+
+	match select3(board.wait_activity(), ignition_edge, board.at(deadline)).await {
+	    Either3::First(_) | Either3::Second(_) => {
+		if manager.activity(board.now()) {
+		    wake(board);
+		}
+	    }
+	    Either3::Third(_) => {
+		if manager.poll(&selection, stay_awake, board.now()) {
+		    sleep(board).await;
+		}
+	    }
+	}
+    }
+}
+
+async fn sleep<B: Board>(board: &B) {
+    board.lock(|s| s.asleep = true);
+    for index in 0..board.positions() {
+	board.send_led(index, LedStatus::Dark);
+    }
+    board.send_status(StatusEvent::Sleep);
+    board.sleep().await;
+}
+
+// Back to what the selector says. The rest wake up by themselves as soon as
+// they're asked to do something, or check in.
+fn wake<B: Board>(board: &B) {
+    let selection = board.lock(|s| {
+	s.asleep = false;
+	s.selector.selection()
+    });
+    board.wake();
+    show_leds(board, &selection);
+    board.send_status(StatusEvent::Selected(selection));
+}
@@ -0,0 +1,210 @@
+//! Task supervision for the hardware watchdog.
+//!
+//! Every critical task checks in regularly, and the watchdog is only fed
+//! while all of them have. When one stops, it's written to a watchdog
+//! scratch register (which survives the reset) before the watchdog bites,
+//! so after the reboot we know which one it was. All times are in ms.
+
+/// A task that needs to check in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Task {
+    /// The NeoPixel loop.
+    Status,
+
+    /// The button reader of position n.
+    Button(u8),
+
+    /// The LED driver of position n.
+    Led(u8),
+}
+
+impl Task {
+    pub fn name(self) -> &'static str {
+	match self {
+	    Task::Status    => "status",
+	    Task::Button(_) => "button",
+	    Task::Led(_)    => "led",
+	}
+    }
+
+    /// Position index, for the per position tasks.
+    pub fn index(self) -> Option<u8> {
+	match self {
+	    Task::Status => None,
+	    Task::Button(n) | Task::Led(n) => Some(n),
+	}
+    }
+
+    /// As a byte - high nibble the kind, low the index.
+    pub fn to_u8(self) -> u8 {
+	match self {
+	    Task::Status    => 0x00,
+	    Task::Button(n) => 0x10 | (n & 0x0F),
+	    Task::Led(n)    => 0x20 | (n & 0x0F),
+	}
+    }
+
+    /// The reverse of `to_u8()`.
+    pub fn from_u8(value: u8) -> Option<Self> {
+	match (value >> 4, value & 0x0F) {
+	    (0, 0) => Some(Task::Status),
+	    (1, n) => Some(Task::Button(n)),
+	    (2, n) => Some(Task::Led(n)),
+	    _ => None,
+	}
+    }
+}
+
+// "WD" in the top half, so a scratch register with anything else in it
+// isn't mistaken for one of ours.
+const SCRATCH_MAGIC: u32 = 0x5744_0000;
+
+/// What to put in the scratch register before letting the watchdog bite.
+pub fn to_scratch(task: Task) -> u32 {
+    SCRATCH_MAGIC | task.to_u8() as u32
+}
+
+/// The task that stopped, if `scratch` was written by `to_scratch()`.
+pub fn from_scratch(scratch: u32) -> Option<Task> {
+    // The byte in between is never set either.
+    match scratch & 0xFFFF_FF00 {
+	SCRATCH_MAGIC => Task::from_u8(scratch as u8),
+	_ => None,
+    }
+}
+
+/// When each task last checked in.
+#[derive(Debug)]
+pub struct Heartbeats<const N: usize> {
+    tasks:   [Option<(Task, u64)>; N],
+    timeout: u64,
+}
+
+impl<const N: usize> Heartbeats<N> {
+    /// A task is stuck if it haven't checked in for `timeout` ms.
+    pub const fn new(timeout: u64) -> Self {
+	Self { tasks: [None; N], timeout }
+    }
+
+    /// Start watching `task`, as if it checked in at `now`. Returns false
+    /// if there's no room for it.
+    pub fn expect(&mut self, task: Task, now: u64) -> bool {
+	if self.tasks.iter().flatten().any(|(t, _)| *t == task) {
+	    self.check_in(task, now);
+	    return true;
+	}
+	match self.tasks.iter_mut().find(|t| t.is_none()) {
+	    Some(slot) => {
+		*slot = Some((task, now));
+		true
+	    }
+	    None => false,
+	}
+    }
+
+    /// `task` is alive at `now`. Tasks that aren't expected are ignored.
+    pub fn check_in(&mut self, task: Task, now: u64) {
+	for (t, at) in self.tasks.iter_mut().flatten() {
+	    if *t == task {
+		*at = now;
+	    }
+	}
+    }
+
+    /// Start over, as if every task checked in at `now` - after a while
+    /// of not needing to.
+    pub fn restart(&mut self, now: u64) {
+	for (_, at) in self.tasks.iter_mut().flatten() {
+	    *at = now;
+	}
+    }
+
+    /// The first task that haven't checked in in time, if any.
+    pub fn stale(&self, now: u64) -> Option<Task> {
+	self.tasks.iter().flatten()
+	    .find(|(_, at)| now.saturating_sub(*at) > self.timeout)
+	    .map(|(task, _)| *task)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TASKS: [Task; 5] = [Task::Status, Task::Button(0), Task::Button(7), Task::Led(0), Task::Led(15)];
+
+    #[test]
+    fn round_trip() {
+	for task in TASKS {
+	    assert_eq!(Task::from_u8(task.to_u8()), Some(task));
+	    assert_eq!(from_scratch(to_scratch(task)), Some(task));
+	}
+	assert_eq!(to_scratch(Task::Led(3)), 0x5744_0023);
+    }
+
+    #[test]
+    fn garbage() {
+	// Not one of ours - power on (0), whatever something else left there.
+	for scratch in [0, 0xFFFF_FFFF, 0xDEAD_BEEF, 0x0000_0010, 0x5745_0010, 0x5744_0100, 0x5744_1210] {
+	    assert_eq!(from_scratch(scratch), None, "{:#x}", scratch);
+	}
+
+	// Ours, but no such task.
+	for byte in [0x01, 0x0F, 0x30, 0xF0, 0xFF] {
+	    assert_eq!(Task::from_u8(byte), None, "{:#x}", byte);
+	    assert_eq!(from_scratch(SCRATCH_MAGIC | byte as u32), None);
+	}
+    }
+
+    #[test]
+    fn stale() {
+	let mut heartbeats = Heartbeats::<4>::new(2000);
+	assert!(heartbeats.expect(Task::Status, 0));
+	assert!(heartbeats.expect(Task::Button(0), 0));
+	assert!(heartbeats.expect(Task::Led(0), 0));
+
+	// Everyone checking in, nothing stuck - right up to the timeout.
+	heartbeats.check_in(Task::Status, 1500);
+	heartbeats.check_in(Task::Led(0), 1500);
+	assert_eq!(heartbeats.stale(2000), None);
+
+	// The one that missed it is named.
+	assert_eq!(heartbeats.stale(2001), Some(Task::Button(0)));
+	heartbeats.check_in(Task::Button(0), 2001);
+	assert_eq!(heartbeats.stale(2001), None);
+	assert_eq!(heartbeats.stale(3501), Some(Task::Status));
+
+	// Checking in for a task that isn't expected doesn't hide anything.
+	heartbeats.check_in(Task::Led(5), 3501);
+	assert_eq!(heartbeats.stale(3501), Some(Task::Status));
+    }
+
+    #[test]
+    fn expect() {
+	let mut heartbeats = Heartbeats::<2>::new(100);
+	assert!(heartbeats.expect(Task::Button(0), 0));
+	assert!(heartbeats.expect(Task::Button(1), 0));
+
+	// Full - but expecting one again is a check-in, not a new one.
+	assert!(!heartbeats.expect(Task::Led(0), 0));
+	assert!(heartbeats.expect(Task::Button(0), 150));
+	assert_eq!(heartbeats.stale(150), Some(Task::Button(1)));
+    }
+
+    #[test]
+    fn restart() {
+	// After sleeping, nobody's stale until they've had the time again.
+	let mut heartbeats = Heartbeats::<2>::new(100);
+	heartbeats.expect(Task::Status, 0);
+	heartbeats.expect(Task::Led(1), 0);
+	assert_eq!(heartbeats.stale(60_000), Some(Task::Status));
+	heartbeats.restart(60_000);
+	assert_eq!(heartbeats.stale(60_100), None);
+	assert_eq!(heartbeats.stale(60_101), Some(Task::Status));
+
+	// Time going backwards (a check-in racing `stale()`) isn't stuck.
+	heartbeats.check_in(Task::Status, 70_000);
+	heartbeats.check_in(Task::Led(1), 70_000);
+	assert_eq!(heartbeats.stale(69_999), None);
+    }
+}
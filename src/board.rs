@@ -0,0 +1,213 @@
+//! Which pin is what, per board revision.
+//!
+//! Pick the board with a cargo feature (`board-pico` is the default). The
+//! configuration is checked at build time - two functions on the same pin
+//! is a compile error.
+
+use embassy_rp::gpio::{AnyPin, Level, Pull};
+
+use selector::{Gear, Interlock, DEFAULT_INTERLOCK, NO_INTERLOCK};
+
+/// Most gear positions a board can have - there's one button reader task
+/// per position, and this is the size of that pool.
+pub const MAX_POSITIONS: usize = 8;
+
+#[derive(Copy, Clone)]
+pub struct InputPin {
+    pub pin:    u8,
+    pub pull:   Pull,
+    pub active: Level, // Level when pressed/true.
+}
+
+#[derive(Copy, Clone)]
+pub struct OutputPin {
+    pub pin:    u8,
+    pub active: Level, // Level when on.
+}
+
+/// A gear position - its button and its LED, and optionally an input that's
+/// active when there's current through the LED (filtered, so PWM reads as
+/// steady). Without one, the LED isn't checked for open load.
+#[derive(Copy, Clone)]
+pub struct Position {
+    pub gear:   Gear,
+    pub button: InputPin,
+    pub led:    OutputPin,
+    pub sense:  Option<InputPin>,
+}
+
+pub struct BoardConfig {
+    /// The gear positions, in whatever order suits the board. Adding a
+    /// position is adding an entry here.
+    pub positions: &'static [Position],
+
+    /// The NeoPixel. Needs to match `Ws2812Pin`.
+    pub ws2812: u8,
+
+    /// Interlock and confirmation inputs.
+    pub brake:      InputPin,
+    pub standstill: InputPin,
+    pub confirm:    InputPin,
+
+    /// What the brake and standstill inputs hold back, and how long the
+    /// transmission have to confirm a gear change (`None` - it isn't
+    /// asked to). Without them wired up, it has to be nothing and `None`,
+    /// or no gear change ever goes through.
+    pub interlock:  Interlock,
+    pub confirm_ms: Option<u64>,
+
+    /// Active while the ignition is on - wakes us up, and keeps us awake.
+    pub ignition:   Option<InputPin>,
+
+    /// MCP2515 chip select and interrupt.
+    pub can_cs:  u8,
+    pub can_int: u8,
+
+    /// Used by peripherals that can't be moved without code changes - SPI0
+    /// for the CAN (PIN_16, PIN_18, PIN_19), the dimmer pot on ADC0
+    /// (PIN_26) and UART0 (PIN_0, PIN_1). Only here so they're included in
+    /// the check.
+    pub fixed: [u8; 6],
+}
+
+const fn active_low(pin: u8) -> InputPin {
+    InputPin { pin, pull: Pull::Up, active: Level::Low }
+}
+
+const fn active_high(pin: u8) -> OutputPin {
+    OutputPin { pin, active: Level::High }
+}
+
+/// The original breadboard - buttons to ground, LEDs to ground. The brake,
+/// standstill and confirm inputs aren't wired up, so it's a plain selector:
+/// no interlocks, nothing to confirm.
+#[cfg(feature = "board-pico")]
+pub const BOARD: BoardConfig = BoardConfig {
+    positions: &[
+	Position { gear: Gear::P, button: active_low(2), led: active_high(6), sense: None },
+	Position { gear: Gear::N, button: active_low(3), led: active_high(7), sense: None },
+	Position { gear: Gear::R, button: active_low(4), led: active_high(8), sense: None },
+	Position { gear: Gear::D, button: active_low(5), led: active_high(9), sense: None },
+    ],
+    ws2812:     15,
+    brake:      active_low(10),
+    standstill: active_low(11),
+    confirm:    active_low(12),
+    interlock:  NO_INTERLOCK,
+    confirm_ms: None,
+    ignition:   Some(active_low(13)),
+    can_cs:     17,
+    can_int:    20,
+    fixed:      [16, 18, 19, 26, 0, 1],
+};
+#[cfg(feature = "board-pico")]
+pub type Ws2812Pin = embassy_rp::peripherals::PIN_15;
+
+/// Selector panel PCB - P/R/N/D plus B (regen braking). LEDs sink current
+/// through the pin. P/R/N/D have current sense on the spare pins, pulled
+/// low while there's current.
+#[cfg(feature = "board-panel")]
+pub const BOARD: BoardConfig = BoardConfig {
+    positions: &[
+	Position { gear: Gear::P, button: active_low(6),  led: OutputPin { pin: 2,  active: Level::Low }, sense: Some(active_low(15)) },
+	Position { gear: Gear::R, button: active_low(7),  led: OutputPin { pin: 3,  active: Level::Low }, sense: Some(active_low(20)) },
+	Position { gear: Gear::N, button: active_low(8),  led: OutputPin { pin: 4,  active: Level::Low }, sense: Some(active_low(27)) },
+	Position { gear: Gear::D, button: active_low(9),  led: OutputPin { pin: 5,  active: Level::Low }, sense: Some(active_low(28)) },
+	Position { gear: Gear::B, button: active_low(12), led: OutputPin { pin: 14, active: Level::Low }, sense: None },
+    ],
+    ws2812:     22,
+    brake:      active_low(10),
+    standstill: active_low(11),
+    confirm:    active_low(13),
+    interlock:  DEFAULT_INTERLOCK,
+    confirm_ms: Some(2000),
+    ignition:   None, // Only the buttons wake it up.
+    can_cs:     17,
+    can_int:    21,
+    fixed:      [16, 18, 19, 26, 0, 1],
+};
+#[cfg(feature = "board-panel")]
+pub type Ws2812Pin = embassy_rp::peripherals::PIN_22;
+
+#[cfg(all(feature = "board-pico", feature = "board-panel"))]
+compile_error!("Select only one board feature");
+
+#[cfg(not(any(feature = "board-pico", feature = "board-panel")))]
+compile_error!("Select a board feature, e.g. `board-pico`");
+
+// Build time check of whichever board was selected.
+const _: () = BOARD.check();
+
+pub const NUM_POSITIONS: usize = BOARD.positions.len();
+
+impl BoardConfig {
+    const fn check(&self) {
+	if self.positions.len() > MAX_POSITIONS {
+	    panic!("Board config have more positions than MAX_POSITIONS");
+	}
+
+	let mut pins = [0u8; 3 * MAX_POSITIONS + 13];
+	let mut n = 0;
+
+	let mut i = 0;
+	while i < self.positions.len() {
+	    let mut j = 0;
+	    while j < i {
+		if self.positions[i].gear as u8 == self.positions[j].gear as u8 {
+		    panic!("Board config have the same gear twice");
+		}
+		j += 1;
+	    }
+
+	    pins[n] = self.positions[i].button.pin;
+	    pins[n + 1] = self.positions[i].led.pin;
+	    n += 2;
+	    if let Some(sense) = self.positions[i].sense {
+		pins[n] = sense.pin;
+		n += 1;
+	    }
+	    i += 1;
+	}
+	pins[n] = self.ws2812;
+	pins[n + 1] = self.brake.pin;
+	pins[n + 2] = self.standstill.pin;
+	pins[n + 3] = self.confirm.pin;
+	pins[n + 4] = self.can_cs;
+	pins[n + 5] = self.can_int;
+	n += 6;
+	if let Some(ignition) = self.ignition {
+	    pins[n] = ignition.pin;
+	    n += 1;
+	}
+	let mut i = 0;
+	while i < self.fixed.len() {
+	    pins[n] = self.fixed[i];
+	    n += 1;
+	    i += 1;
+	}
+
+	let mut i = 0;
+	while i < n {
+	    if pins[i] > 29 {
+		panic!("Board config uses a pin that doesn't exist");
+	    }
+	    let mut j = i + 1;
+	    while j < n {
+		if pins[i] == pins[j] {
+		    panic!("Board config uses the same pin twice");
+		}
+		j += 1;
+	    }
+	    i += 1;
+	}
+    }
+}
+
+/// The pin with this number.
+///
+/// Safety: `BOARD.check()` makes sure every pin is only in the config once,
+/// so as long as only pins from `BOARD` are taken this way (and never the
+/// same one twice), nothing else owns them.
+pub fn pin(n: u8) -> AnyPin {
+    unsafe { AnyPin::steal(n) }
+}
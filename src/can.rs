@@ -0,0 +1,149 @@
+//! Gear and button events out on the CAN bus, acks from the transmission in.
+//!
+//! MCP2515 on SPI0 - SCK on PIN_18, MOSI on PIN_19, MISO on PIN_16, CS and
+//! INT wherever `main` says.
+
+use defmt::{info, warn};
+
+use embassy_futures::select::{select3, Either3};
+use embassy_rp::gpio::{AnyPin, Input, Level, Output, Pull};
+use embassy_rp::peripherals::{DMA_CH1, DMA_CH2, PIN_16, PIN_18, PIN_19, SPI0};
+use embassy_rp::spi::{self, Spi};
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Ticker, Timer};
+
+use selector::tasks::{self, Confirmation};
+use selector::{DiagRequest, Fault, Gear, Reaction, Subsystem, DEFAULT_LAYOUT, DEFAULT_POLICY};
+
+use crate::mcp2515::{self, Mcp2515};
+use crate::power::{self, Sleeper};
+use crate::{clear_dtcs, Pico, CONFIRMED, SHARED};
+
+pub enum CanEvent {
+    /// The selected gear changed - send it now rather than waiting for
+    /// the next periodic frame.
+    GearChanged,
+
+    /// A button was pressed (`true`) or released.
+    Button(Gear, bool),
+}
+
+// Anything that should go out on the bus. Sent with `try_send` - if the
+// bus is down, we'd rather lose events than stop reading buttons.
+pub static CAN_TX: Channel<ThreadModeRawMutex, CanEvent, 16> = Channel::new();
+
+// How long to leave the MCP2515 alone if INT stays low after reading
+// everything (or reading failed), rather than spinning on it.
+const INT_RETRY_MS: u64 = 100;
+
+#[embassy_executor::task]
+pub async fn can_bus(
+    spi:     SPI0,
+    clk:     PIN_18,
+    mosi:    PIN_19,
+    miso:    PIN_16,
+    tx_dma:  DMA_CH1,
+    rx_dma:  DMA_CH2,
+    cs_pin:  AnyPin,
+    int_pin: AnyPin)
+{
+    let mut config = spi::Config::default();
+    config.frequency = 8_000_000;
+    let spi = Spi::new(spi, clk, mosi, miso, tx_dma, rx_dma, config);
+
+    let mut mcp = Mcp2515::new(spi, Output::new(cs_pin, Level::High));
+    let mut int = Input::new(int_pin, Pull::Up);
+    let mut attempt = 0;
+    while let Err(e) = mcp.init().await {
+	warn!("No CAN: {}", e);
+	if tasks::handle_fault(&Pico, Fault::InitFailed(Subsystem::Can), attempt) != Reaction::Retry {
+	    return;
+	}
+	attempt += 1;
+	Timer::after_millis(DEFAULT_POLICY.retry_ms).await;
+    }
+    info!("CAN up");
+
+    let layout = DEFAULT_LAYOUT;
+    let mut ticker = Ticker::every(Duration::from_millis(layout.period_ms));
+    let mut counter: u8 = 0;
+
+    // The last frame is still waiting to go out - nobody's ACKing them.
+    let mut stalled = false;
+    loop {
+	// The periodic gear frame stops while we're asleep, the bus is
+	// probably quiet anyway.
+	let period = async {
+	    if power::asleep() {
+		power::woken(Sleeper::Can).await
+	    } else {
+		ticker.next().await
+	    }
+	};
+	let frame = match select3(period, CAN_TX.receive(), int.wait_for_low()).await {
+	    Either3::First(_) | Either3::Second(CanEvent::GearChanged) => {
+		let selection = SHARED.lock(|s| s.borrow().selector.selection());
+		counter = counter.wrapping_add(1);
+		Some(layout.encode_gear(&selection, counter))
+	    }
+	    Either3::Second(CanEvent::Button(button, pressed)) => Some(layout.encode_button(button, pressed)),
+	    Either3::Third(_) => {
+		let mut reply = None;
+		loop {
+		    let frame = match mcp.receive().await {
+			Ok(Some(frame)) => frame,
+			Ok(None) => break,
+			Err(e) => {
+			    warn!("CAN receive failed: {}", e);
+			    break;
+			}
+		    };
+		    match layout.decode_ack(&frame) {
+			Some(Some(gear)) => {
+			    info!("Transmission in {}", gear.name());
+			    CONFIRMED.signal(Confirmation::Gear(gear));
+			}
+			Some(None)       => info!("Transmission in no gear"),
+			None => {}
+		    }
+
+		    // A tester only sends one request at a time, and waits
+		    // for the answer.
+		    if let Some(request) = layout.decode_diag(&frame) {
+			if request == DiagRequest::ClearDtcs {
+			    clear_dtcs();
+			}
+			reply = Some(layout.encode_diag_reply(request));
+		    }
+		}
+
+		// Nothing (more) to read, but INT is still low - clear it all
+		// and give it a moment, or this branch is all we'd ever run.
+		if int.is_low() {
+		    mcp.clear_interrupts().await.ok();
+		    Timer::after_millis(INT_RETRY_MS).await;
+		}
+		reply
+	    }
+	};
+
+	// While the bus isn't taking our frames, each one would just be
+	// `Busy` - only say so once, and pick up again when it's gone out.
+	if let Some(frame) = frame {
+	    match mcp.send(&frame).await {
+		Ok(()) if stalled => {
+		    info!("CAN frames going out again");
+		    stalled = false;
+		}
+		Ok(()) => {}
+		Err(mcp2515::Error::Busy) if stalled => {}
+		Err(mcp2515::Error::Busy) => {
+		    warn!("CAN frame not sent, holding off until the last one is");
+		    stalled = true;
+		}
+		Err(e) => warn!("CAN send failed: {}", e),
+	    }
+	}
+    }
+}
@@ -0,0 +1,75 @@
+//! Dashboard dimmer - a pot on PIN_26 (ADC0), or a level set with
+//! `DIMMER_LEVEL`. Whichever changed last wins.
+
+use embassy_futures::select::{select3, Either3};
+use embassy_rp::adc::{self, Adc};
+use embassy_rp::gpio::Pull;
+use embassy_rp::peripherals::{ADC, PIN_26};
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Ticker, Timer};
+
+use selector::{Dimmer, LedStatus, DEFAULT_DIMMER};
+
+use crate::board::NUM_POSITIONS;
+use crate::power::{self, Sleeper};
+use crate::{Irqs, LEDS};
+
+// How often to read the pot.
+const POLL_MS: u64 = 100;
+
+// Ignore pot changes smaller than this, so noise doesn't keep us ramping.
+const HYSTERESIS: u8 = 8;
+
+pub static DIMMER_LEVEL: Signal<ThreadModeRawMutex, u8> = Signal::new();
+
+#[embassy_executor::task]
+pub async fn dimmer(adc: ADC, pot_pin: PIN_26) {
+    let mut adc = Adc::new(adc, Irqs, adc::Config::default());
+    let mut pot = adc::Channel::new_pin(pot_pin, Pull::None);
+
+    let mut dimmer = Dimmer::new(DEFAULT_DIMMER, 255);
+    let mut sent = None;
+    let mut last_pot = None;
+    let mut ticker = Ticker::every(Duration::from_millis(POLL_MS));
+    loop {
+	// Tell the LEDs, if anything changed.
+	let now = Instant::now().as_millis();
+	let brightness = dimmer.brightness(now);
+	if sent != Some(brightness) {
+	    let (selected, unselected) = brightness;
+	    for channel in &LEDS[..NUM_POSITIONS] {
+		channel.try_send(LedStatus::Brightness(selected)).ok();
+		channel.try_send(LedStatus::Backlight(unselected)).ok();
+	    }
+	    sent = Some(brightness);
+	}
+
+	// No polling the pot while we're asleep.
+	let poll = async {
+	    if power::asleep() {
+		power::woken(Sleeper::Dimmer).await
+	    } else {
+		ticker.next().await
+	    }
+	};
+	let next = dimmer.next_update(now).map_or(Instant::MAX, Instant::from_millis);
+	match select3(Timer::at(next), DIMMER_LEVEL.wait(), poll).await {
+	    Either3::First(_) => {}
+	    Either3::Second(level) => dimmer.set(level, Instant::now().as_millis()),
+	    Either3::Third(_) => {
+		if let Ok(raw) = adc.read(&mut pot).await {
+		    // 12 bit ADC.
+		    let level = (raw >> 4) as u8;
+
+		    // Against where the pot was, not the level - after a
+		    // `DIMMER_LEVEL`, the pot only wins once it's moved.
+		    if last_pot.is_none_or(|last: u8| level.abs_diff(last) > HYSTERESIS) {
+			last_pot = Some(level);
+			dimmer.set(level, Instant::now().as_millis());
+		    }
+		}
+	    }
+	}
+    }
+}
@@ -0,0 +1,147 @@
+//! The event log - button presses, gear changes, faults. The newest ones
+//! are kept in RAM, and with the `event-spill` feature they're all written
+//! to flash too (`EVENTS` in memory.x), so there's a history from before the
+//! last reset. See `selector::events`.
+
+use core::cell::{Cell, RefCell};
+
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Instant;
+
+use selector::{EventLog, LogEntry, LogEvent};
+
+use crate::flash::PicoFlash;
+
+#[cfg(feature = "event-spill")]
+pub use spill::{flash_entry, flash_slots, spill};
+
+// How many events are kept in RAM.
+pub const LOG_SIZE: usize = 64;
+
+static EVENTS: Mutex<ThreadModeRawMutex, RefCell<EventLog<LOG_SIZE>>> = Mutex::new(RefCell::new(EventLog::new()));
+
+// The boot the events are from. Set by `start()`.
+static BOOT: Mutex<ThreadModeRawMutex, Cell<u32>> = Mutex::new(Cell::new(0));
+
+/// We've booted (this is boot number `boot`). With `event-spill`, also
+/// finds where in flash to continue - call before `STORAGE` is set up.
+pub fn start(boot: u32, _flash: &mut PicoFlash) {
+    BOOT.lock(|b| b.set(boot));
+
+    #[cfg(feature = "event-spill")]
+    spill::start(_flash);
+}
+
+pub fn log(event: LogEvent) {
+    let entry = LogEntry { at: Instant::now().as_millis(), boot: BOOT.lock(|b| b.get()), event };
+    EVENTS.lock(|e| e.borrow_mut().push(entry));
+
+    #[cfg(feature = "event-spill")]
+    spill::SPILL_SOON.signal(());
+}
+
+/// A copy of the log in RAM, so it can be sent without holding the lock.
+pub fn ram() -> EventLog<LOG_SIZE> {
+    EVENTS.lock(|e| e.borrow().clone())
+}
+
+/// How many entries there's room for in flash - none without `event-spill`.
+#[cfg(not(feature = "event-spill"))]
+pub fn flash_slots() -> usize {
+    0
+}
+
+/// Entry `n` of the log in flash, oldest first. `None` for an empty slot.
+#[cfg(not(feature = "event-spill"))]
+pub fn flash_entry(_n: usize) -> Option<LogEntry> {
+    None
+}
+
+// ================================================================================
+
+#[cfg(feature = "event-spill")]
+mod spill {
+    use core::cell::RefCell;
+
+    use defmt::warn;
+
+    use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+    use embassy_sync::blocking_mutex::Mutex;
+    use embassy_sync::signal::Signal;
+    use embassy_time::Timer;
+
+    use selector::{EventSpill, LogEntry};
+
+    use super::EVENTS;
+    use crate::flash::{PicoFlash, EVENTS_OFFSET, EVENTS_SIZE};
+    use crate::STORAGE;
+
+    // Wait this long after an event before writing, so a burst of them is
+    // written in one go.
+    const SPILL_MS: u64 = 1000;
+
+    static SPILL: Mutex<ThreadModeRawMutex, RefCell<EventSpill>> =
+	Mutex::new(RefCell::new(EventSpill::new(EVENTS_OFFSET, EVENTS_SIZE)));
+
+    // Something was logged.
+    pub(super) static SPILL_SOON: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+    pub(super) fn start(flash: &mut PicoFlash) {
+	SPILL.lock(|s| {
+	    if let Err(e) = s.borrow_mut().find(flash) {
+		warn!("Failed to read the event log: {}", e);
+	    }
+	});
+    }
+
+    // Writes new events to flash.
+    #[embassy_executor::task]
+    pub async fn spill() {
+	loop {
+	    SPILL_SOON.wait().await;
+	    Timer::after_millis(SPILL_MS).await;
+
+	    let events = EVENTS.lock(|e| {
+		let mut e = e.borrow_mut();
+		let events = e.clone();
+		e.spilled();
+		events
+	    });
+	    STORAGE.lock(|s| {
+		let Some(storage) = s.borrow_mut().as_mut() else {
+		    return;
+		};
+		SPILL.lock(|spill| {
+		    let mut spill = spill.borrow_mut();
+		    for entry in events.unspilled() {
+			if let Err(e) = spill.write(storage.flash(), entry) {
+			    warn!("Failed to save event: {}", e);
+			    break;
+			}
+		    }
+		});
+	    });
+	}
+    }
+
+    /// How many entries there's room for in flash.
+    pub fn flash_slots() -> usize {
+	SPILL.lock(|s| s.borrow().slots())
+    }
+
+    /// Entry `n` of the log in flash, oldest first. `None` for an empty slot.
+    pub fn flash_entry(n: usize) -> Option<LogEntry> {
+	STORAGE.lock(|s| {
+	    let mut s = s.borrow_mut();
+	    let storage = s.as_mut()?;
+	    SPILL.lock(|spill| match spill.borrow().read(storage.flash(), n) {
+		Ok(entry) => entry,
+		Err(e) => {
+		    warn!("Failed to read event: {}", e);
+		    None
+		}
+	    })
+	})
+    }
+}
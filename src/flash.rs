@@ -0,0 +1,40 @@
+//! The RP2040 flash, as `selector::storage` wants to see it.
+
+use embassy_rp::flash::{Blocking, Error, Flash, ERASE_SIZE};
+use embassy_rp::peripherals::FLASH;
+
+pub const FLASH_SIZE: usize = 2 * 1024 * 1024;
+
+// The last two sectors - `STATE` in memory.x.
+pub const STATE_SIZE: u32 = 2 * ERASE_SIZE as u32;
+pub const STATE_OFFSET: u32 = FLASH_SIZE as u32 - STATE_SIZE;
+
+// The two before that - `DTC` in memory.x.
+pub const DTC_OFFSET: u32 = STATE_OFFSET - 2 * ERASE_SIZE as u32;
+
+// The four before that - `EVENTS` in memory.x. Only used with the
+// `event-spill` feature, but always kept free.
+#[cfg(feature = "event-spill")]
+pub const EVENTS_SIZE: u32 = 4 * ERASE_SIZE as u32;
+#[cfg(feature = "event-spill")]
+pub const EVENTS_OFFSET: u32 = DTC_OFFSET - EVENTS_SIZE;
+
+pub struct PicoFlash(pub Flash<'static, FLASH, Blocking, FLASH_SIZE>);
+
+impl selector::Flash for PicoFlash {
+    type Error = Error;
+
+    const SECTOR_SIZE: u32 = ERASE_SIZE as u32;
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), Error> {
+	self.0.blocking_read(offset, buf)
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Error> {
+	self.0.blocking_write(offset, data)
+    }
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+	self.0.blocking_erase(from, to)
+    }
+}
@@ -0,0 +1,55 @@
+//! The pins, as what `selector::tasks` wants - `Active` for `embedded-hal`
+//! inputs, `Pwm` for the LEDs and `NeoPixel` for the status light. Only the
+//! task wrappers in `main` know it's an RP2040, everything they hand off to
+//! works with any pins that implement `embedded-hal`.
+
+use embedded_hal::digital::InputPin;
+use embedded_hal::pwm::SetDutyCycle;
+use embedded_hal_async::digital::Wait;
+
+use selector::tasks::{ActiveInput, LedOutput, StatusPixel, WaitInput};
+use selector::Rgb;
+
+/// An input, and whether it's active high.
+pub struct Active<I> {
+    pin:  I,
+    high: bool,
+}
+
+impl<I> Active<I> {
+    pub fn new(pin: I, high: bool) -> Self {
+	Self { pin, high }
+    }
+}
+
+impl<I: InputPin> ActiveInput for Active<I> {
+    // A pin that can't be read counts as inactive.
+    fn is_active(&mut self) -> bool {
+	self.pin.is_high().is_ok_and(|h| h == self.high)
+    }
+}
+
+impl<I: InputPin + Wait> WaitInput for Active<I> {
+    async fn changed(&mut self) {
+	self.pin.wait_for_any_edge().await.ok();
+    }
+}
+
+/// A LED on a PWM output.
+pub struct Pwm<O>(pub O);
+
+impl<O: SetDutyCycle> LedOutput for Pwm<O> {
+    fn set(&mut self, level: u8) {
+	// Squared, so the steps look even to the eye.
+	self.0.set_duty_cycle_fraction(level as u16 * level as u16, 255 * 255).ok();
+    }
+}
+
+/// The NeoPixel, driven by PIO.
+pub struct NeoPixel<'d, P: embassy_rp::pio::Instance, const S: usize>(pub ws2812::Ws2812<'d, P, S, 1>);
+
+impl<'d, P: embassy_rp::pio::Instance, const S: usize> StatusPixel for NeoPixel<'d, P, S> {
+    async fn show(&mut self, (r, g, b): Rgb) {
+	self.0.write(&[(r, g, b).into()]).await;
+    }
+}
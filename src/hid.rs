@@ -0,0 +1,83 @@
+//! USB HID shifter, next to the console on the same USB device. A joystick
+//! with the `hid-joystick` feature, a keyboard with `hid-keyboard`. See
+//! `selector::hid`.
+
+use defmt::warn;
+
+use embassy_rp::peripherals::USB;
+use embassy_rp::usb::Driver;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_usb::class::hid::{self, HidWriter};
+use embassy_usb::Builder;
+
+use selector::{Gear, Selection};
+
+use crate::SHARED;
+
+#[cfg(all(feature = "hid-joystick", feature = "hid-keyboard"))]
+compile_error!("Select only one of `hid-joystick` and `hid-keyboard`");
+
+#[cfg(not(any(feature = "hid-joystick", feature = "hid-keyboard")))]
+compile_error!("Use `hid-joystick` or `hid-keyboard`, not `hid`");
+
+#[cfg(feature = "hid-joystick")]
+use selector::hid::{joystick_report, JOYSTICK_DESCRIPTOR as DESCRIPTOR, JOYSTICK_REPORT_SIZE as REPORT_SIZE};
+
+#[cfg(feature = "hid-keyboard")]
+use selector::hid::{DEFAULT_KEYMAP, KEYBOARD_DESCRIPTOR as DESCRIPTOR, KEYBOARD_REPORT_SIZE as REPORT_SIZE};
+
+// How long a key is held, in keyboard mode.
+#[cfg(feature = "hid-keyboard")]
+const KEY_PRESS_MS: u64 = 50;
+
+// Every gear change, from `Pico::changed()`.
+pub static HID_GEAR: Signal<ThreadModeRawMutex, Selection> = Signal::new();
+
+pub type Writer<'d> = HidWriter<'d, Driver<'d, USB>, REPORT_SIZE>;
+
+pub fn new<'d>(builder: &mut Builder<'d, Driver<'d, USB>>, state: &'d mut hid::State<'d>) -> Writer<'d> {
+    let config = hid::Config {
+	report_descriptor: DESCRIPTOR,
+	request_handler:   None,
+	poll_ms:           10,
+	max_packet_size:   REPORT_SIZE as u16,
+    };
+    HidWriter::new(builder, state, config)
+}
+
+pub async fn run(writer: &mut Writer<'_>) {
+    writer.ready().await;
+
+    // The joystick buttons are a state - tell the host what it is now.
+    // The keyboard only taps a key when it changes.
+    let mut gear = SHARED.lock(|s| s.borrow().selector.selection()).target();
+    #[cfg(feature = "hid-joystick")]
+    send(writer, gear).await;
+
+    loop {
+	let target = HID_GEAR.wait().await.target();
+	if target != gear {
+	    gear = target;
+	    send(writer, gear).await;
+	}
+    }
+}
+
+#[cfg(feature = "hid-joystick")]
+async fn send(writer: &mut Writer<'_>, gear: Option<Gear>) {
+    write(writer, &joystick_report(gear)).await;
+}
+
+#[cfg(feature = "hid-keyboard")]
+async fn send(writer: &mut Writer<'_>, gear: Option<Gear>) {
+    write(writer, &DEFAULT_KEYMAP.report(gear)).await;
+    embassy_time::Timer::after_millis(KEY_PRESS_MS).await;
+    write(writer, &DEFAULT_KEYMAP.report(None)).await;
+}
+
+async fn write(writer: &mut Writer<'_>, report: &[u8]) {
+    if let Err(e) = writer.write(report).await {
+	warn!("HID: {}", e);
+    }
+}
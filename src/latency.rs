@@ -0,0 +1,105 @@
+//! How long from a button to the LEDs - from the debounced press edge to
+//! the LED of that button's gear turning on for the gear change it caused.
+//! Kept per button, see `selector::latency`.
+//!
+//! That includes waiting for the gesture: a short or long press is only
+//! reported when it's released, and a short press of a button with a double
+//! press too (D) only when the double-press window is over. How much of
+//! each time that was, from the press to the gesture, is kept separately.
+
+use core::cell::{Cell, RefCell};
+
+use defmt::info;
+
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Instant;
+
+use selector::{Gear, Histogram, LatencyStats};
+
+use crate::board::{BOARD, MAX_POSITIONS};
+
+// Press to LED, and the part of that from the press to the gesture, in
+// `BOARD.positions` order.
+static HISTOGRAMS: Mutex<ThreadModeRawMutex, RefCell<[Histogram; MAX_POSITIONS]>> =
+    Mutex::new(RefCell::new([const { Histogram::new() }; MAX_POSITIONS]));
+static GESTURES: Mutex<ThreadModeRawMutex, RefCell<[Histogram; MAX_POSITIONS]>> =
+    Mutex::new(RefCell::new([const { Histogram::new() }; MAX_POSITIONS]));
+
+// When each button last went down, and when its reader last reported a
+// gesture, in `BOARD.positions` order.
+static PRESSED: Mutex<ThreadModeRawMutex, Cell<[Instant; MAX_POSITIONS]>> =
+    Mutex::new(Cell::new([Instant::from_ticks(0); MAX_POSITIONS]));
+static GESTURED: Mutex<ThreadModeRawMutex, Cell<[Instant; MAX_POSITIONS]>> =
+    Mutex::new(Cell::new([Instant::from_ticks(0); MAX_POSITIONS]));
+
+// The button whose gear change the LEDs are about to show, and its press.
+static PENDING: Mutex<ThreadModeRawMutex, Cell<Option<(usize, Instant)>>> = Mutex::new(Cell::new(None));
+
+fn stamp(times: &Mutex<ThreadModeRawMutex, Cell<[Instant; MAX_POSITIONS]>>, index: usize) {
+    times.lock(|t| {
+	let mut all = t.get();
+	all[index] = Instant::now();
+	t.set(all);
+    });
+}
+
+fn micros(from: Instant, to: Instant) -> u32 {
+    (to - from).as_micros().min(u32::MAX as u64) as u32
+}
+
+/// The button of `BOARD.positions[index]` went down (debounced), just now.
+pub fn press(index: usize) {
+    stamp(&PRESSED, index);
+}
+
+/// The button of `BOARD.positions[index]` made a gesture, just now.
+pub fn gesture(index: usize) {
+    stamp(&GESTURED, index);
+}
+
+/// The last gesture of `button` made the LEDs change. Call after the LED
+/// drivers have been told, before they get to run.
+pub fn start(button: Gear) {
+    let Some(index) = BOARD.positions.iter().position(|p| p.gear == button) else {
+	return;
+    };
+    let pressed = PRESSED.lock(|p| p.get()[index]);
+    let gestured = GESTURED.lock(|g| g.get()[index]);
+    let us = micros(pressed, gestured);
+    info!("Gesture {}: {}us", button.name(), us);
+    GESTURES.lock(|g| g.borrow_mut()[index].record(us));
+    PENDING.lock(|p| p.set(Some((index, pressed))));
+}
+
+/// The LED driver of `BOARD.positions[index]` set its pin to `level` after
+/// a command. Only the LED of the button passed to `start()` turning on
+/// counts - any other LED, or turning off, is some other part of the same
+/// gear change.
+pub fn led_set(index: usize, level: u8) {
+    let Some((button, at)) = PENDING.lock(|p| p.get()) else {
+	return;
+    };
+    if button != index || level == 0 {
+	return;
+    }
+    PENDING.lock(|p| p.set(None));
+    let us = micros(at, Instant::now());
+    info!("Latency {}: {}us", BOARD.positions[index].gear.name(), us);
+    HISTOGRAMS.lock(|h| h.borrow_mut()[index].record(us));
+}
+
+/// Press to LED, for the button of `BOARD.positions[index]`.
+pub fn stats(index: usize) -> LatencyStats {
+    HISTOGRAMS.lock(|h| h.borrow()[index].stats())
+}
+
+/// How much of `stats()` was waiting for the gesture.
+pub fn gesture_stats(index: usize) -> LatencyStats {
+    GESTURES.lock(|g| g.borrow()[index].stats())
+}
+
+pub fn clear() {
+    HISTOGRAMS.lock(|h| h.borrow_mut().iter_mut().for_each(Histogram::clear));
+    GESTURES.lock(|g| g.borrow_mut().iter_mut().for_each(Histogram::clear));
+}
@@ -1,158 +1,564 @@
-//! This example shows the ease of debouncing a button with async rust.
-//! Hook up a button or switch between pin 9 and ground.
+//! Gear selector firmware for the RP2040. One button and one LED per gear
+//! position, a NeoPixel showing what's going on, and the transmission told
+//! (and asked to confirm) over CAN or GPIO. The pins are in `board`, the
+//! logic itself in the `selector` crate, and so are the tasks that run it
+//! (`selector::tasks`) - this is the glue to the hardware, `Pico`.
 
 #![no_std]
 #![no_main]
 
-use defmt::info;
+use core::cell::{Cell, RefCell};
+use core::future::Future;
 
-use embassy_executor::Spawner;
-use embassy_rp::gpio::{AnyPin, Level, Input, Output, Pin, Pull};
-use embassy_time::{with_deadline, Duration, Instant, Timer};
+use defmt::{info, warn};
+
+use embassy_executor::{SpawnToken, Spawner};
+use embassy_futures::select::{select, Either};
+use embassy_rp::gpio::{Input, Level};
+use embassy_time::{Duration, Instant, Timer};
 use embassy_rp::bind_interrupts;
-use embassy_rp::peripherals::PIO0;
+use embassy_rp::flash::Flash;
+use embassy_rp::gpio::Pin;
+use embassy_rp::peripherals::{PIO0, UART0, USB};
 use embassy_rp::pio::{InterruptHandler, Pio};
+use embassy_rp::watchdog::Watchdog;
 use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
-use embassy_sync::channel::{Channel, Receiver};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::signal::Signal;
+
+use selector::console::{DisplayTask, Event};
+use selector::tasks::{self, Board, Confirmation, Shared, SAFE_GEAR};
+use selector::watchdog::Task;
+use selector::{
+    DtcArea, DtcStore, Edge, Fault, FaultLog, FreezeFrame, Gear, GearSelector, Gesture, Inputs, LedStatus, LogEvent,
+    Message, Reaction, Selection, State, StatusEvent, Storage, Subsystem, DEFAULT_AGING,
+};
 
 use ws2812;
-use debounce;
 
-use {defmt_rtt as _, panic_probe as _};
+use defmt_rtt as _;
+
+mod board;
+mod can;
+mod dimmer;
+mod events;
+mod flash;
+mod hal;
+mod latency;
+#[cfg(feature = "hid")]
+mod hid;
+mod mcp2515;
+mod panic;
+mod power;
+mod pwm;
+mod uart;
+mod usb;
+mod watchdog;
+use board::{InputPin, Ws2812Pin, BOARD, MAX_POSITIONS, NUM_POSITIONS};
+use can::{can_bus, CanEvent, CAN_TX};
+use dimmer::dimmer;
+use flash::{PicoFlash, DTC_OFFSET, STATE_OFFSET, STATE_SIZE};
+use hal::{Active, NeoPixel, Pwm};
+use power::Sleeper;
+use pwm::PwmLed;
+use uart::{uart, UART_TX};
+use usb::{usb, CONSOLE};
+
+// Gear to start in if there's no (valid) saved one.
+const FALLBACK_GEAR: Gear = Gear::P;
+
+// After the watchdog (or a panic) reset us we start in `SAFE_GEAR` - we
+// don't know what state we were in, so don't trust the saved gear.
+
+// How long to wait before writing changed trouble codes to flash, so a
+// fault that keeps coming back doesn't wear it out.
+const DTC_SAVE_MS: u64 = 10_000;
+
+// One per gear position, in `BOARD.positions` order. Only the first
+// `NUM_POSITIONS` are used.
+static LEDS: [Channel<ThreadModeRawMutex, LedStatus, 64>; MAX_POSITIONS] = [const { Channel::new() }; MAX_POSITIONS];
+
+// The selected gear, the interlock inputs (as last read by `read_inputs`),
+// the buttons, safe state and sleep - what the tasks share.
+static SHARED: Mutex<ThreadModeRawMutex, RefCell<Shared>> = Mutex::new(RefCell::new(Shared::new(match BOARD.confirm_ms {
+    Some(ms) => GearSelector::new(BOARD.interlock).confirm_within(ms),
+    None => GearSelector::new(BOARD.interlock),
+})));
+
+// Where the selected gear is saved. Set up in `main`.
+static STORAGE: Mutex<ThreadModeRawMutex, RefCell<Option<Storage<PicoFlash>>>> = Mutex::new(RefCell::new(None));
+
+// How many glitches the debouncer of each button have seen, in
+// `BOARD.positions` order.
+static GLITCHES: Mutex<ThreadModeRawMutex, Cell<[u32; MAX_POSITIONS]>> = Mutex::new(Cell::new([0; MAX_POSITIONS]));
 
-enum LedStatus { On, Off }
+// Gestures from all the button readers.
+static GESTURES: Channel<ThreadModeRawMutex, (Gear, Gesture), 16> = Channel::new();
 
-static CHANNEL_P: Channel<ThreadModeRawMutex, LedStatus, 64> = Channel::new();
-static CHANNEL_N: Channel<ThreadModeRawMutex, LedStatus, 64> = Channel::new();
-static CHANNEL_R: Channel<ThreadModeRawMutex, LedStatus, 64> = Channel::new();
-static CHANNEL_D: Channel<ThreadModeRawMutex, LedStatus, 64> = Channel::new();
+// Confirmations from the transmission.
+static CONFIRMED: Signal<ThreadModeRawMutex, Confirmation> = Signal::new();
 
-#[derive(Copy, Clone)]
-#[repr(u8)]
-enum Button { P, N, R, D }
+// What's going on, for the NeoPixel. Sent with `try_send`, a missed status
+// isn't worth blocking for.
+static STATUS: Channel<ThreadModeRawMutex, StatusEvent, 8> = Channel::new();
+
+// The most recent faults, since boot.
+static FAULTS: Mutex<ThreadModeRawMutex, RefCell<FaultLog<16>>> = Mutex::new(RefCell::new(FaultLog::new()));
+
+// Trouble codes, since they were last cleared. Loaded in `main`.
+static DTCS: Mutex<ThreadModeRawMutex, RefCell<DtcStore>> = Mutex::new(RefCell::new(DtcStore::new(DEFAULT_AGING)));
+
+// The trouble codes changed - save them within this many ms.
+static DTC_SAVE: Signal<ThreadModeRawMutex, u64> = Signal::new();
 
 bind_interrupts!(struct Irqs {
     PIO0_IRQ_0 => InterruptHandler<PIO0>;
+    ADC_IRQ_FIFO => embassy_rp::adc::InterruptHandler;
+    UART0_IRQ => embassy_rp::uart::BufferedInterruptHandler<UART0>;
+    USBCTRL_IRQ => embassy_rp::usb::InterruptHandler<USB>;
 });
 
 // ================================================================================
 
-#[embassy_executor::task(pool_size = 4)]
-async fn set_led(receiver: Receiver<'static, ThreadModeRawMutex, LedStatus, 64>, led_pin: AnyPin) {
-    let mut led = Output::new(led_pin, Level::Low);
+fn save_gear(gear: Gear) {
+    STORAGE.lock(|s| {
+	if let Some(storage) = s.borrow_mut().as_mut() {
+	    if let Err(e) = storage.store_gear(gear) {
+		warn!("Failed to save gear: {}", e);
+	    }
+	}
+    });
+}
 
-    loop {
-	match receiver.try_receive() {
-	    Ok(LedStatus::On)  => led.set_high(),
-	    Ok(LedStatus::Off) => led.set_low(),
-	    _ => Timer::after_millis(250).await, // Don't allow another button for quarter second.
+fn log_fault(fault: Fault) {
+    let now = Instant::now().as_millis();
+    FAULTS.lock(|f| f.borrow_mut().push(fault, now));
+    if fault.is_dtc() {
+	let frame = SHARED.lock(|s| {
+	    let s = s.borrow();
+	    FreezeFrame { gear: s.selector.selection().gear, buttons: s.buttons.pressed(), uptime: now }
+	});
+	DTCS.lock(|d| d.borrow_mut().record(fault, now, frame));
+	DTC_SAVE.signal(DTC_SAVE_MS);
+    }
+    CONSOLE.try_send(Event::Fault(fault)).ok();
+    events::log(match fault {
+	Fault::Rejected(rejection) => LogEvent::Rejected(rejection),
+	fault => LogEvent::Fault(fault),
+    });
+}
+
+// From the UART, USB or CAN. Saved right away.
+fn clear_dtcs() {
+    info!("Clearing trouble codes");
+    DTCS.lock(|d| d.borrow_mut().clear());
+    DTC_SAVE.signal(0);
+}
+
+// Spawn a task, dealing with it failing according to the fault policy.
+// Returns false if it failed.
+fn spawn<S>(spawner: Spawner, token: SpawnToken<S>, subsystem: Subsystem) -> bool {
+    match spawner.spawn(token) {
+	Ok(()) => true,
+	Err(_) => {
+	    tasks::handle_fault(&Pico, Fault::SpawnFailed(subsystem), 0);
+	    false
 	}
     }
 }
 
-#[embassy_executor::task(pool_size = 4)]
-async fn read_button(
-    spawner: Spawner,
-    button:  Button,
-    btn_pin: AnyPin,
-    led_pin: AnyPin)
-{
-    let mut btn = debounce::Debouncer::new(Input::new(btn_pin, Pull::Up), Duration::from_millis(20));
-
-    // Spawn off a LED driver for this button.
-    let receiver: Receiver<'static, ThreadModeRawMutex, LedStatus, 64>;
-    match button {
-	Button::P  => receiver = CHANNEL_P.receiver(),
-	Button::N  => receiver = CHANNEL_N.receiver(),
-	Button::R  => receiver = CHANNEL_R.receiver(),
-	Button::D  => receiver = CHANNEL_D.receiver(),
-    }
-    spawner.spawn(set_led(receiver, led_pin)).unwrap();
+fn boot_count() -> u32 {
+    STORAGE.lock(|s| s.borrow().as_ref().map_or(0, |s| s.state().boot_count))
+}
 
-    loop {
-        // button pressed
-        btn.debounce().await;
-        let start = Instant::now();
-        info!("Button Press");
-
-	// Don't really care how long a button have been pressed as,
-	// the `debounce()` will detect when it's been RELEASED.
-	match with_deadline(start + Duration::from_secs(1), btn.debounce()).await {
-            Ok(_) => {
-		info!("Button pressed for: {}ms", start.elapsed().as_millis());
-
-		// We know who WE are, so turn ON our own LED and turn off all the other LEDs.
-		// Turn on our OWN LED.
-		match button {
-		    Button::P  => {
-			CHANNEL_P.send(LedStatus::On).await;
-			CHANNEL_N.send(LedStatus::Off).await;
-			CHANNEL_R.send(LedStatus::Off).await;
-			CHANNEL_D.send(LedStatus::Off).await;
-
-			continue;
-		    }
-		    Button::N  => {
-			CHANNEL_P.send(LedStatus::Off).await;
-			CHANNEL_N.send(LedStatus::On).await;
-			CHANNEL_R.send(LedStatus::Off).await;
-			CHANNEL_D.send(LedStatus::Off).await;
-
-			continue;
-		    }
-		    Button::R  => {
-			CHANNEL_P.send(LedStatus::Off).await;
-			CHANNEL_N.send(LedStatus::Off).await;
-			CHANNEL_R.send(LedStatus::On).await;
-			CHANNEL_D.send(LedStatus::Off).await;
-
-			continue;
-		    }
-		    Button::D  => {
-			CHANNEL_P.send(LedStatus::Off).await;
-			CHANNEL_N.send(LedStatus::Off).await;
-			CHANNEL_R.send(LedStatus::Off).await;
-			CHANNEL_D.send(LedStatus::On).await;
-
-			continue;
-		    }
+// ================================================================================
+
+// What `selector::tasks` run on - the RP2040, under embassy.
+struct Pico;
+
+// `u64::MAX` is never.
+fn instant(ms: u64) -> Instant {
+    match ms {
+	u64::MAX => Instant::MAX,
+	ms => Instant::from_millis(ms),
+    }
+}
+
+impl Board for Pico {
+    fn now(&self) -> u64 {
+	Instant::now().as_millis()
+    }
+
+    fn at(&self, at: u64) -> impl Future<Output = ()> {
+	Timer::at(instant(at))
+    }
+
+    fn positions(&self) -> usize {
+	NUM_POSITIONS
+    }
+
+    fn gear(&self, index: usize) -> Gear {
+	BOARD.positions[index].gear
+    }
+
+    fn lock<R>(&self, f: impl FnOnce(&mut Shared) -> R) -> R {
+	SHARED.lock(|s| f(&mut s.borrow_mut()))
+    }
+
+    fn send_led(&self, index: usize, status: LedStatus) -> bool {
+	LEDS[index].try_send(status).is_ok()
+    }
+
+    fn receive_led(&self, index: usize) -> impl Future<Output = LedStatus> {
+	LEDS[index].receive()
+    }
+
+    fn send_status(&self, event: StatusEvent) -> bool {
+	STATUS.try_send(event).is_ok()
+    }
+
+    fn receive_status(&self) -> impl Future<Output = StatusEvent> {
+	STATUS.receive()
+    }
+
+    fn send_gesture(&self, button: Gear, gesture: Gesture) -> impl Future<Output = ()> {
+	GESTURES.send((button, gesture))
+    }
+
+    fn receive_gesture(&self) -> impl Future<Output = (Gear, Gesture)> {
+	GESTURES.receive()
+    }
+
+    fn receive_confirmation(&self) -> impl Future<Output = Confirmation> {
+	CONFIRMED.wait()
+    }
+
+    fn activity(&self) {
+	power::activity();
+    }
+
+    fn wait_activity(&self) -> impl Future<Output = ()> {
+	power::wait_activity()
+    }
+
+    fn stay_awake(&self) -> bool {
+	power::console_connected()
+    }
+
+    fn sleep(&self) -> impl Future<Output = ()> {
+	power::sleep()
+    }
+
+    fn wake(&self) {
+	power::wake();
+    }
+
+    fn woken(&self, index: usize) -> impl Future<Output = ()> {
+	power::woken(Sleeper::Button(index))
+    }
+
+    fn check_in(&self, task: Task) -> u64 {
+	watchdog::check_in(task)
+    }
+
+    fn button(&self, index: usize, edge: Edge) {
+	let button = BOARD.positions[index].gear;
+	let pressed = edge == Edge::Press;
+	CAN_TX.try_send(CanEvent::Button(button, pressed)).ok();
+	UART_TX.try_send(Message::Button(button, pressed)).ok();
+	CONSOLE.try_send(Event::Button(button, pressed)).ok();
+	match edge {
+	    Edge::Press => {
+		latency::press(index);
+		info!("Button Press");
+		events::log(LogEvent::Press(button));
+	    }
+	    Edge::Release(held) => {
+		info!("Button pressed for: {}ms", held);
+		events::log(LogEvent::Release(button, held as u32));
+	    }
+	}
+    }
+
+    fn gesture(&self, index: usize, gesture: Gesture) {
+	latency::gesture(index);
+	let button = BOARD.positions[index].gear;
+	info!("Gesture: {} {}", button.name(), gesture.kind().name());
+	if let Gesture::Long(held) = gesture {
+	    events::log(LogEvent::LongHold(button, held as u32));
+	}
+    }
+
+    fn changed(&self, selection: &Selection) {
+	info!("Gear: {}, requested: {} ({}{})", selection.gear.map_or("-", Gear::name),
+	      selection.requested.map_or("-", Gear::name), selection.mode.name(),
+	      if selection.park_lock { ", park lock" } else { "" });
+
+	CAN_TX.try_send(CanEvent::GearChanged).ok();
+	UART_TX.try_send(Message::GearChanged(*selection)).ok();
+	CONSOLE.try_send(Event::GearChanged(*selection)).ok();
+	events::log(LogEvent::GearChanged(*selection));
+	#[cfg(feature = "hid")]
+	hid::HID_GEAR.signal(*selection);
+    }
+
+    fn fault(&self, fault: Fault, reaction: Reaction) {
+	match reaction {
+	    Reaction::Retry => warn!("Fault: {}, retrying", defmt::Display2Format(&Event::Fault(fault))),
+	    Reaction::Log => {
+		if let Fault::Rejected(rejection) = fault {
+		    info!("Gear change rejected: {}", rejection.reason());
 		}
-            }
-	    Err(_) => info!("Button Long Held")
+		log_fault(fault);
+	    }
+	    Reaction::Degrade => {
+		warn!("Fault: {}, carrying on without it", defmt::Display2Format(&Event::Fault(fault)));
+		log_fault(fault);
+	    }
+	    Reaction::SafeState => {
+		warn!("Fault: {}, going to {}", defmt::Display2Format(&Event::Fault(fault)), SAFE_GEAR.name());
+		log_fault(fault);
+	    }
 	}
+    }
+
+    fn glitches(&self, index: usize, glitches: u32) {
+	GLITCHES.lock(|g| {
+	    let mut all = g.get();
+	    all[index] = glitches;
+	    g.set(all);
+	});
+    }
+
+    fn applied(&self, button: Gear) {
+	latency::start(button);
+    }
+
+    fn led_set(&self, index: usize, level: u8) {
+	latency::led_set(index, level);
+    }
 
-	// wait for button release before handling another press
-	btn.debounce().await;
-	info!("Button pressed for: {}ms", start.elapsed().as_millis());
+    fn save_gear(&self, gear: Gear) {
+	save_gear(gear);
     }
 }
 
+// ================================================================================
+
+// Drives the LED of `BOARD.positions[index]`.
+#[embassy_executor::task(pool_size = 8)] // MAX_POSITIONS
+async fn set_led(index: usize) {
+    let position = BOARD.positions[index];
+    let output = Pwm(PwmLed::new(board::pin(position.led.pin), position.led.active));
+    let sense = position.sense
+	.map(|pin| Active::new(Input::new(board::pin(pin.pin), pin.pull), pin.active == Level::High));
+    tasks::led_loop(&Pico, index, output, sense).await
+}
+
+#[embassy_executor::task]
+async fn read_inputs(brake_pin: InputPin, standstill_pin: InputPin) {
+    let mut brake = Input::new(board::pin(brake_pin.pin), brake_pin.pull);
+    let mut standstill = Input::new(board::pin(standstill_pin.pin), standstill_pin.pull);
+
+    loop {
+	let inputs = Inputs {
+	    brake:      brake.get_level() == brake_pin.active,
+	    standstill: standstill.get_level() == standstill_pin.active,
+	};
+	SHARED.lock(|s| s.borrow_mut().inputs = inputs);
+
+	select(brake.wait_for_any_edge(), standstill.wait_for_any_edge()).await;
+    }
+}
+
+// The confirmation input going active confirms whatever gear we've requested.
+#[embassy_executor::task]
+async fn read_confirm(confirm_pin: InputPin) {
+    let mut confirm = Input::new(board::pin(confirm_pin.pin), confirm_pin.pull);
+
+    loop {
+	match confirm_pin.active {
+	    Level::Low  => confirm.wait_for_falling_edge().await,
+	    Level::High => confirm.wait_for_rising_edge().await,
+	}
+	CONFIRMED.signal(Confirmation::Requested);
+    }
+}
+
+// Writes the trouble codes to flash, when they've changed.
+#[embassy_executor::task]
+async fn save_dtcs(mut area: DtcArea) {
+    loop {
+	// Wait for the first change, and then until the shortest delay any
+	// change asked for has passed.
+	let mut at = Instant::now() + Duration::from_millis(DTC_SAVE.wait().await);
+	while let Either::Second(ms) = select(Timer::at(at), DTC_SAVE.wait()).await {
+	    at = at.min(Instant::now() + Duration::from_millis(ms));
+	}
+
+	let dtcs = DTCS.lock(|d| d.borrow().clone());
+	STORAGE.lock(|s| {
+	    if let Some(storage) = s.borrow_mut().as_mut() {
+		if let Err(e) = area.store(storage.flash(), &dtcs) {
+		    warn!("Failed to save trouble codes: {}", e);
+		}
+	    }
+	});
+    }
+}
+
+// See `selector::tasks::gesture_loop()`.
+#[embassy_executor::task]
+async fn handle_gestures() {
+    tasks::gesture_loop(&Pico).await
+}
+
+// Reads the button of `BOARD.positions[index]`.
+#[embassy_executor::task(pool_size = 8)] // MAX_POSITIONS
+async fn read_button(spawner: Spawner, index: usize) {
+    let position = BOARD.positions[index];
+    let input = Input::new(board::pin(position.button.pin), position.button.pull);
+
+    // Spawn off a LED driver for this button. It doesn't run until we
+    // yield, so there's no hurry watching it.
+    if spawn(spawner, set_led(index), Subsystem::Leds) {
+	watchdog::expect(Task::Led(index as u8));
+    }
+
+    tasks::button_loop(&Pico, index, Active::new(input, position.button.active == Level::High)).await
+}
+
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
     info!("Start");
 
     let p = embassy_rp::init(Default::default());
 
+    // =====
+    // Find out if the watchdog reset us, before anything else can.
+    let mut wd = Watchdog::new(p.WATCHDOG);
+    let watchdog_reset = watchdog::reset_cause(&mut wd);
+
+    // Or if we panicked.
+    let panicked = panic::take();
+
+    // =====
+    // Restore the gear we were in before the power went, and count the boot.
+    let mut storage = Storage::new(PicoFlash(Flash::new_blocking(p.FLASH)), STATE_OFFSET, STATE_SIZE);
+    let state = match storage.load() {
+	Ok(Some(state)) => state,
+	Ok(None) => {
+	    warn!("No valid saved state, using {}", FALLBACK_GEAR.name());
+	    State::default()
+	}
+	Err(e) => {
+	    warn!("Failed to read saved state: {}", e);
+	    State::default()
+	}
+    };
+
+    // =====
+    // Load the trouble codes, and forget the ones that haven't come back in
+    // a long time.
+    let mut dtc_area = DtcArea::new(DTC_OFFSET);
+    DTCS.lock(|d| {
+	let mut dtcs = d.borrow_mut();
+	match dtc_area.load(storage.flash(), &mut dtcs) {
+	    Ok(true) => info!("{} trouble codes", dtcs.len()),
+	    Ok(false) => info!("No saved trouble codes"),
+	    Err(e) => warn!("Failed to read trouble codes: {}", e),
+	}
+	if dtcs.start(state.boot_count + 1) {
+	    DTC_SAVE.signal(DTC_SAVE_MS);
+	}
+    });
+    events::start(state.boot_count + 1, storage.flash());
+
+    // =====
+    // Go to the safe gear if something went wrong last time.
+    let gear = match watchdog_reset {
+	Some(task) => {
+	    warn!("Reset by the watchdog ({}), going to {}", defmt::Display2Format(&DisplayTask(task)), SAFE_GEAR.name());
+	    log_fault(Fault::WatchdogReset(task));
+	    SAFE_GEAR
+	}
+	None => state.gear.unwrap_or(FALLBACK_GEAR),
+    };
+    let gear = match panicked {
+	Some(record) => {
+	    warn!("Panicked at {}:{}:{}, going to {}", record.file(), record.line(), record.column(), SAFE_GEAR.name());
+	    log_fault(Fault::Panic);
+	    SAFE_GEAR
+	}
+	None => gear,
+    };
+    let state = State { gear: Some(gear), boot_count: state.boot_count + 1 };
+    if let Err(e) = storage.store(state) {
+	warn!("Failed to save state: {}", e);
+    }
+    STORAGE.lock(|s| s.replace(Some(storage)));
+
+    let restored = SHARED.lock(|s| s.borrow_mut().selector.restore(gear));
+    info!("Boot #{}, gear {}", state.boot_count, gear.name());
+
     // =====
     // Initialize the NeoPixel LED.
     let Pio { mut common, sm0, .. } = Pio::new(p.PIO0, Irqs);
-    let mut ws2812 = ws2812::Ws2812::new(&mut common, sm0, p.DMA_CH0, p.PIN_15);
+    // Safety: the board config is checked for duplicates, nothing else uses this pin.
+    let ws2812_pin = unsafe { Ws2812Pin::steal() };
+    defmt::assert_eq!(ws2812_pin.pin(), BOARD.ws2812, "Ws2812Pin doesn't match the board config");
+    let mut pixel = NeoPixel(ws2812::Ws2812::new(&mut common, sm0, p.DMA_CH0, ws2812_pin));
 
-    // Spawn off one button reader per button.
-    spawner.spawn(read_button(spawner, Button::P, p.PIN_2.degrade(), p.PIN_6.degrade())).unwrap(); // button/P
-    spawner.spawn(read_button(spawner, Button::N, p.PIN_3.degrade(), p.PIN_7.degrade())).unwrap(); // button/N
-    spawner.spawn(read_button(spawner, Button::R, p.PIN_4.degrade(), p.PIN_8.degrade())).unwrap(); // button/R
-    spawner.spawn(read_button(spawner, Button::D, p.PIN_5.degrade(), p.PIN_9.degrade())).unwrap(); // button/D
+    // Spawn off one button reader per gear position.
+    for index in 0..BOARD.positions.len() {
+	if spawn(spawner, read_button(spawner, index), Subsystem::Buttons) {
+	    watchdog::expect(Task::Button(index as u8));
+	}
+    }
 
-    // =====
-    info!("Debounce Demo");
-    loop {
-	// Set the NeoPixel BLUE.
-	ws2812.write(&[(0,0,255).into()]).await;
-	Timer::after_secs(1).await;
+    // Show the restored gear (unless a button reader failed, and we're
+    // already showing the safe one).
+    if !SHARED.lock(|s| s.borrow().safe_state) {
+	tasks::show_leds(&Pico, &restored);
+    }
+
+    // Gestures to gear changes.
+    spawn(spawner, handle_gestures(), Subsystem::Gestures);
+
+    // Interlock inputs.
+    spawn(spawner, read_inputs(BOARD.brake, BOARD.standstill), Subsystem::Inputs);
 
-	// Turn off the NeoPixel
-	ws2812.write(&[(0,0,0).into()]).await;
-	Timer::after_secs(1).await;
+    // Gear confirmation from the transmission.
+    spawn(spawner, read_confirm(BOARD.confirm), Subsystem::Confirm);
+
+    // Dashboard dimmer.
+    spawn(spawner, dimmer(p.ADC, p.PIN_26), Subsystem::Dimmer);
+
+    // CAN bus, MCP2515 on SPI0.
+    spawn(spawner, can_bus(p.SPI0, p.PIN_18, p.PIN_19, p.PIN_16, p.DMA_CH1, p.DMA_CH2,
+			   board::pin(BOARD.can_cs), board::pin(BOARD.can_int)), Subsystem::Can);
+
+    // Bench control and monitoring.
+    spawn(spawner, uart(p.UART0, p.PIN_0, p.PIN_1), Subsystem::Uart);
+
+    // USB console.
+    spawn(spawner, usb(p.USB), Subsystem::Usb);
+
+    // Sleep when parked and idle, wake on a button or the ignition.
+    spawn(spawner, power::power(BOARD.ignition), Subsystem::Power);
+
+    // Trouble codes to flash.
+    spawn(spawner, save_dtcs(dtc_area), Subsystem::Storage);
+
+    // Event log to flash.
+    #[cfg(feature = "event-spill")]
+    spawn(spawner, events::spill(), Subsystem::Storage);
+
+    // Only feed the watchdog while everything above keeps checking in.
+    if spawn(spawner, watchdog::feed(wd), Subsystem::Watchdog) {
+	watchdog::expect(Task::Status);
     }
+
+    tasks::status_loop(&Pico, &mut pixel, restored).await
 }
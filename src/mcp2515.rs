@@ -0,0 +1,156 @@
+//! Just enough of a MCP2515 driver to send and receive standard frames.
+//!
+//! Set up for a 8MHz crystal (what most of the cheap modules have) and
+//! 500kbit/s. Only TX buffer 0 and RX buffer 0 are used, RX0 also drives
+//! the INT pin.
+
+use embedded_hal::digital::OutputPin;
+use embedded_hal_async::spi::SpiBus;
+
+use selector::Frame;
+
+// Instructions.
+const RESET: u8       = 0xC0;
+const READ: u8        = 0x03;
+const WRITE: u8       = 0x02;
+const BIT_MODIFY: u8  = 0x05;
+const READ_STATUS: u8 = 0xA0;
+const LOAD_TX0: u8    = 0x40;
+const RTS_TX0: u8     = 0x81;
+const READ_RX0: u8    = 0x90;
+
+// Registers.
+const CANSTAT: u8  = 0x0E;
+const CANCTRL: u8  = 0x0F;
+const CNF3: u8     = 0x28;
+const CNF2: u8     = 0x29;
+const CNF1: u8     = 0x2A;
+const CANINTE: u8  = 0x2B;
+const CANINTF: u8  = 0x2C;
+const EFLG: u8     = 0x2D;
+const RXB0CTRL: u8 = 0x60;
+
+// Modes (CANCTRL/CANSTAT bits 7:5).
+const MODE_MASK: u8   = 0xE0;
+const MODE_NORMAL: u8 = 0x00;
+const MODE_CONFIG: u8 = 0x80;
+
+// READ_STATUS bits.
+const STATUS_RX0IF: u8 = 0x01;
+const STATUS_TX0REQ: u8 = 0x04;
+
+#[derive(Debug, defmt::Format)]
+pub enum Error<E> {
+    Spi(E),
+
+    /// Didn't go into the mode we asked for - usually means there's no
+    /// MCP2515 there at all.
+    Mode,
+
+    /// Previous frame still haven't gone out.
+    Busy,
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(e: E) -> Self {
+	Error::Spi(e)
+    }
+}
+
+pub struct Mcp2515<SPI, CS> {
+    spi: SPI,
+    cs:  CS,
+}
+
+impl<SPI: SpiBus, CS: OutputPin> Mcp2515<SPI, CS> {
+    pub fn new(spi: SPI, cs: CS) -> Self {
+	Self { spi, cs }
+    }
+
+    pub async fn init(&mut self) -> Result<(), Error<SPI::Error>> {
+	self.transfer(&mut [RESET]).await?;
+	embassy_time::Timer::after_millis(5).await;
+	self.set_mode(MODE_CONFIG).await?;
+
+	// 500kbit/s from 8MHz.
+	self.write(CNF1, 0x00).await?;
+	self.write(CNF2, 0x90).await?;
+	self.write(CNF3, 0x02).await?;
+
+	// Receive everything in RX0, and interrupt on it.
+	self.write(RXB0CTRL, 0x60).await?;
+	self.write(CANINTE, 0x01).await?;
+
+	self.set_mode(MODE_NORMAL).await
+    }
+
+    pub async fn send(&mut self, frame: &Frame) -> Result<(), Error<SPI::Error>> {
+	if self.status().await? & STATUS_TX0REQ != 0 {
+	    return Err(Error::Busy);
+	}
+
+	let mut buf = [0u8; 14];
+	buf[0] = LOAD_TX0;
+	buf[1] = (frame.id >> 3) as u8;        // SIDH
+	buf[2] = ((frame.id & 0x07) << 5) as u8; // SIDL
+	buf[5] = frame.len;                     // DLC
+	buf[6..14].copy_from_slice(&frame.data);
+	self.transfer(&mut buf[..6 + frame.len as usize]).await?;
+
+	self.transfer(&mut [RTS_TX0]).await
+    }
+
+    /// A received frame, if there is one. Reading it clears the interrupt.
+    pub async fn receive(&mut self) -> Result<Option<Frame>, Error<SPI::Error>> {
+	if self.status().await? & STATUS_RX0IF == 0 {
+	    return Ok(None);
+	}
+
+	let mut buf = [0u8; 14];
+	buf[0] = READ_RX0;
+	self.transfer(&mut buf).await?;
+
+	let id = ((buf[1] as u16) << 3) | ((buf[2] as u16) >> 5);
+	let len = (buf[5] & 0x0F).min(8) as usize;
+	Ok(Some(Frame::new(id, &buf[6..6 + len])))
+    }
+
+    /// Clear every interrupt flag, and the receive overflows - so a stuck
+    /// INT lets go.
+    pub async fn clear_interrupts(&mut self) -> Result<(), Error<SPI::Error>> {
+	self.write(CANINTF, 0x00).await?;
+	self.write(EFLG, 0x00).await
+    }
+
+    async fn set_mode(&mut self, mode: u8) -> Result<(), Error<SPI::Error>> {
+	self.transfer(&mut [BIT_MODIFY, CANCTRL, MODE_MASK, mode]).await?;
+	match self.read(CANSTAT).await? & MODE_MASK == mode {
+	    true  => Ok(()),
+	    false => Err(Error::Mode),
+	}
+    }
+
+    async fn status(&mut self) -> Result<u8, Error<SPI::Error>> {
+	let mut buf = [READ_STATUS, 0];
+	self.transfer(&mut buf).await?;
+	Ok(buf[1])
+    }
+
+    async fn read(&mut self, reg: u8) -> Result<u8, Error<SPI::Error>> {
+	let mut buf = [READ, reg, 0];
+	self.transfer(&mut buf).await?;
+	Ok(buf[2])
+    }
+
+    async fn write(&mut self, reg: u8, value: u8) -> Result<(), Error<SPI::Error>> {
+	self.transfer(&mut [WRITE, reg, value]).await
+    }
+
+    // One instruction, with CS held low for all of it.
+    async fn transfer(&mut self, buf: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+	self.cs.set_low().ok();
+	let result = self.spi.transfer_in_place(buf).await;
+	self.cs.set_high().ok();
+	Ok(result?)
+    }
+}
@@ -0,0 +1,55 @@
+//! Panic handler. Instead of halting (and leaving the gear LEDs however
+//! they were), write down where we panicked and reset. The record is kept
+//! in RAM that isn't cleared at boot, and reported by `main` afterwards.
+
+use core::cell::Cell;
+use core::mem::MaybeUninit;
+use core::panic::PanicInfo;
+use core::ptr::{addr_of, addr_of_mut};
+
+use cortex_m::peripheral::SCB;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+use selector::PanicRecord;
+
+// Not zeroed by the runtime, so it survives the reset. Garbage after a
+// power-on, which is what the magic and the CRC in the record are for.
+#[link_section = ".uninit.PANIC"]
+static mut PANIC: MaybeUninit<PanicRecord> = MaybeUninit::uninit();
+
+// What `take()` found, for the console.
+static LAST: Mutex<ThreadModeRawMutex, Cell<Option<PanicRecord>>> = Mutex::new(Cell::new(None));
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+
+    let (file, line, column) = info.location().map_or(("?", 0, 0), |l| (l.file(), l.line(), l.column()));
+    defmt::error!("Panic at {}:{}:{}", file, line, column);
+
+    // Safety: interrupts are off and we never return, nothing else can get at it.
+    unsafe { addr_of_mut!(PANIC).write_volatile(MaybeUninit::new(PanicRecord::new(file, line, column))) };
+
+    SCB::sys_reset();
+}
+
+/// Where we panicked before the last reset, if we did. Clears it, so the
+/// next boot doesn't report it again.
+pub fn take() -> Option<PanicRecord> {
+    // Safety: only called from `main` before anything is spawned, and the
+    // bytes are only trusted if the record checks out.
+    let record = unsafe {
+	let record = addr_of!(PANIC).read_volatile().assume_init();
+	addr_of_mut!(PANIC).write_volatile(MaybeUninit::new(PanicRecord::empty()));
+	record
+    };
+    let record = record.is_valid().then_some(record);
+    LAST.lock(|l| l.set(record));
+    record
+}
+
+/// What `take()` returned.
+pub fn last() -> Option<PanicRecord> {
+    LAST.lock(|l| l.get())
+}
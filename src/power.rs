@@ -0,0 +1,160 @@
+//! Low power idle. After `DEFAULT_POWER.idle_ms` in P with nothing going
+//! on, the LEDs and the NeoPixel go dark, clk_sys is slowed right down and
+//! nothing wakes up by itself any more - no check-ins, no CAN frames, no
+//! dimmer polling - so the executor sleeps (WFE) until an interrupt. A
+//! button or the ignition wakes everything up again, as it was. The when
+//! is `selector::tasks::power_loop()`, this is the how.
+//!
+//! It's sleep rather than dormant. Dormant stops the crystal, and with it
+//! the timer embassy keeps time with, and the PLLs would need bringing back
+//! by hand. clk_peri (the UART and the SPI to the CAN controller) normally
+//! runs off clk_sys, so it's moved straight onto the system PLL first - same
+//! rate, not slowed down with it. We don't sleep at all while the USB
+//! console is connected.
+
+use core::cell::Cell;
+
+use defmt::info;
+
+use embassy_rp::gpio::{Input, Level};
+use embassy_rp::pac;
+use embassy_rp::pac::clocks::regs::{ClkPeriCtrl, ClkSysDiv};
+use embassy_rp::pac::clocks::vals::ClkPeriCtrlAuxsrc;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::Timer;
+
+use selector::tasks;
+
+use crate::board::{self, InputPin, MAX_POSITIONS};
+use crate::hal::Active;
+use crate::{Pico, SHARED};
+
+// Give the LED drivers and the NeoPixel this long to go dark before the
+// clocks go down - the NeoPixel timing depends on clk_sys.
+const SETTLE_MS: u64 = 50;
+
+// clk_sys is divided by this while asleep, 125MHz down to about 2MHz.
+const SLEEP_DIV: u32 = 64;
+
+// clk_peri has no glitchless mux - it has to be stopped for this many of
+// its cycles before switching it.
+const PERI_STOP_CYCLES: u32 = 3;
+
+/// The tasks that wait for us to wake up, rather than waking up regularly.
+#[derive(Copy, Clone)]
+pub enum Sleeper {
+    Watchdog,
+    Dimmer,
+    Can,
+
+    /// The button reader of position n - in case it's the ignition that
+    /// wakes us.
+    Button(usize),
+}
+
+impl Sleeper {
+    fn index(self) -> usize {
+	match self {
+	    Sleeper::Watchdog  => 0,
+	    Sleeper::Dimmer    => 1,
+	    Sleeper::Can       => 2,
+	    Sleeper::Button(n) => 3 + n,
+	}
+    }
+}
+
+// Something happened - a button, a gear change, a command.
+static ACTIVITY: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+// The USB console is connected.
+static CONSOLE: Mutex<ThreadModeRawMutex, Cell<bool>> = Mutex::new(Cell::new(false));
+
+// How clk_sys was divided and where clk_peri came from before we went to
+// sleep, while asleep.
+static CLOCKS: Mutex<ThreadModeRawMutex, Cell<Option<(ClkSysDiv, ClkPeriCtrl)>>> = Mutex::new(Cell::new(None));
+
+// One per `Sleeper`, a `Signal` only wakes one waiter.
+static WAKE: [Signal<ThreadModeRawMutex, ()>; 3 + MAX_POSITIONS] = [const { Signal::new() }; 3 + MAX_POSITIONS];
+
+pub fn asleep() -> bool {
+    SHARED.lock(|s| s.borrow().asleep)
+}
+
+/// Something happened, so we're not idle - and if we were asleep, wake up.
+pub fn activity() {
+    ACTIVITY.signal(());
+}
+
+pub async fn wait_activity() {
+    ACTIVITY.wait().await
+}
+
+/// The USB console was connected, or disconnected.
+pub fn console(connected: bool) {
+    CONSOLE.lock(|c| c.set(connected));
+    ACTIVITY.signal(());
+}
+
+/// We don't sleep while the USB console is connected.
+pub fn console_connected() -> bool {
+    CONSOLE.lock(|c| c.get())
+}
+
+/// Returns when we wake up next. Any wake-up from before we last went to
+/// sleep is forgotten, but check `asleep()` first anyway.
+pub async fn woken(sleeper: Sleeper) {
+    WAKE[sleeper.index()].wait().await;
+}
+
+#[embassy_executor::task]
+pub async fn power(ignition_pin: Option<InputPin>) {
+    let ignition = ignition_pin
+	.map(|pin| Active::new(Input::new(board::pin(pin.pin), pin.pull), pin.active == Level::High));
+    tasks::power_loop(&Pico, ignition).await
+}
+
+/// The LEDs have been told to go dark - give them time to, and slow down.
+pub async fn sleep() {
+    info!("Idle, going to sleep");
+
+    // Only what `wake()` signals from now on wakes the sleepers, not what
+    // was left over from last time.
+    for wake in &WAKE {
+	wake.reset();
+    }
+    Timer::after_millis(SETTLE_MS).await;
+
+    let div = pac::CLOCKS.clk_sys_div().read();
+    let peri = pac::CLOCKS.clk_peri_ctrl().read();
+    CLOCKS.lock(|c| c.set(Some((div, peri))));
+
+    // clk_sys comes straight from the PLL, so this doesn't change the rate.
+    let mut pll = peri;
+    pll.set_auxsrc(ClkPeriCtrlAuxsrc::CLKSRC_PLL_SYS);
+    set_clk_peri(pll);
+    pac::CLOCKS.clk_sys_div().write(|w| w.set_int(SLEEP_DIV));
+}
+
+/// Back to full speed, and wake up everyone waiting for it.
+pub fn wake() {
+    if let Some((div, peri)) = CLOCKS.lock(|c| c.take()) {
+	pac::CLOCKS.clk_sys_div().write_value(div);
+	set_clk_peri(peri);
+    }
+    info!("Awake");
+
+    // The rest wake up by themselves as soon as they're asked to do
+    // something, or check in.
+    for wake in &WAKE {
+	wake.signal(());
+    }
+}
+
+// Only with clk_sys at full speed, so the delay is long enough.
+fn set_clk_peri(ctrl: ClkPeriCtrl) {
+    pac::CLOCKS.clk_peri_ctrl().modify(|w| w.set_enable(false));
+    cortex_m::asm::delay(PERI_STOP_CYCLES);
+    pac::CLOCKS.clk_peri_ctrl().write_value(ctrl);
+}
@@ -0,0 +1,75 @@
+//! A single PWM output, driven straight on the registers.
+//!
+//! The gear LEDs pair up on the PWM slices (on the Pico board, PIN_6/7 on
+//! slice 3 and PIN_8/9 on slice 4), and the HAL `Pwm` wants the whole slice
+//! - which doesn't work with one task per LED. Each `PwmLed` only ever
+//! touches its own channel.
+
+use core::convert::Infallible;
+
+use embassy_rp::gpio::{AnyPin, Level, Pin};
+use embassy_rp::pac;
+use embassy_rp::pac::io::vals::Outover;
+use embedded_hal::pwm::{ErrorType, SetDutyCycle};
+
+// 255 * 255, so a squared level (see `hal::Pwm`) is the compare value as
+// is. Gives about 1.9kHz at 125MHz - no flicker.
+const TOP: u16 = 65025;
+
+// IO_BANK0 function select for PWM.
+const FUNCSEL_PWM: u8 = 4;
+
+pub struct PwmLed {
+    slice: usize,
+    b:     bool,
+}
+
+impl PwmLed {
+    /// `active` is the level that turns the LED on - if it's low, the
+    /// output is inverted.
+    pub fn new(pin: AnyPin, active: Level) -> Self {
+	let n = pin.pin() as usize;
+	let slice = (n >> 1) & 7;
+
+	pac::PADS_BANK0.gpio(n).modify(|w| {
+	    w.set_od(false);
+	    w.set_ie(false);
+	});
+	pac::IO_BANK0.gpio(n).ctrl().write(|w| {
+	    w.set_funcsel(FUNCSEL_PWM);
+	    w.set_outover(match active {
+		Level::High => Outover::NORMAL,
+		Level::Low  => Outover::INVERT,
+	    });
+	});
+
+	// Both channels of a slice share these, so setting them twice is fine.
+	let ch = pac::PWM.ch(slice);
+	ch.div().write(|w| w.set_int(1));
+	ch.top().write(|w| w.set_top(TOP));
+	ch.csr().modify(|w| w.set_en(true));
+
+	let mut led = Self { slice, b: n & 1 == 1 };
+	_ = led.set_duty_cycle(0);
+	led
+    }
+}
+
+impl ErrorType for PwmLed {
+    type Error = Infallible;
+}
+
+impl SetDutyCycle for PwmLed {
+    fn max_duty_cycle(&self) -> u16 {
+	TOP
+    }
+
+    fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Infallible> {
+	let compare = duty.min(TOP);
+	pac::PWM.ch(self.slice).cc().modify(|w| match self.b {
+	    true  => w.set_b(compare),
+	    false => w.set_a(compare),
+	});
+	Ok(())
+    }
+}
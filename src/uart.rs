@@ -0,0 +1,134 @@
+//! Bench control and monitoring over UART0 - TX on PIN_0, RX on PIN_1,
+//! 115200 8N1. See `selector::protocol` for what goes over it, and
+//! `host/` for the other end.
+
+use defmt::{info, warn};
+
+use embassy_futures::select::{select, Either};
+use embassy_rp::peripherals::{PIN_0, PIN_1, UART0};
+use embassy_rp::uart::{self, BufferedUart, BufferedUartTx};
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::channel::Channel;
+use embedded_io_async::{Read, Write};
+
+use selector::protocol::{self, Config, Decoder, Nak, MAX_FRAME};
+use selector::tasks;
+use selector::{Action, Command, Message, Packet, DEFAULT_THRESHOLDS};
+
+use crate::board::BOARD;
+use crate::dimmer::DIMMER_LEVEL;
+use crate::{events, latency, power};
+use crate::{boot_count, clear_dtcs, Irqs, Pico, DTCS, FAULTS, SHARED};
+
+const BAUDRATE: u32 = 115200;
+
+// Events to push to the host. Sent with `try_send` - nobody might be
+// listening.
+pub static UART_TX: Channel<ThreadModeRawMutex, Message, 16> = Channel::new();
+
+#[embassy_executor::task]
+pub async fn uart(uart: UART0, tx_pin: PIN_0, rx_pin: PIN_1) {
+    let mut tx_buf = [0; 64];
+    let mut rx_buf = [0; 64];
+    let mut config = uart::Config::default();
+    config.baudrate = BAUDRATE;
+    let uart = BufferedUart::new(uart, Irqs, tx_pin, rx_pin, &mut tx_buf, &mut rx_buf, config);
+    let (mut tx, mut rx) = uart.split();
+
+    let mut decoder = Decoder::new();
+    let mut byte = [0];
+    loop {
+	match select(rx.read(&mut byte), UART_TX.receive()).await {
+	    Either::First(Ok(_)) => {
+		let command = match decoder.push(byte[0]) {
+		    None => continue,
+		    Some(payload) => payload.and_then(Command::read),
+		};
+		match command {
+		    Ok(command) => handle(&mut tx, command).await,
+		    Err(e) => {
+			warn!("UART: {}", e.reason());
+			send(&mut tx, &Message::Nak(Nak::BadCommand)).await;
+		    }
+		}
+	    }
+	    Either::First(Err(e)) => warn!("UART: {}", e),
+	    Either::Second(message) => send(&mut tx, &message).await,
+	}
+    }
+}
+
+async fn handle(tx: &mut BufferedUartTx<'_, UART0>, command: Command) {
+    info!("UART command: {}", defmt::Debug2Format(&command));
+    power::activity();
+
+    match command {
+	Command::SelectGear(gear) => match tasks::apply(&Pico, Action::Select(gear)) {
+	    // The Gear changed event from `Pico::changed()` only goes out
+	    // after the Ack, so answer with where we ended up too.
+	    Ok(selection) => send(tx, &Message::State(selection)).await,
+	    Err(rejection) => {
+		send(tx, &Message::Nak(Nak::Rejected(rejection))).await;
+		return;
+	    }
+	},
+	Command::QueryState => {
+	    let selection = SHARED.lock(|s| s.borrow().selector.selection());
+	    send(tx, &Message::State(selection)).await;
+	}
+	Command::SetBrightness(level) => DIMMER_LEVEL.signal(level),
+	Command::ReadConfig => {
+	    let config = Config {
+		positions:  BOARD.positions.iter().fold(0, |p, position| p | 1 << position.gear as u8),
+		confirm_ms: BOARD.confirm_ms.unwrap_or(0) as u16,
+		long_ms:    DEFAULT_THRESHOLDS.long_ms as u16,
+		double_ms:  DEFAULT_THRESHOLDS.double_ms as u16,
+		boot_count: boot_count(),
+	    };
+	    send(tx, &Message::Config(config)).await;
+	}
+	Command::ReadFaultLog => {
+	    // Copy them out, so we don't hold the lock while sending.
+	    let faults = FAULTS.lock(|f| f.borrow().clone());
+	    for (index, record) in faults.iter().enumerate() {
+		send(tx, &Message::Fault(index as u8, *record)).await;
+	    }
+	}
+	Command::ReadDtcs => {
+	    let dtcs = DTCS.lock(|d| d.borrow().clone());
+	    for (index, dtc) in dtcs.iter().enumerate() {
+		send(tx, &Message::Dtc(index as u8, *dtc)).await;
+	    }
+	}
+	Command::ClearDtcs => clear_dtcs(),
+	Command::ReadEvents(false) => {
+	    let events = events::ram();
+	    for (index, entry) in events.iter().enumerate() {
+		send(tx, &Message::Event(index as u16, *entry)).await;
+	    }
+	}
+	Command::ReadEvents(true) => {
+	    // Read one at a time, there's a lot more of them. Empty slots
+	    // are skipped, but still counted.
+	    for index in 0..events::flash_slots() {
+		if let Some(entry) = events::flash_entry(index) {
+		    send(tx, &Message::Event(index as u16, entry)).await;
+		}
+	    }
+	}
+	Command::ReadLatency => {
+	    for (index, position) in BOARD.positions.iter().enumerate() {
+		send(tx, &Message::Latency(position.gear, latency::stats(index))).await;
+	    }
+	}
+	Command::ClearLatency => latency::clear(),
+    }
+    send(tx, &Message::Ack).await;
+}
+
+async fn send(tx: &mut BufferedUartTx<'_, UART0>, message: &Message) {
+    let mut frame = [0; MAX_FRAME];
+    if let Err(e) = tx.write_all(protocol::encode(message, &mut frame)).await {
+	warn!("UART: {}", e);
+    }
+}
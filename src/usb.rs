@@ -0,0 +1,264 @@
+//! Serial console (CDC-ACM) on the Pico's own USB port, so a unit can be
+//! looked at without a debug probe. See `selector::console` for the
+//! commands. With one of the `hid-*` features, there's a HID shifter on
+//! the same device.
+
+use core::fmt::Write;
+
+use defmt::{info, warn};
+
+use embassy_futures::join::join3;
+use embassy_futures::select::{select, Either};
+use embassy_rp::peripherals::USB;
+use embassy_rp::usb::Driver;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Instant, Timer};
+use embassy_usb::class::cdc_acm::{CdcAcmClass, State};
+use embassy_usb::driver::EndpointError;
+use embassy_usb::Builder;
+
+use selector::console::{
+    self, Command, DisplayDtc, DisplayEntry, DisplayLatency, DisplaySelection, Event, Key, LineBuffer, LogLevel,
+    Text,
+};
+use selector::tasks;
+use selector::{Action, LedStatus};
+
+use crate::board::{BOARD, NUM_POSITIONS};
+use crate::{events, latency, power};
+use crate::{boot_count, clear_dtcs, panic, Irqs, Pico, DTCS, FAULTS, GLITCHES, LEDS, SHARED};
+
+const MAX_PACKET: usize = 64;
+
+// Room for the longest reply, which is `help`.
+type Output = Text<512>;
+
+// Events for the console to print, if the log level says so. Sent with
+// `try_send` - nobody might be listening.
+pub static CONSOLE: Channel<ThreadModeRawMutex, Event, 16> = Channel::new();
+
+#[embassy_executor::task]
+pub async fn usb(usb: USB) {
+    let driver = Driver::new(usb, Irqs);
+
+    let mut config = embassy_usb::Config::new(0xc0de, 0xcafe);
+    config.manufacturer = Some("FransUrbo");
+    config.product = Some("Gear selector");
+    config.max_power = 100;
+    config.max_packet_size_0 = 64;
+
+    // Composite device (IAD), so Windows finds the serial port too.
+    config.device_class = 0xEF;
+    config.device_sub_class = 0x02;
+    config.device_protocol = 0x01;
+    config.composite_with_iads = true;
+
+    let mut config_descriptor = [0; 256];
+    let mut bos_descriptor = [0; 256];
+    let mut msos_descriptor = [0; 256];
+    let mut control_buf = [0; 64];
+    let mut state = State::new();
+    #[cfg(feature = "hid")]
+    let mut hid_state = embassy_usb::class::hid::State::new();
+
+    let mut builder = Builder::new(driver, config, &mut config_descriptor, &mut bos_descriptor,
+				   &mut msos_descriptor, &mut control_buf);
+    let mut class = CdcAcmClass::new(&mut builder, &mut state, MAX_PACKET as u16);
+
+    #[cfg(feature = "hid")]
+    let mut hid_writer = crate::hid::new(&mut builder, &mut hid_state);
+
+    let mut usb = builder.build();
+
+    let console = async {
+	let mut level = LogLevel::Info;
+	loop {
+	    class.wait_connection().await;
+	    info!("USB console connected");
+	    // Stay awake while someone's looking.
+	    power::console(true);
+	    if let Err(EndpointError::BufferOverflow) = session(&mut class, &mut level).await {
+		warn!("USB console: buffer overflow");
+	    }
+	    power::console(false);
+	    info!("USB console disconnected");
+	}
+    };
+
+    #[cfg(feature = "hid")]
+    let shifter = crate::hid::run(&mut hid_writer);
+    #[cfg(not(feature = "hid"))]
+    let shifter = async {};
+
+    join3(usb.run(), console, shifter).await;
+}
+
+// Runs until the terminal goes away.
+async fn session<'d>(class: &mut CdcAcmClass<'d, Driver<'d, USB>>, level: &mut LogLevel) -> Result<(), EndpointError> {
+    let mut line = LineBuffer::<64>::new();
+    let mut out = Output::new();
+    let mut buf = [0; MAX_PACKET];
+
+    _ = out.write_str("Gear selector, 'help' for commands\n> ");
+    loop {
+	send(class, &mut out).await?;
+
+	match select(class.read_packet(&mut buf), CONSOLE.receive()).await {
+	    Either::First(len) => {
+		// Anything that doesn't fit in `out` is dropped, so the
+		// `fmt::Result`s are ignored.
+		for byte in &buf[..len?] {
+		    match line.push(*byte) {
+			Key::Char(c) => _ = out.write_char(c as char),
+			Key::Erase => _ = out.write_str("\x08 \x08"),
+			Key::Line(l) => {
+			    _ = out.write_str("\n");
+			    match console::parse(l) {
+				Ok(Some(command)) => run(class, command, &mut out, level).await?,
+				Ok(None) => {}
+				Err(e) => _ = writeln!(out, "{}", e),
+			    }
+			    _ = out.write_str("> ");
+			}
+			Key::TooLong => _ = out.write_str("\nline too long\n> "),
+			Key::Ignored => {}
+		    }
+		}
+	    }
+	    Either::Second(event) => {
+		if event.level() <= *level {
+		    _ = write!(out, "\n{}\n> ", event);
+		}
+	    }
+	}
+    }
+}
+
+async fn run<'d>(
+    class:   &mut CdcAcmClass<'d, Driver<'d, USB>>,
+    command: Command,
+    out:     &mut Output,
+    level:   &mut LogLevel) -> Result<(), EndpointError>
+{
+    let result = match command {
+	Command::Help => out.write_str(console::HELP),
+	Command::Status => status(out),
+	Command::Gear(gear) => match tasks::apply(&Pico, Action::Select(gear)) {
+	    Ok(_) => writeln!(out, "ok"),
+	    Err(rejection) => writeln!(out, "rejected: {}", rejection.reason()),
+	},
+	Command::Led(n, on) if n < NUM_POSITIONS => {
+	    LEDS[n].send(if on { LedStatus::On } else { LedStatus::Off }).await;
+	    Ok(())
+	}
+	Command::Led(..) => writeln!(out, "no such LED, there's {}", NUM_POSITIONS),
+	Command::Log(new) => {
+	    *level = new.unwrap_or(*level);
+	    writeln!(out, "log level {}", level.name())
+	}
+	Command::Dtcs => {
+	    // One at a time, they don't all fit in `out`.
+	    let dtcs = DTCS.lock(|d| d.borrow().clone());
+	    for dtc in dtcs.iter() {
+		_ = writeln!(out, "{}", DisplayDtc(dtc));
+		send(class, out).await?;
+	    }
+	    writeln!(out, "{} trouble code{}", dtcs.len(), if dtcs.len() == 1 { "" } else { "s" })
+	}
+	Command::ClearDtcs => {
+	    clear_dtcs();
+	    writeln!(out, "cleared")
+	}
+	Command::Events(false) => {
+	    let events = events::ram();
+	    for entry in events.iter() {
+		_ = writeln!(out, "{}", DisplayEntry(entry));
+		send(class, out).await?;
+	    }
+	    writeln!(out, "{} event{}", events.len(), if events.len() == 1 { "" } else { "s" })
+	}
+	Command::Events(true) if events::flash_slots() == 0 => writeln!(out, "not kept in flash"),
+	Command::Events(true) => {
+	    let mut count = 0;
+	    for index in 0..events::flash_slots() {
+		if let Some(entry) = events::flash_entry(index) {
+		    _ = writeln!(out, "{}", DisplayEntry(&entry));
+		    send(class, out).await?;
+		    count += 1;
+		}
+	    }
+	    writeln!(out, "{} event{}", count, if count == 1 { "" } else { "s" })
+	}
+	Command::Latency => {
+	    for (index, position) in BOARD.positions.iter().enumerate() {
+		_ = writeln!(out, "{}", DisplayLatency(position.gear, latency::stats(index)));
+		send(class, out).await?;
+	    }
+	    _ = writeln!(out, "of which waiting for the gesture:");
+	    for (index, position) in BOARD.positions.iter().enumerate() {
+		_ = writeln!(out, "{}", DisplayLatency(position.gear, latency::gesture_stats(index)));
+		send(class, out).await?;
+	    }
+	    Ok(())
+	}
+	Command::ClearLatency => {
+	    latency::clear();
+	    writeln!(out, "cleared")
+	}
+	Command::Reset => {
+	    _ = out.write_str("resetting\n");
+	    send(class, out).await?;
+	    Timer::after_millis(100).await;
+	    cortex_m::peripheral::SCB::sys_reset();
+	}
+    };
+    if result.is_err() {
+	warn!("USB console: reply truncated");
+    }
+    Ok(())
+}
+
+fn status(out: &mut Output) -> core::fmt::Result {
+    let (selection, inputs, safe_state) = SHARED.lock(|s| {
+	let s = s.borrow();
+	(s.selector.selection(), s.inputs, s.safe_state)
+    });
+    let faults = FAULTS.lock(|f| f.borrow().total());
+
+    writeln!(out, "gear:       {}", DisplaySelection(&selection))?;
+    writeln!(out, "brake:      {}", if inputs.brake { "pressed" } else { "released" })?;
+    writeln!(out, "standstill: {}", if inputs.standstill { "yes" } else { "no" })?;
+    writeln!(out, "boot:       #{}, up {}s", boot_count(), Instant::now().as_secs())?;
+    writeln!(out, "faults:     {}", faults)?;
+    if safe_state {
+	writeln!(out, "safe state: yes, reset to get out of it")?;
+    }
+    if let Some(record) = panic::last() {
+	writeln!(out, "panicked:   {}:{}:{}", record.file(), record.line(), record.column())?;
+    }
+    write!(out, "LEDs:      ")?;
+    for (n, position) in BOARD.positions.iter().enumerate() {
+	write!(out, " {}={}", n, position.gear.name())?;
+    }
+    writeln!(out)?;
+    write!(out, "glitches:  ")?;
+    for (glitches, position) in GLITCHES.lock(|g| g.get()).iter().zip(BOARD.positions) {
+	write!(out, " {}={}", position.gear.name(), glitches)?;
+    }
+    writeln!(out)
+}
+
+async fn send<'d>(class: &mut CdcAcmClass<'d, Driver<'d, USB>>, out: &mut Output) -> Result<(), EndpointError> {
+    let bytes = out.as_bytes();
+    for chunk in bytes.chunks(MAX_PACKET) {
+	class.write_packet(chunk).await?;
+    }
+    // A full last packet needs an empty one after it, or the host keeps
+    // waiting for more.
+    if !bytes.is_empty() && bytes.len() % MAX_PACKET == 0 {
+	class.write_packet(&[]).await?;
+    }
+    out.clear();
+    Ok(())
+}
@@ -0,0 +1,102 @@
+//! The hardware watchdog. Only fed while every critical task (the button
+//! readers, the LED drivers and the NeoPixel loop) keeps checking in - if
+//! one of them gets stuck, we reset. See `selector::watchdog`.
+//!
+//! While we're asleep nothing checks in, so the watchdog is stopped until
+//! we wake up again.
+
+use core::cell::RefCell;
+
+use defmt::warn;
+
+use embassy_rp::watchdog::{ResetReason, Watchdog};
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant, Ticker};
+
+use selector::watchdog::{self, Heartbeats, Task};
+
+use crate::board::MAX_POSITIONS;
+use crate::power::{self, Sleeper};
+
+// Tasks check in at least this often, even if they have nothing to do.
+const CHECK_IN_MS: u64 = 500;
+
+// A task that haven't checked in for this long is stuck.
+const STUCK_MS: u64 = 2000;
+
+// The watchdog itself - if we don't feed it for this long, it resets us.
+const WATCHDOG_MS: u64 = 1000;
+const FEED_MS: u64 = 250;
+
+// Which scratch register the stuck task goes in.
+const SCRATCH: usize = 0;
+
+// A button reader and a LED driver per position, plus the NeoPixel loop.
+static HEARTBEATS: Mutex<ThreadModeRawMutex, RefCell<Heartbeats<{ 2 * MAX_POSITIONS + 1 }>>> =
+    Mutex::new(RefCell::new(Heartbeats::new(STUCK_MS)));
+
+/// Start watching `task`. Call before spawning it.
+pub fn expect(task: Task) {
+    if !HEARTBEATS.lock(|h| h.borrow_mut().expect(task, Instant::now().as_millis())) {
+	warn!("Watchdog: no room for task {}", task.to_u8());
+    }
+}
+
+/// `task` is alive. Returns when to check in next at the latest, in ms -
+/// never (`u64::MAX`), while we're asleep.
+pub fn check_in(task: Task) -> u64 {
+    let now = Instant::now().as_millis();
+    HEARTBEATS.lock(|h| h.borrow_mut().check_in(task, now));
+    if power::asleep() {
+	return u64::MAX;
+    }
+    now + CHECK_IN_MS
+}
+
+/// Were we reset by the watchdog? If so, which task was stuck (if we know).
+/// Clears the scratch register, so a later reset isn't blamed on it too.
+pub fn reset_cause(watchdog: &mut Watchdog) -> Option<Option<Task>> {
+    let task = watchdog::from_scratch(watchdog.get_scratch(SCRATCH));
+    watchdog.set_scratch(SCRATCH, 0);
+
+    match watchdog.reset_reason() {
+	Some(ResetReason::TimedOut) => Some(task),
+	_ => None,
+    }
+}
+
+#[embassy_executor::task]
+pub async fn feed(mut watchdog: Watchdog) {
+    // Don't reset while sitting at a breakpoint.
+    watchdog.pause_on_debug(true);
+    watchdog.start(Duration::from_millis(WATCHDOG_MS));
+
+    let mut ticker = Ticker::every(Duration::from_millis(FEED_MS));
+    loop {
+	ticker.next().await;
+
+	if power::asleep() {
+	    watchdog.stop();
+	    while power::asleep() {
+		power::woken(Sleeper::Watchdog).await;
+	    }
+
+	    // Give everyone a chance to notice we're awake before counting.
+	    HEARTBEATS.lock(|h| h.borrow_mut().restart(Instant::now().as_millis()));
+	    watchdog.start(Duration::from_millis(WATCHDOG_MS));
+	    ticker.reset();
+	    continue;
+	}
+
+	match HEARTBEATS.lock(|h| h.borrow().stale(Instant::now().as_millis())) {
+	    None => watchdog.feed(),
+	    Some(task) => {
+		// Stop feeding, and let it bite.
+		warn!("Task {} {} is stuck, resetting", task.name(), task.index());
+		watchdog.set_scratch(SCRATCH, watchdog::to_scratch(task));
+		return;
+	    }
+	}
+    }
+}